@@ -14,10 +14,22 @@
 
 use criterion::{criterion_group, criterion_main, Criterion};
 use isosurface::{
-    distance::Signed, extractor::IndexedVertices, implicit::Torus, sampler::Sampler,
+    distance::Signed, extractor::IndexedVertices, feature::MinimiseQEF, implicit::Torus,
+    math::Vec3, sampler::Sampler, source::ScalarSource, DualContouring, ExtendedMarchingCubes,
     LinearHashedMarchingCubes, MarchingCubes,
 };
 
+// A triply periodic surface, which crosses far more cells than the torus, and
+// so stresses vertex deduplication rather than sampling
+struct Gyroid;
+
+impl ScalarSource for Gyroid {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        let p = p * 24.0;
+        Signed((p.x.sin() * p.y.cos() + p.y.sin() * p.z.cos() + p.z.sin() * p.x.cos()) / 24.0)
+    }
+}
+
 fn marching_cubes() {
     let torus = Torus::new(0.25, 0.1);
     let sampler = Sampler::new(&torus);
@@ -26,8 +38,72 @@ fn marching_cubes() {
     let mut indices = vec![];
     let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
 
-    let mut marching_cubes = MarchingCubes::<Signed>::new(128);
-    marching_cubes.extract(&sampler, &mut extractor);
+    let mut marching_cubes = MarchingCubes::<Signed>::new(128).unwrap();
+    marching_cubes.extract(&sampler, &mut extractor).unwrap();
+}
+
+fn bounded_marching_cubes() {
+    let torus = Torus::new(0.25, 0.1);
+    let sampler = Sampler::new(&torus);
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
+
+    let mut marching_cubes = MarchingCubes::<Signed>::new(128).unwrap();
+    marching_cubes
+        .extract_bounded(&sampler, &mut extractor)
+        .unwrap();
+}
+
+fn dense_marching_cubes() {
+    let sampler = Sampler::new(&Gyroid);
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
+
+    let mut marching_cubes = MarchingCubes::<Signed>::new(128).unwrap();
+    marching_cubes.extract(&sampler, &mut extractor).unwrap();
+}
+
+fn streaming_marching_cubes() {
+    let sampler = Sampler::new(&Gyroid);
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
+
+    let mut marching_cubes = MarchingCubes::<Signed>::new(128).unwrap();
+    marching_cubes
+        .extract_streaming(&sampler, &mut extractor)
+        .unwrap();
+}
+
+fn extended_marching_cubes() {
+    let torus = Torus::new(0.25, 0.1);
+    let sampler = Sampler::new(&torus);
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
+
+    let mut extended_marching_cubes = ExtendedMarchingCubes::new(128).unwrap();
+    extended_marching_cubes
+        .extract(&sampler, &mut extractor)
+        .unwrap();
+}
+
+fn dual_contouring() {
+    let torus = Torus::new(0.25, 0.1);
+    let sampler = Sampler::new(&torus);
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
+
+    let mut dual_contouring = DualContouring::new(128, MinimiseQEF {}).unwrap();
+    dual_contouring.extract(&sampler, &mut extractor).unwrap();
 }
 
 fn linear_hashed_marching_cubes() {
@@ -38,14 +114,23 @@ fn linear_hashed_marching_cubes() {
     let mut indices = vec![];
     let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
 
-    let mut marching_cubes = LinearHashedMarchingCubes::new(7);
-    marching_cubes.extract(&sampler, &mut extractor);
+    let mut marching_cubes = LinearHashedMarchingCubes::new(7).unwrap();
+    marching_cubes.extract(&sampler, &mut extractor).unwrap();
 }
 
 fn marching_cubes_benchmark(c: &mut Criterion) {
-    c.bench_function("marching cubes", |b| b.iter(|| marching_cubes()));
+    c.bench_function("marching cubes", |b| b.iter(marching_cubes));
+    c.bench_function("bounded marching cubes", |b| b.iter(bounded_marching_cubes));
+    c.bench_function("dense marching cubes", |b| b.iter(dense_marching_cubes));
+    c.bench_function("streaming marching cubes", |b| {
+        b.iter(streaming_marching_cubes)
+    });
+    c.bench_function("extended marching cubes", |b| {
+        b.iter(extended_marching_cubes)
+    });
+    c.bench_function("dual contouring", |b| b.iter(dual_contouring));
     c.bench_function("linear hashed marching cubes", |b| {
-        b.iter(|| linear_hashed_marching_cubes())
+        b.iter(linear_hashed_marching_cubes)
     });
 }
 
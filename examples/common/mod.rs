@@ -13,6 +13,8 @@
 // limitations under the License.
 
 pub mod sources;
+// Not every example renders text
+#[allow(dead_code)]
 pub mod text;
 
 use std::mem;
@@ -23,7 +25,7 @@ use std::slice;
 /// type system. I hope that Rust provides safe functionality to handle this in
 /// the future. In the meantime, reproduce this workaround at your own risk.
 pub fn reinterpret_cast_slice<S, T>(input: &[S]) -> &[T] {
-    let length_in_bytes = input.len() * mem::size_of::<S>();
+    let length_in_bytes = std::mem::size_of_val(input);
     let desired_length = length_in_bytes / mem::size_of::<T>();
     unsafe { slice::from_raw_parts(input.as_ptr() as *const T, desired_length) }
 }
@@ -83,9 +83,11 @@ fn main() {
 
     let mut vertices = vec![];
     let mut extractor = OnlyInterleavedNormals::new(&mut vertices, &sampler);
-    let mut marcher = PointCloud::<Signed>::new(subdivisions);
+    let mut marcher = PointCloud::<Signed>::new(subdivisions).expect("invalid grid size");
 
-    marcher.extract(&sampler, &mut extractor);
+    marcher
+        .extract(&sampler, &mut extractor)
+        .expect("failed to extract point cloud");
 
     let vertex_buffer: glium::VertexBuffer<VertexWithNormal> = {
         glium::VertexBuffer::new(&display, reinterpret_cast_slice(&vertices))
@@ -302,8 +304,8 @@ fn main() {
     .unwrap();
 
     events_loop.run(move |event, _, control_flow| {
-        match event {
-            glutin::event::Event::WindowEvent { event, .. } => match event {
+        if let glutin::event::Event::WindowEvent { event, .. } = event {
+            match event {
                 WindowEvent::CloseRequested => {
                     *control_flow = glutin::event_loop::ControlFlow::Exit
                 }
@@ -311,19 +313,13 @@ fn main() {
                     input:
                         KeyboardInput {
                             state: ElementState::Pressed,
-                            virtual_keycode,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
                             ..
                         },
                     ..
-                } => match virtual_keycode {
-                    Some(VirtualKeyCode::Escape) => {
-                        *control_flow = glutin::event_loop::ControlFlow::Exit
-                    }
-                    _ => (),
-                },
+                } => *control_flow = glutin::event_loop::ControlFlow::Exit,
                 _ => (),
-            },
-            _ => (),
+            }
         }
 
         let mut framebuffer1 = glium::framebuffer::MultiOutputFrameBuffer::with_depth_buffer(
@@ -358,7 +354,7 @@ fn main() {
             };
 
             framebuffer1
-                .draw(&vertex_buffer, &index_buffer, &program, &uniforms, &params)
+                .draw(&vertex_buffer, index_buffer, &program, &uniforms, &params)
                 .expect("failed to draw to surface");
         }
 
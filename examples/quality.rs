@@ -0,0 +1,70 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use isosurface::{
+    distance::Signed, evaluation::Evaluator, extractor::IndexedVertices, feature::MinimiseQEF,
+    implicit::Torus, math::Vec3, sampler::Sampler, DualContouring, ExtendedMarchingCubes,
+    MarchingCubes,
+};
+
+// Prints the accuracy of each algorithm on the same torus the benchmarks
+// extract, so that changes in quality can be tracked alongside changes in speed
+fn main() {
+    let torus = Torus::new(0.25, 0.1);
+    let sampler = Sampler::new(&torus);
+    let evaluator = Evaluator::new();
+
+    let report = |name: &str, vertices: Vec<f32>, indices: Vec<u32>| {
+        let positions = vertices
+            .chunks(3)
+            .map(|v| Vec3::new(v[0], v[1], v[2]))
+            .collect::<Vec<_>>();
+        let distance = evaluator.evaluate_distance(&torus, &positions, &indices);
+        let normals = evaluator.evaluate_normals(&torus, &positions, &indices);
+        println!(
+            "{}: distance rms {:.2e}, max {:.2e}; normal rms {:.2}º, max {:.2}º",
+            name, distance.rms, distance.max, normals.rms, normals.max
+        );
+    };
+
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    MarchingCubes::<Signed>::new(64)
+        .unwrap()
+        .extract(
+            &sampler,
+            &mut IndexedVertices::new(&mut vertices, &mut indices),
+        )
+        .unwrap();
+    report("marching cubes", vertices, indices);
+
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    ExtendedMarchingCubes::new(64)
+        .unwrap()
+        .extract(
+            &sampler,
+            &mut IndexedVertices::new(&mut vertices, &mut indices),
+        )
+        .unwrap();
+    report("extended marching cubes", vertices, indices);
+
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    DualContouring::new(64, MinimiseQEF {})
+        .unwrap()
+        .extract(
+            &sampler,
+            &mut IndexedVertices::new(&mut vertices, &mut indices),
+        )
+        .unwrap();
+    report("dual contouring", vertices, indices);
+}
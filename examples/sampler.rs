@@ -55,7 +55,7 @@ struct Vertex {
 
 implement_vertex!(Vertex, position, normal);
 
-const HELP_TEXT: &'static str =
+const HELP_TEXT: &str =
     "Press [A] to change Algorithm, [S] Shape, [C] Complexity, [N] Normals, or [W] Wireframe";
 
 struct GenerateResult(glium::VertexBuffer<Vertex>, glium::IndexBuffer<u32>, String);
@@ -104,23 +104,35 @@ where
 
     let algorithm_name = match algorithm % 4 {
         0 => {
-            let mut marching_cubes = MarchingCubes::<Signed>::new(grid_size);
-            marching_cubes.extract(&sampler, &mut extractor);
+            let mut marching_cubes =
+                MarchingCubes::<Signed>::new(grid_size).expect("invalid grid size");
+            marching_cubes
+                .extract(&sampler, &mut extractor)
+                .expect("failed to extract mesh");
             "Marching Cubes"
         }
         1 => {
-            let mut linear_hashed_marching_cubes = LinearHashedMarchingCubes::new(max_level);
-            linear_hashed_marching_cubes.extract(&sampler, &mut extractor);
+            let mut linear_hashed_marching_cubes =
+                LinearHashedMarchingCubes::new(max_level).expect("invalid octree depth");
+            linear_hashed_marching_cubes
+                .extract(&sampler, &mut extractor)
+                .expect("failed to extract mesh");
             "Linear Hashed Marching Cubes"
         }
         2 => {
-            let mut extended_marching_cubes = ExtendedMarchingCubes::new(grid_size);
-            extended_marching_cubes.extract(&sampler, &mut extractor);
+            let mut extended_marching_cubes =
+                ExtendedMarchingCubes::new(grid_size).expect("invalid grid size");
+            extended_marching_cubes
+                .extract(&sampler, &mut extractor)
+                .expect("failed to extract mesh");
             "Extended Marching Cubes"
         }
         _ => {
-            let mut dual_contouring = DualContouring::new(grid_size, ParticleBasedMinimisation {});
-            dual_contouring.extract(&sampler, &mut extractor);
+            let mut dual_contouring = DualContouring::new(grid_size, ParticleBasedMinimisation {})
+                .expect("invalid grid size");
+            dual_contouring
+                .extract(&sampler, &mut extractor)
+                .expect("failed to extract mesh");
             "Dual Contouring"
         }
     };
@@ -234,8 +246,8 @@ fn main() {
     let label_transform = layout_text(65.0, aspect, 1.0, 65.0 / aspect - 2.0);
 
     events_loop.run(move |event, _, control_flow| {
-        match event {
-            glutin::event::Event::WindowEvent { event, .. } => match event {
+        if let glutin::event::Event::WindowEvent { event, .. } = event {
+            match event {
                 WindowEvent::CloseRequested => {
                     *control_flow = glutin::event_loop::ControlFlow::Exit
                 }
@@ -272,8 +284,7 @@ fn main() {
                     _ => (),
                 },
                 _ => (),
-            },
-            _ => (),
+            }
         }
 
         let mut surface = display.draw();
@@ -0,0 +1,538 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{buffer::IndexType, math::Vec3};
+use std::collections::{HashMap, HashSet};
+
+/// The result of analysing a mesh.
+///
+/// Edges are given as pairs of vertex indices, with the smaller index first.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MeshReport {
+    /// The number of vertices referenced by at least one face.
+    pub vertex_count: usize,
+    pub face_count: usize,
+    /// Edges shared by three or more faces.
+    pub non_manifold_edges: Vec<(usize, usize)>,
+    /// Vertices where two or more separate fans of faces meet at a point.
+    pub non_manifold_vertices: Vec<usize>,
+    /// Loops of vertices around each hole in the mesh, following the winding
+    /// of the faces which border the hole.
+    pub boundary_loops: Vec<Vec<usize>>,
+    /// Edges shared by two faces which run along the edge in the same
+    /// direction, i.e. with opposite winding.
+    pub inconsistent_edges: Vec<(usize, usize)>,
+    /// Faces which repeat a vertex.
+    pub degenerate_faces: Vec<usize>,
+    /// Faces with three distinct vertices, but (close to) zero area.
+    pub zero_area_faces: Vec<usize>,
+    /// Pairs of distinct vertices within the analyser's epsilon of each other.
+    pub duplicate_vertices: Vec<(usize, usize)>,
+    /// The number of separate pieces of the mesh, connected through shared
+    /// vertices.
+    pub components: usize,
+    /// The total number of handles across all components, if the mesh is
+    /// watertight and consistently oriented (otherwise genus is undefined).
+    pub genus: Option<usize>,
+}
+
+impl MeshReport {
+    /// Test if every edge is shared by at most two faces, and the faces
+    /// around every vertex form a single fan.
+    pub fn is_manifold(&self) -> bool {
+        self.non_manifold_edges.is_empty() && self.non_manifold_vertices.is_empty()
+    }
+
+    /// Test if the mesh is manifold and has no holes, so that it encloses a
+    /// volume.
+    pub fn is_watertight(&self) -> bool {
+        self.is_manifold() && self.boundary_loops.is_empty()
+    }
+
+    /// Test if the mesh passed every check. Meshes with holes may still be
+    /// valid, as long as they are otherwise well-formed.
+    pub fn is_valid(&self) -> bool {
+        self.is_manifold()
+            && self.inconsistent_edges.is_empty()
+            && self.degenerate_faces.is_empty()
+            && self.zero_area_faces.is_empty()
+            && self.duplicate_vertices.is_empty()
+    }
+}
+
+/// Finds, and repairs, topological problems in indexed triangle meshes.
+///
+/// Extraction algorithms may produce non-manifold edges where several sheets
+/// of the surface pass through a single cell, and slivers of zero area where
+/// the surface passes through grid corners. Meshes assembled from several
+/// extractions (i.e. one per chunk of a larger volume) also contain duplicate
+/// vertices along the seams. Meshes are given as a list of positions, and a
+/// list of indices with three per face.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshAnalyser {
+    /// The distance within which two vertices are considered duplicates.
+    /// Faces with an area less than the square of this are considered to have
+    /// zero area.
+    pub epsilon: f32,
+}
+
+impl MeshAnalyser {
+    pub fn new(epsilon: f32) -> Self {
+        Self { epsilon }
+    }
+
+    /// Analyse a mesh, and report any problems.
+    pub fn analyse<I: IndexType>(&self, positions: &[Vec3], indices: &[I]) -> MeshReport {
+        let faces = to_faces(indices);
+        let mut report = MeshReport {
+            face_count: faces.len(),
+            ..MeshReport::default()
+        };
+
+        for (f, face) in faces.iter().enumerate() {
+            if is_degenerate(face) {
+                report.degenerate_faces.push(f);
+            } else if self.is_zero_area(positions, face) {
+                report.zero_area_faces.push(f);
+            }
+        }
+
+        let edges = edge_faces(&faces);
+        let mut boundary = HashMap::<usize, Vec<usize>>::new();
+        for (&edge, uses) in &edges {
+            match uses[..] {
+                [(_, forward)] => {
+                    let (a, b) = if forward { edge } else { (edge.1, edge.0) };
+                    boundary.entry(a).or_default().push(b);
+                }
+                [(_, a), (_, b)] if a == b => report.inconsistent_edges.push(edge),
+                [_, _] => {}
+                _ => report.non_manifold_edges.push(edge),
+            }
+        }
+        report.non_manifold_edges.sort_unstable();
+        report.inconsistent_edges.sort_unstable();
+        report.boundary_loops = boundary_loops(boundary);
+
+        let mut vertex_faces = HashMap::<usize, Vec<usize>>::new();
+        for (f, face) in faces.iter().enumerate() {
+            for &v in face.iter().collect::<HashSet<_>>() {
+                vertex_faces.entry(v).or_default().push(f);
+            }
+        }
+        report.vertex_count = vertex_faces.len();
+        report.non_manifold_vertices = vertex_faces
+            .iter()
+            .filter(|(&v, fan)| fan_count(v, fan, &faces) > 1)
+            .map(|(&v, _)| v)
+            .collect();
+        report.non_manifold_vertices.sort_unstable();
+
+        report.duplicate_vertices = self.find_duplicates(positions);
+
+        let mut components = DisjointSet::new(positions.len());
+        for face in &faces {
+            components.union(face[0], face[1]);
+            components.union(face[0], face[2]);
+        }
+        let roots = vertex_faces
+            .keys()
+            .map(|&v| components.find(v))
+            .collect::<HashSet<_>>();
+        report.components = roots.len();
+
+        // Each closed, orientable component contributes 2 - 2g to the Euler
+        // characteristic
+        if report.is_watertight() && report.inconsistent_edges.is_empty() {
+            let euler = report.vertex_count as isize - edges.len() as isize + faces.len() as isize;
+            let genus = (2 * report.components as isize - euler) / 2;
+            report.genus = Some(genus.max(0) as usize);
+        }
+
+        report
+    }
+
+    /// Merge vertices within epsilon of each other into the first of them, and
+    /// remove the rest. Returns the number of vertices removed.
+    ///
+    /// Faces which become degenerate are left in place, to be removed by
+    /// [remove_degenerate_faces](MeshAnalyser::remove_degenerate_faces).
+    pub fn stitch_vertices<I: IndexType>(
+        &self,
+        positions: &mut Vec<Vec3>,
+        indices: &mut [I],
+    ) -> usize {
+        let mut merged = DisjointSet::new(positions.len());
+        for (a, b) in self.find_duplicates(positions) {
+            merged.union(a, b);
+        }
+
+        // Keep the lowest-numbered vertex of each group, and renumber the
+        // survivors in order
+        let mut first = HashMap::new();
+        let mut remap = vec![0; positions.len()];
+        let mut kept = 0;
+        for v in 0..positions.len() {
+            let root = merged.find(v);
+            remap[v] = *first.entry(root).or_insert_with(|| {
+                positions[kept] = positions[v];
+                kept += 1;
+                kept - 1
+            });
+        }
+
+        let removed = positions.len() - kept;
+        positions.truncate(kept);
+        for index in indices.iter_mut() {
+            *index = I::from_index(remap[index.to_index()]);
+        }
+        removed
+    }
+
+    /// Remove faces which repeat a vertex, or have zero area. Returns the
+    /// number of faces removed.
+    pub fn remove_degenerate_faces<I: IndexType>(
+        &self,
+        positions: &[Vec3],
+        indices: &mut Vec<I>,
+    ) -> usize {
+        let count = indices.len() / 3;
+        let faces = to_faces(indices);
+        let kept = faces
+            .iter()
+            .zip(indices.chunks_exact(3))
+            .filter(|(face, _)| !is_degenerate(face) && !self.is_zero_area(positions, face))
+            .flat_map(|(_, indices)| indices.iter().copied())
+            .collect::<Vec<_>>();
+        *indices = kept;
+        count - indices.len() / 3
+    }
+
+    /// Flip faces so that every pair of faces sharing an edge winds the same
+    /// way. Within each component, the winding of the first face is kept.
+    /// Returns the number of faces flipped.
+    ///
+    /// Faces are only propagated across manifold edges, and components which
+    /// aren't orientable (i.e. a Möbius strip) can't be fully unified.
+    pub fn unify_orientation<I: IndexType>(&self, indices: &mut [I]) -> usize {
+        let faces = to_faces(indices);
+        let edges = edge_faces(&faces);
+        let mut flipped = vec![false; faces.len()];
+        let mut visited = vec![false; faces.len()];
+        let mut count = 0;
+
+        for seed in 0..faces.len() {
+            if visited[seed] {
+                continue;
+            }
+            visited[seed] = true;
+            let mut stack = vec![seed];
+
+            while let Some(f) = stack.pop() {
+                for i in 0..3 {
+                    let (a, b) = (faces[f][i], faces[f][(i + 1) % 3]);
+                    let edge = (a.min(b), a.max(b));
+                    let uses = &edges[&edge];
+                    if uses.len() != 2 {
+                        continue;
+                    }
+                    // Whether this face, as it will be output, runs forwards
+                    // along the edge
+                    let forward = (a < b) != flipped[f];
+                    for &(g, g_forward) in uses {
+                        if g == f || visited[g] {
+                            continue;
+                        }
+                        visited[g] = true;
+                        if g_forward == forward {
+                            flipped[g] = true;
+                            count += 1;
+                        }
+                        stack.push(g);
+                    }
+                }
+            }
+        }
+
+        for (face, &flip) in indices.chunks_exact_mut(3).zip(&flipped) {
+            if flip {
+                face.swap(1, 2);
+            }
+        }
+        count
+    }
+
+    fn is_zero_area(&self, positions: &[Vec3], face: &[usize; 3]) -> bool {
+        let [a, b, c] = face.map(|v| positions[v]);
+        (b - a).cross(c - a).len() * 0.5 < self.epsilon * self.epsilon
+    }
+
+    // Find pairs of vertices within epsilon of each other, by hashing them
+    // into a grid of cells the size of epsilon
+    fn find_duplicates(&self, positions: &[Vec3]) -> Vec<(usize, usize)> {
+        let cell_size = self.epsilon.max(f32::MIN_POSITIVE);
+        let cell = |p: Vec3| {
+            let c = p / cell_size;
+            [c.x.floor() as i64, c.y.floor() as i64, c.z.floor() as i64]
+        };
+
+        let mut grid = HashMap::<[i64; 3], Vec<usize>>::new();
+        for (v, &p) in positions.iter().enumerate() {
+            grid.entry(cell(p)).or_default().push(v);
+        }
+
+        let mut duplicates = vec![];
+        for (v, &p) in positions.iter().enumerate() {
+            let c = cell(p);
+            for offset in 0..27 {
+                let key = [
+                    c[0] + offset % 3 - 1,
+                    c[1] + offset / 3 % 3 - 1,
+                    c[2] + offset / 9 - 1,
+                ];
+                for &u in grid.get(&key).into_iter().flatten() {
+                    if u > v && (positions[u] - p).len() <= self.epsilon {
+                        duplicates.push((v, u));
+                    }
+                }
+            }
+        }
+        duplicates.sort_unstable();
+        duplicates
+    }
+}
+
+fn to_faces<I: IndexType>(indices: &[I]) -> Vec<[usize; 3]> {
+    indices
+        .chunks_exact(3)
+        .map(|f| [f[0].to_index(), f[1].to_index(), f[2].to_index()])
+        .collect()
+}
+
+fn is_degenerate(face: &[usize; 3]) -> bool {
+    face[0] == face[1] || face[1] == face[2] || face[2] == face[0]
+}
+
+// The faces which use each undirected edge, and whether they run from the
+// lower to the higher vertex along it
+fn edge_faces(faces: &[[usize; 3]]) -> HashMap<(usize, usize), Vec<(usize, bool)>> {
+    let mut edges = HashMap::<_, Vec<_>>::new();
+    for (f, face) in faces.iter().enumerate() {
+        if is_degenerate(face) {
+            continue;
+        }
+        for i in 0..3 {
+            let (a, b) = (face[i], face[(i + 1) % 3]);
+            edges
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((f, a < b));
+        }
+    }
+    edges
+}
+
+// Chain boundary edges, given as a map from the start of each to their ends,
+// into loops. Where several holes meet at a vertex, each hole gets a loop of
+// its own.
+fn boundary_loops(mut boundary: HashMap<usize, Vec<usize>>) -> Vec<Vec<usize>> {
+    let mut starts = boundary.keys().copied().collect::<Vec<_>>();
+    starts.sort_unstable();
+    // Follow the lowest successor first, so that the loops don't depend on
+    // the order edges were found in
+    for successors in boundary.values_mut() {
+        successors.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    let mut loops = vec![];
+    for start in starts {
+        // The path walked so far, and the position of each vertex on it
+        let mut path = vec![];
+        let mut positions = HashMap::new();
+        let mut v = start;
+        while let Some(next) = boundary.get_mut(&v).and_then(|s| s.pop()) {
+            positions.insert(v, path.len());
+            path.push(v);
+            v = next;
+            // Returning to a vertex on the path closes a loop
+            if let Some(&i) = positions.get(&v) {
+                let boundary_loop = path.split_off(i);
+                for u in &boundary_loop {
+                    positions.remove(u);
+                }
+                loops.push(boundary_loop);
+            }
+        }
+        if !path.is_empty() {
+            loops.push(path);
+        }
+    }
+    loops
+}
+
+// The number of separate fans of faces around a vertex, where faces in the
+// same fan are connected by edges incident to the vertex
+fn fan_count(v: usize, fan: &[usize], faces: &[[usize; 3]]) -> usize {
+    let mut fans = DisjointSet::new(fan.len());
+    let mut spokes = HashMap::new();
+    for (i, &f) in fan.iter().enumerate() {
+        for &u in &faces[f] {
+            if u != v {
+                if let Some(&j) = spokes.get(&u) {
+                    fans.union(i, j);
+                } else {
+                    spokes.insert(u, i);
+                }
+            }
+        }
+    }
+    (0..fan.len()).filter(|&i| fans.find(i) == i).count()
+}
+
+struct DisjointSet {
+    parents: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        // Keep the lowest index as the root
+        if a < b {
+            self.parents[b] = a;
+        } else {
+            self.parents[a] = b;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{extract_mesh, Centered},
+        implicit::Torus,
+    };
+
+    // A torus centered in the unit cube
+    fn extract() -> (Vec<Vec3>, Vec<u32>) {
+        extract_mesh(&Centered(Torus::new(0.25, 0.1)), 24)
+    }
+
+    #[test]
+    fn test_analysis() {
+        let analyser = MeshAnalyser::new(1e-6);
+        let (positions, indices) = extract();
+        let report = analyser.analyse(&positions, &indices);
+        assert!(report.is_watertight());
+        assert!(report.inconsistent_edges.is_empty());
+        assert_eq!(report.components, 1);
+        assert_eq!(report.genus, Some(1));
+
+        // Cut a hole, flip a face, and glue a degenerate fin onto an edge
+        let mut broken = indices.clone();
+        broken.drain(0..3);
+        broken.swap(10, 11);
+        let [a, b] = [broken[18], broken[19]];
+        broken.extend_from_slice(&[a, b, b, a, b, a]);
+        let report = analyser.analyse(&positions, &broken);
+        assert_eq!(report.boundary_loops.len(), 1);
+        assert_eq!(report.boundary_loops[0].len(), 3);
+        assert_eq!(report.inconsistent_edges.len(), 3);
+        assert_eq!(report.degenerate_faces.len(), 2);
+        assert!(report.is_manifold());
+        assert_eq!(report.genus, None);
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn test_holes_sharing_a_vertex() {
+        let analyser = MeshAnalyser::new(1e-6);
+        let (positions, indices) = extract();
+        let faces = to_faces(&indices);
+
+        // Remove two faces which share a vertex, but no edges
+        let (a, b) = (0..faces.len())
+            .flat_map(|a| (a + 1..faces.len()).map(move |b| (a, b)))
+            .find(|&(a, b)| {
+                let shared = faces[a].iter().filter(|v| faces[b].contains(v));
+                shared.count() == 1
+            })
+            .unwrap();
+        let holed = indices
+            .chunks(3)
+            .enumerate()
+            .filter(|&(f, _)| f != a && f != b)
+            .flat_map(|(_, face)| face.iter().copied())
+            .collect::<Vec<_>>();
+
+        let report = analyser.analyse(&positions, &holed);
+        let sorted = |vertices: &[usize]| {
+            let mut vertices = vertices.to_vec();
+            vertices.sort_unstable();
+            vertices
+        };
+        let mut found = report
+            .boundary_loops
+            .iter()
+            .map(|l| sorted(l))
+            .collect::<Vec<_>>();
+        let mut expected = vec![sorted(&faces[a]), sorted(&faces[b])];
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_repair() {
+        let analyser = MeshAnalyser::new(1e-6);
+        let (positions, indices) = extract();
+
+        // Give every face its own vertices, as if the mesh had been unindexed
+        let mut soup = indices
+            .iter()
+            .map(|&i| positions[i as usize])
+            .collect::<Vec<_>>();
+        let mut soup_indices = (0..soup.len() as u32).collect::<Vec<_>>();
+        for face in soup_indices.chunks_exact_mut(3).step_by(2) {
+            face.swap(1, 2);
+        }
+        let report = analyser.analyse(&soup, &soup_indices);
+        assert_eq!(report.boundary_loops.len(), indices.len() / 3);
+        assert!(!report.duplicate_vertices.is_empty());
+
+        let removed = analyser.stitch_vertices(&mut soup, &mut soup_indices);
+        assert_eq!(soup.len(), positions.len());
+        assert_eq!(removed, indices.len() - positions.len());
+        analyser.remove_degenerate_faces(&soup, &mut soup_indices);
+        assert!(analyser.unify_orientation(&mut soup_indices) > 0);
+
+        let report = analyser.analyse(&soup, &soup_indices);
+        assert!(report.is_valid());
+        assert!(report.is_watertight());
+        assert_eq!(report.genus, Some(1));
+    }
+}
@@ -0,0 +1,462 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    extractor::{Vertex, VertexExtractor},
+    math::Vec3,
+};
+use std::collections::HashMap;
+
+/// Integer types which can be used to index vertex buffers.
+pub trait IndexType: Copy {
+    /// The largest index representable by this type.
+    const MAX: usize;
+
+    /// Convert an index which is known to be no larger than [MAX](IndexType::MAX).
+    fn from_index(index: usize) -> Self;
+
+    fn to_index(self) -> usize;
+}
+
+impl IndexType for u16 {
+    const MAX: usize = u16::MAX as usize;
+
+    fn from_index(index: usize) -> Self {
+        debug_assert!(index <= <Self as IndexType>::MAX);
+        index as u16
+    }
+
+    fn to_index(self) -> usize {
+        self as usize
+    }
+}
+
+impl IndexType for u32 {
+    const MAX: usize = u32::MAX as usize;
+
+    fn from_index(index: usize) -> Self {
+        debug_assert!(index <= <Self as IndexType>::MAX);
+        index as u32
+    }
+
+    fn to_index(self) -> usize {
+        self as usize
+    }
+}
+
+impl IndexType for usize {
+    const MAX: usize = usize::MAX;
+
+    fn from_index(index: usize) -> Self {
+        index
+    }
+
+    fn to_index(self) -> usize {
+        self
+    }
+}
+
+/// The representation of positions and normals within a vertex buffer.
+pub trait PositionFormat {
+    /// The type of each component of a position or normal.
+    type Component: Copy;
+
+    fn encode_position(&self, position: Vec3) -> [Self::Component; 3];
+    fn encode_normal(&self, normal: Vec3) -> [Self::Component; 3];
+}
+
+/// Store positions and normals as single-precision floats.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Float32;
+
+impl PositionFormat for Float32 {
+    type Component = f32;
+
+    fn encode_position(&self, p: Vec3) -> [f32; 3] {
+        [p.x, p.y, p.z]
+    }
+
+    fn encode_normal(&self, n: Vec3) -> [f32; 3] {
+        [n.x, n.y, n.z]
+    }
+}
+
+/// Store positions and normals as double-precision floats.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Float64;
+
+impl PositionFormat for Float64 {
+    type Component = f64;
+
+    fn encode_position(&self, p: Vec3) -> [f64; 3] {
+        [p.x as f64, p.y as f64, p.z as f64]
+    }
+
+    fn encode_normal(&self, n: Vec3) -> [f64; 3] {
+        [n.x as f64, n.y as f64, n.z as f64]
+    }
+}
+
+/// Store positions as 16-bit integers, quantised across a bounding box, and
+/// normals as 16-bit signed normalised integers.
+///
+/// The bounding box maps to the range `-32767..=32767` on each axis, which
+/// matches the `SNORM16` vertex formats of most graphics APIs. Positions
+/// outside of the box are clamped, and on any axis along which the box is flat
+/// (e.g. for a mesh lying in a plane), positions are encoded as zero.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quantised16 {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Quantised16 {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Recover an (approximate) position from its quantised form.
+    pub fn decode_position(&self, p: [i16; 3]) -> Vec3 {
+        let t = Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) / 32767.0;
+        self.min + (self.max - self.min) * ((t + Vec3::one()) * 0.5)
+    }
+}
+
+fn snorm16(v: Vec3) -> [i16; 3] {
+    let v = v.max(-Vec3::one()).min(Vec3::one()) * 32767.0;
+    [v.x.round() as i16, v.y.round() as i16, v.z.round() as i16]
+}
+
+impl PositionFormat for Quantised16 {
+    type Component = i16;
+
+    fn encode_position(&self, p: Vec3) -> [i16; 3] {
+        let extent = self.max - self.min;
+        let axis = |p: f32, min: f32, extent: f32| {
+            if extent > 0.0 {
+                (p - min) / extent * 2.0 - 1.0
+            } else {
+                0.0
+            }
+        };
+        snorm16(Vec3::new(
+            axis(p.x, self.min.x, extent.x),
+            axis(p.y, self.min.y, extent.y),
+            axis(p.z, self.min.z, extent.z),
+        ))
+    }
+
+    fn encode_normal(&self, n: Vec3) -> [i16; 3] {
+        snorm16(n)
+    }
+}
+
+/// Whether, and where, vertex normals are written.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalLayout {
+    /// Discard normals, and write only positions.
+    None,
+    /// Follow each position in [Buffers::vertices] with its normal.
+    Interleaved,
+    /// Write normals to [Buffers::normals], in the same order as the positions.
+    Separate,
+}
+
+/// Vertex and index buffers, in a layout ready to be uploaded to the GPU.
+///
+/// Vertices which the extraction algorithm didn't provide a normal for are
+/// given a zero normal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buffers<C, I> {
+    /// Positions, optionally interleaved with normals.
+    pub vertices: Vec<C>,
+    /// Normals, if they are laid out separately from positions.
+    pub normals: Vec<C>,
+    pub indices: Vec<I>,
+}
+
+impl<C, I> Buffers<C, I> {
+    pub fn new() -> Self {
+        Self {
+            vertices: vec![],
+            normals: vec![],
+            indices: vec![],
+        }
+    }
+
+    fn push_vertex(&mut self, layout: NormalLayout, position: [C; 3], normal: [C; 3]) {
+        self.vertices.extend(IntoIterator::into_iter(position));
+        match layout {
+            NormalLayout::None => {}
+            NormalLayout::Interleaved => self.vertices.extend(IntoIterator::into_iter(normal)),
+            NormalLayout::Separate => self.normals.extend(IntoIterator::into_iter(normal)),
+        }
+    }
+}
+
+impl<C, I> Default for Buffers<C, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Output vertices and indices to a single set of [Buffers], in the given
+/// [PositionFormat] and [IndexType].
+///
+/// Extraction fails if the mesh has more vertices than the index type can
+/// address. Use [ChunkedBuffers] to split such meshes instead.
+pub struct IndexedBuffers<'a, F: PositionFormat, I> {
+    buffers: &'a mut Buffers<F::Component, I>,
+    format: F,
+    layout: NormalLayout,
+}
+
+impl<'a, F: PositionFormat, I> IndexedBuffers<'a, F, I> {
+    pub fn new(buffers: &'a mut Buffers<F::Component, I>, format: F, layout: NormalLayout) -> Self {
+        Self {
+            buffers,
+            format,
+            layout,
+        }
+    }
+}
+
+impl<'a, F: PositionFormat, I: IndexType, A> VertexExtractor<A> for IndexedBuffers<'a, F, I> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        let position = self.format.encode_position(vertex.position);
+        let normal = self.format.encode_normal(vertex.normal.unwrap_or_default());
+        self.buffers.push_vertex(self.layout, position, normal);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.buffers.indices.push(I::from_index(index));
+    }
+
+    fn max_index(&self) -> usize {
+        I::MAX
+    }
+}
+
+/// Output vertices and indices to a sequence of [Buffers], each of which has
+/// no more vertices than its [IndexType] can address.
+///
+/// This allows meshes of any size to be extracted with 16-bit indices. Each
+/// chunk holds whole triangles, and vertices shared by triangles in different
+/// chunks are duplicated in each. Since a triangle can't be placed until all
+/// of its vertices are known, vertices are held until the mesh is complete,
+/// and chunks are written as their triangles arrive.
+pub struct ChunkedBuffers<'a, F: PositionFormat, I> {
+    chunks: &'a mut Vec<Buffers<F::Component, I>>,
+    format: F,
+    layout: NormalLayout,
+    chunk_size: usize,
+    // Every vertex extracted so far, with normals laid out separately
+    vertices: Buffers<F::Component, I>,
+    triangle: Vec<usize>,
+    // The index of each extracted vertex within the current chunk
+    remap: HashMap<usize, usize>,
+}
+
+impl<'a, F: PositionFormat, I: IndexType> ChunkedBuffers<'a, F, I> {
+    /// Create a ChunkedBuffers which fills each chunk with as many vertices as
+    /// the index type can address.
+    pub fn new(
+        chunks: &'a mut Vec<Buffers<F::Component, I>>,
+        format: F,
+        layout: NormalLayout,
+    ) -> Self {
+        Self::new_with_chunk_size(chunks, format, layout, I::MAX.saturating_add(1))
+    }
+
+    /// Create a ChunkedBuffers which limits each chunk to `chunk_size`
+    /// vertices. The chunk size is clamped to the range the index type can
+    /// address, and must leave room for at least one triangle.
+    pub fn new_with_chunk_size(
+        chunks: &'a mut Vec<Buffers<F::Component, I>>,
+        format: F,
+        layout: NormalLayout,
+        chunk_size: usize,
+    ) -> Self {
+        Self {
+            chunks,
+            format,
+            layout,
+            chunk_size: chunk_size.clamp(3, I::MAX.saturating_add(1)),
+            vertices: Buffers::new(),
+            triangle: Vec::with_capacity(3),
+            remap: HashMap::new(),
+        }
+    }
+
+    fn add_triangle(&mut self) {
+        let new = self
+            .triangle
+            .iter()
+            .filter(|v| !self.remap.contains_key(v))
+            .count();
+        if self.chunks.is_empty() || self.remap.len() + new > self.chunk_size {
+            self.chunks.push(Buffers::new());
+            self.remap.clear();
+        }
+
+        let chunk = self.chunks.last_mut().unwrap();
+        let (vertices, layout) = (&self.vertices, self.layout);
+        for &v in &self.triangle {
+            let next = self.remap.len();
+            let index = *self.remap.entry(v).or_insert_with(|| {
+                let component = |data: &[F::Component], i| data[v * 3 + i];
+                let position = [0, 1, 2].map(|i| component(&vertices.vertices, i));
+                let normal = [0, 1, 2].map(|i| component(&vertices.normals, i));
+                chunk.push_vertex(layout, position, normal);
+                next
+            });
+            chunk.indices.push(I::from_index(index));
+        }
+        self.triangle.clear();
+    }
+}
+
+impl<'a, F: PositionFormat, I: IndexType, A> VertexExtractor<A> for ChunkedBuffers<'a, F, I> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        let position = self.format.encode_position(vertex.position);
+        let normal = self.format.encode_normal(vertex.normal.unwrap_or_default());
+        self.vertices
+            .push_vertex(NormalLayout::Separate, position, normal);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.triangle.push(index);
+        if self.triangle.len() == 3 {
+            self.add_triangle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed,
+        error::Error,
+        fixtures::{ball, extract_mesh},
+        sampler::Sampler,
+        source::ScalarSource,
+        MarchingCubes,
+    };
+
+    // Many parallel planes, for a mesh with a lot of vertices
+    struct Sheets;
+
+    impl ScalarSource for Sheets {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            Signed((p.x * 60.0).sin())
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let ball = ball();
+        let (positions, indices) = extract_mesh(&ball, 16);
+        let mut extractor = MarchingCubes::<Signed>::new(16).unwrap();
+        let sampler = Sampler::new(&ball);
+
+        let mut buffers = Buffers::<f64, u16>::new();
+        extractor
+            .extract(
+                &sampler,
+                &mut IndexedBuffers::new(&mut buffers, Float64, NormalLayout::Separate),
+            )
+            .unwrap();
+        assert_eq!(buffers.vertices.len(), positions.len() * 3);
+        assert_eq!(buffers.normals.len(), positions.len() * 3);
+        for (v, p) in buffers.vertices.chunks(3).zip(&positions) {
+            assert_eq!(v, &[p.x as f64, p.y as f64, p.z as f64]);
+        }
+        assert!(buffers
+            .indices
+            .iter()
+            .map(|&i| i as u32)
+            .eq(indices.clone()));
+
+        let format = Quantised16::new(Vec3::zero(), Vec3::one());
+        let mut buffers = Buffers::<i16, usize>::new();
+        extractor
+            .extract(
+                &sampler,
+                &mut IndexedBuffers::new(&mut buffers, format, NormalLayout::Interleaved),
+            )
+            .unwrap();
+        assert_eq!(buffers.vertices.len(), positions.len() * 6);
+        assert!(buffers.normals.is_empty());
+        for (v, p) in buffers.vertices.chunks(6).zip(&positions) {
+            let decoded = format.decode_position([v[0], v[1], v[2]]);
+            assert!((decoded - *p).abs().max_component() < 1e-4);
+        }
+        assert!(buffers.indices.iter().map(|&i| i as u32).eq(indices));
+
+        // 16-bit indices can't address every vertex of a large mesh
+        let mut buffers = Buffers::<f32, u16>::new();
+        let result = MarchingCubes::<Signed>::new(64).unwrap().extract(
+            &Sampler::new(&Sheets),
+            &mut IndexedBuffers::new(&mut buffers, Float32, NormalLayout::None),
+        );
+        assert!(matches!(result, Err(Error::IndexOverflow { .. })));
+    }
+
+    #[test]
+    fn test_flat_quantisation() {
+        // A box with no extent along z, as for a mesh lying in a plane
+        let format = Quantised16::new(Vec3::new(0.0, 0.0, 0.5), Vec3::new(1.0, 1.0, 0.5));
+        for &p in &[Vec3::new(0.25, 0.75, 0.5), Vec3::new(1.0, 0.0, 0.6)] {
+            let encoded = format.encode_position(p);
+            assert_eq!(encoded[2], 0);
+            let decoded = format.decode_position(encoded);
+            assert!((decoded - Vec3::new(p.x, p.y, 0.5)).abs().max_component() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_chunking() {
+        let ball = ball();
+        let (positions, indices) = extract_mesh(&ball, 16);
+        let mut chunks = Vec::<Buffers<f32, u16>>::new();
+        MarchingCubes::<Signed>::new(16)
+            .unwrap()
+            .extract(
+                &Sampler::new(&ball),
+                &mut ChunkedBuffers::new_with_chunk_size(
+                    &mut chunks,
+                    Float32,
+                    NormalLayout::None,
+                    100,
+                ),
+            )
+            .unwrap();
+        assert!(chunks.len() > 1);
+
+        // Every triangle survives, in order, with the same vertex positions
+        let mut triangles = indices.chunks(3);
+        for chunk in &chunks {
+            assert!(chunk.vertices.len() <= 300);
+            for face in chunk.indices.chunks(3) {
+                let expected = triangles.next().unwrap();
+                for (&i, &e) in face.iter().zip(expected) {
+                    let v = &chunk.vertices[i as usize * 3..i as usize * 3 + 3];
+                    let p = positions[e as usize];
+                    assert_eq!(v, &[p.x, p.y, p.z]);
+                }
+            }
+        }
+        assert!(triangles.next().is_none());
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    bytecode::{Op, Program, Register},
+    math::Vec3,
+};
+use std::collections::HashMap;
+
+/// The registers holding the coordinates of a point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Point {
+    pub x: Register,
+    pub y: Register,
+    pub z: Register,
+}
+
+/// A distance field which can be compiled to a [Program].
+pub trait Compile {
+    /// Emit the operations needed to evaluate the distance field at the point
+    /// `p`, and return the register holding the resulting signed distance.
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register;
+}
+
+/// Builds a [Program] one operation at a time.
+///
+/// While building, every operation writes to a fresh register (i.e. the
+/// program is in static single assignment form). Registers are only reused
+/// once the program is finished.
+pub struct Compiler {
+    ops: Vec<Op>,
+    constants: HashMap<u32, Register>,
+}
+
+impl Compiler {
+    /// Create a new Compiler, with the coordinates of the sample point already
+    /// loaded.
+    pub fn new() -> Self {
+        Self {
+            ops: vec![Op::X, Op::Y, Op::Z],
+            constants: HashMap::new(),
+        }
+    }
+
+    /// The registers holding the coordinates of the sample point.
+    pub fn point(&self) -> Point {
+        Point {
+            x: Register(0),
+            y: Register(1),
+            z: Register(2),
+        }
+    }
+
+    /// Append an operation, returning the register that holds its result.
+    pub fn push(&mut self, op: Op) -> Register {
+        let register = Register(self.ops.len() as u32);
+        self.ops.push(op);
+        register
+    }
+
+    /// Load a constant. Repeated constants share a single register.
+    pub fn constant(&mut self, value: f32) -> Register {
+        if let Some(&register) = self.constants.get(&value.to_bits()) {
+            return register;
+        }
+        let register = self.push(Op::Const(value));
+        self.constants.insert(value.to_bits(), register);
+        register
+    }
+
+    pub fn neg(&mut self, a: Register) -> Register {
+        self.push(Op::Neg(a))
+    }
+
+    pub fn abs(&mut self, a: Register) -> Register {
+        self.push(Op::Abs(a))
+    }
+
+    pub fn square(&mut self, a: Register) -> Register {
+        self.push(Op::Square(a))
+    }
+
+    pub fn sqrt(&mut self, a: Register) -> Register {
+        self.push(Op::Sqrt(a))
+    }
+
+    pub fn add(&mut self, a: Register, b: Register) -> Register {
+        self.push(Op::Add(a, b))
+    }
+
+    pub fn sub(&mut self, a: Register, b: Register) -> Register {
+        self.push(Op::Sub(a, b))
+    }
+
+    pub fn mul(&mut self, a: Register, b: Register) -> Register {
+        self.push(Op::Mul(a, b))
+    }
+
+    pub fn min(&mut self, a: Register, b: Register) -> Register {
+        self.push(Op::Min(a, b))
+    }
+
+    pub fn max(&mut self, a: Register, b: Register) -> Register {
+        self.push(Op::Max(a, b))
+    }
+
+    /// Subtract a constant from a value.
+    pub fn sub_constant(&mut self, a: Register, value: f32) -> Register {
+        let b = self.constant(value);
+        self.sub(a, b)
+    }
+
+    /// The Euclidean length of the vector formed from the given values.
+    pub fn length(&mut self, values: &[Register]) -> Register {
+        let mut sum = self.square(values[0]);
+        for &v in &values[1..] {
+            let sq = self.square(v);
+            sum = self.add(sum, sq);
+        }
+        self.sqrt(sum)
+    }
+
+    /// Offset a point, such that a distance field compiled at the resulting
+    /// point will be centred on `offset`.
+    pub fn translate(&mut self, p: Point, offset: Vec3) -> Point {
+        Point {
+            x: self.sub_constant(p.x, offset.x),
+            y: self.sub_constant(p.y, offset.y),
+            z: self.sub_constant(p.z, offset.z),
+        }
+    }
+
+    /// Finish compilation, with the final signed distance held in `output`.
+    pub fn finish(self, output: Register) -> Program {
+        Program::new(self.ops, output)
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+mod compiler;
+mod program;
+
+pub use compiler::*;
+pub use program::*;
+
+/// A register in the virtual machine, holding a single scalar value.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Register(pub(crate) u32);
+
+/// A single operation in a compiled [Program].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    /// Load the x coordinate of the sample point.
+    X,
+    /// Load the y coordinate of the sample point.
+    Y,
+    /// Load the z coordinate of the sample point.
+    Z,
+    /// Load a constant value.
+    Const(f32),
+    /// Negate a value.
+    Neg(Register),
+    /// Take the absolute value of a value.
+    Abs(Register),
+    /// Square a value.
+    Square(Register),
+    /// Take the square root of a value.
+    Sqrt(Register),
+    /// Add two values.
+    Add(Register, Register),
+    /// Subtract the second value from the first.
+    Sub(Register, Register),
+    /// Multiply two values.
+    Mul(Register, Register),
+    /// Take the minimum of two values.
+    Min(Register, Register),
+    /// Take the maximum of two values.
+    Max(Register, Register),
+}
+
+/// An instruction in a compiled [Program], which evaluates an [Op] and stores
+/// the result in the `out` register.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Instruction {
+    pub out: Register,
+    pub op: Op,
+}
+
+impl Op {
+    /// The registers this operation reads from.
+    pub fn arguments(&self) -> impl Iterator<Item = Register> {
+        let (a, b) = match *self {
+            Op::X | Op::Y | Op::Z | Op::Const(_) => (None, None),
+            Op::Neg(a) | Op::Abs(a) | Op::Square(a) | Op::Sqrt(a) => (Some(a), None),
+            Op::Add(a, b) | Op::Sub(a, b) | Op::Mul(a, b) | Op::Min(a, b) | Op::Max(a, b) => {
+                (Some(a), Some(b))
+            }
+        };
+        a.into_iter().chain(b)
+    }
+
+    /// Create a copy of this operation with every argument replaced by the
+    /// result of the provided function.
+    pub(crate) fn remap<F: Fn(Register) -> Register>(&self, f: F) -> Self {
+        match *self {
+            Op::X => Op::X,
+            Op::Y => Op::Y,
+            Op::Z => Op::Z,
+            Op::Const(c) => Op::Const(c),
+            Op::Neg(a) => Op::Neg(f(a)),
+            Op::Abs(a) => Op::Abs(f(a)),
+            Op::Square(a) => Op::Square(f(a)),
+            Op::Sqrt(a) => Op::Sqrt(f(a)),
+            Op::Add(a, b) => Op::Add(f(a), f(b)),
+            Op::Sub(a, b) => Op::Sub(f(a), f(b)),
+            Op::Mul(a, b) => Op::Mul(f(a), f(b)),
+            Op::Min(a, b) => Op::Min(f(a), f(b)),
+            Op::Max(a, b) => Op::Max(f(a), f(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        implicit::{Cylinder, Difference, RectangularPrism, Sphere, Torus, Union},
+        math::Vec3,
+        source::{BatchScalarSource, ScalarSource},
+    };
+
+    fn assert_matches<S: ScalarSource + Compile>(source: &S) {
+        let program = Program::compile(source);
+
+        for i in 0..64 {
+            let p = Vec3::new(
+                (i % 4) as f32 - 1.5,
+                ((i / 4) % 4) as f32 - 1.5,
+                (i / 16) as f32 - 1.5,
+            );
+            let expected = source.sample_scalar(p).0;
+            let actual = program.sample_scalar(p).0;
+            assert!(
+                (expected - actual).abs() < 1e-5,
+                "{} != {}",
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_primitives() {
+        assert_matches(&Sphere::new(1.0));
+        assert_matches(&RectangularPrism::new(Vec3::new(0.5, 1.0, 1.5)));
+        assert_matches(&Cylinder::new(1.0, 0.5));
+        assert_matches(&Torus::new(1.0, 0.25));
+        assert_matches(&Union::new(
+            Difference::new(
+                Sphere::new(1.0),
+                RectangularPrism::new(Vec3::from_scalar(0.8)),
+            ),
+            Cylinder::new(0.1, 1.0),
+        ));
+    }
+
+    #[test]
+    fn test_batch_matches_scalar() {
+        let program = Program::compile(&Torus::new(1.0, 0.25));
+
+        let points: Vec<Vec3> = (0..200)
+            .map(|i| Vec3::new(i as f32 * 0.01, 1.0 - i as f32 * 0.01, 0.1))
+            .collect();
+        let mut distances = vec![crate::distance::Signed(0.0); points.len()];
+        program.sample_scalar_batch(&points, &mut distances);
+
+        for (&p, d) in points.iter().zip(distances.iter()) {
+            assert_eq!(program.sample_scalar(p).0, d.0);
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut compiler = Compiler::new();
+        let p = compiler.point();
+        let p_a = compiler.translate(p, Vec3::new(-0.5, 0.0, 0.0));
+        let a = Sphere::new(0.25).compile(&mut compiler, p_a);
+        let p_b = compiler.translate(p, Vec3::new(0.5, 0.0, 0.0));
+        let b = Sphere::new(0.25).compile(&mut compiler, p_b);
+        let output = compiler.min(a, b);
+        let program = compiler.finish(output);
+
+        // Near the left sphere, the right sphere can't affect the result
+        let min = Vec3::new(-0.75, -0.25, -0.25);
+        let max = Vec3::new(-0.25, 0.25, 0.25);
+        let pruned = program.prune(min, max);
+
+        assert!(pruned.instructions().len() < program.instructions().len());
+        for i in 0..27 {
+            let f = Vec3::new((i % 3) as f32, ((i / 3) % 3) as f32, (i / 9) as f32) * 0.5;
+            let p = min + (max - min) * f;
+            assert_eq!(pruned.sample_scalar(p).0, program.sample_scalar(p).0);
+        }
+
+        let bounds = program.evaluate_interval(min, max);
+        assert!(bounds.contains(program.sample_scalar(min).0));
+        assert!(bounds.contains(program.sample_scalar(Vec3::new(-0.5, 0.0, 0.0)).0));
+    }
+}
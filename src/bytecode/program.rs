@@ -0,0 +1,330 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    bytecode::{Compile, Compiler, Instruction, Op, Register},
+    distance::Signed,
+    math::{Interval, Vec3},
+    source::{BatchScalarSource, IntervalSource, ScalarSource},
+};
+
+// Programs with at most this many registers are evaluated without touching
+// the heap.
+const STACK_REGISTERS: usize = 32;
+
+// The number of points evaluated together during batch evaluation.
+const BATCH_SIZE: usize = 64;
+
+/// A distance field compiled to a flat list of register-based instructions.
+///
+/// Deeply nested CSG trees built from the generic types in
+/// [implicit](crate::implicit) are fast to evaluate, but each new tree is a
+/// new type, and compile times grow quickly with the depth of the tree. A
+/// Program evaluates any tree with a single small interpreter instead.
+///
+/// Programs can also be specialised to a region of space via
+/// [prune](Program::prune), which uses interval arithmetic to remove any
+/// branches of the tree that can't affect the field within that region.
+/// The bounded traversals do so automatically for each block or octree node
+/// they sample, via [IntervalSource::specialise].
+#[derive(Clone)]
+pub struct Program {
+    // The program in static single assignment form, where op N writes to
+    // register N. Retained so that the program can be pruned.
+    ops: Vec<Op>,
+    output: Register,
+    // The program after register allocation, which is what we evaluate.
+    instructions: Vec<Instruction>,
+    register_count: usize,
+    result: Register,
+}
+
+impl Program {
+    /// Compile a distance field to a Program.
+    pub fn compile<C: Compile>(source: &C) -> Self {
+        let mut compiler = Compiler::new();
+        let p = compiler.point();
+        let output = source.compile(&mut compiler, p);
+        compiler.finish(output)
+    }
+
+    pub(crate) fn new(ops: Vec<Op>, output: Register) -> Self {
+        let (ops, output) = Self::eliminate_dead_code(&ops, output);
+        let (instructions, register_count, result) = Self::allocate_registers(&ops, output);
+
+        Self {
+            ops,
+            output,
+            instructions,
+            register_count,
+            result,
+        }
+    }
+
+    /// The instructions that make up this program.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// The number of registers needed to evaluate this program.
+    pub fn register_count(&self) -> usize {
+        self.register_count
+    }
+
+    /// Evaluate the program over an axis-aligned box, returning bounds on the
+    /// value of the distance field anywhere in that box.
+    pub fn evaluate_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let mut registers = vec![Interval::point(0.0); self.register_count];
+
+        for instruction in &self.instructions {
+            registers[instruction.out.0 as usize] =
+                Self::interval_op(instruction.op, &registers, min, max);
+        }
+
+        registers[self.result.0 as usize]
+    }
+
+    /// Create a copy of this program specialised to an axis-aligned box.
+    ///
+    /// Wherever interval arithmetic proves that one side of a min or max
+    /// operation always wins inside the box, the other side is discarded.
+    /// The resulting program evaluates to exactly the same values inside the
+    /// box, but may be substantially shorter. Outside the box, results are
+    /// undefined.
+    pub fn prune(&self, min: Vec3, max: Vec3) -> Self {
+        let mut intervals = Vec::with_capacity(self.ops.len());
+        let mut remap = Vec::with_capacity(self.ops.len());
+        let mut ops = Vec::with_capacity(self.ops.len());
+
+        for &op in &self.ops {
+            let interval = Self::interval_op(op, &intervals, min, max);
+            intervals.push(interval);
+
+            // For min, `a` wins if it is always lower. For max, `a` wins if
+            // `b` is always lower.
+            let choice = match op {
+                Op::Min(a, b) => {
+                    Self::choose(intervals[a.0 as usize], intervals[b.0 as usize], a, b)
+                }
+                Op::Max(a, b) => {
+                    Self::choose(intervals[b.0 as usize], intervals[a.0 as usize], a, b)
+                }
+                _ => None,
+            };
+
+            if let Some(register) = choice {
+                remap.push(remap[register.0 as usize]);
+            } else {
+                remap.push(Register(ops.len() as u32));
+                ops.push(op.remap(|r| remap[r.0 as usize]));
+            }
+        }
+
+        Self::new(ops, remap[self.output.0 as usize])
+    }
+
+    // Given the intervals of the operand which should win (`preferred`) and the
+    // operand which should lose (`other`), decide if we can statically choose
+    // between the registers `a` and `b`. The preferred operand is always `a`.
+    fn choose(preferred: Interval, other: Interval, a: Register, b: Register) -> Option<Register> {
+        if preferred.max < other.min {
+            Some(a)
+        } else if other.max < preferred.min {
+            Some(b)
+        } else {
+            None
+        }
+    }
+
+    fn interval_op(op: Op, registers: &[Interval], min: Vec3, max: Vec3) -> Interval {
+        let r = |r: Register| registers[r.0 as usize];
+        match op {
+            Op::X => Interval::new(min.x, max.x),
+            Op::Y => Interval::new(min.y, max.y),
+            Op::Z => Interval::new(min.z, max.z),
+            Op::Const(c) => Interval::point(c),
+            Op::Neg(a) => -r(a),
+            Op::Abs(a) => r(a).abs(),
+            Op::Square(a) => r(a).square(),
+            Op::Sqrt(a) => r(a).sqrt(),
+            Op::Add(a, b) => r(a) + r(b),
+            Op::Sub(a, b) => r(a) - r(b),
+            Op::Mul(a, b) => r(a) * r(b),
+            Op::Min(a, b) => r(a).min(r(b)),
+            Op::Max(a, b) => r(a).max(r(b)),
+        }
+    }
+
+    #[inline]
+    fn scalar_op(op: Op, registers: &[f32], p: Vec3) -> f32 {
+        let r = |r: Register| registers[r.0 as usize];
+        match op {
+            Op::X => p.x,
+            Op::Y => p.y,
+            Op::Z => p.z,
+            Op::Const(c) => c,
+            Op::Neg(a) => -r(a),
+            Op::Abs(a) => r(a).abs(),
+            Op::Square(a) => r(a) * r(a),
+            Op::Sqrt(a) => r(a).sqrt(),
+            Op::Add(a, b) => r(a) + r(b),
+            Op::Sub(a, b) => r(a) - r(b),
+            Op::Mul(a, b) => r(a) * r(b),
+            Op::Min(a, b) => r(a).min(r(b)),
+            Op::Max(a, b) => r(a).max(r(b)),
+        }
+    }
+
+    fn evaluate(&self, p: Vec3, registers: &mut [f32]) -> f32 {
+        for instruction in &self.instructions {
+            registers[instruction.out.0 as usize] = Self::scalar_op(instruction.op, registers, p);
+        }
+        registers[self.result.0 as usize]
+    }
+
+    fn evaluate_batch(&self, points: &[Vec3], distances: &mut [Signed], registers: &mut [f32]) {
+        let lane = |r: Register| r.0 as usize * BATCH_SIZE;
+
+        for instruction in &self.instructions {
+            let out = lane(instruction.out);
+            for (i, p) in points.iter().enumerate() {
+                let r = |r: Register| registers[lane(r) + i];
+                registers[out + i] = match instruction.op {
+                    Op::X => p.x,
+                    Op::Y => p.y,
+                    Op::Z => p.z,
+                    Op::Const(c) => c,
+                    Op::Neg(a) => -r(a),
+                    Op::Abs(a) => r(a).abs(),
+                    Op::Square(a) => r(a) * r(a),
+                    Op::Sqrt(a) => r(a).sqrt(),
+                    Op::Add(a, b) => r(a) + r(b),
+                    Op::Sub(a, b) => r(a) - r(b),
+                    Op::Mul(a, b) => r(a) * r(b),
+                    Op::Min(a, b) => r(a).min(r(b)),
+                    Op::Max(a, b) => r(a).max(r(b)),
+                };
+            }
+        }
+
+        let result = lane(self.result);
+        for (i, d) in distances.iter_mut().enumerate() {
+            *d = Signed(registers[result + i]);
+        }
+    }
+
+    // Remove any ops that don't contribute to the output, and renumber the
+    // remaining ops to be contiguous.
+    fn eliminate_dead_code(ops: &[Op], output: Register) -> (Vec<Op>, Register) {
+        let mut live = vec![false; ops.len()];
+        live[output.0 as usize] = true;
+
+        for i in (0..ops.len()).rev() {
+            if live[i] {
+                for a in ops[i].arguments() {
+                    live[a.0 as usize] = true;
+                }
+            }
+        }
+
+        let mut remap = vec![Register(0); ops.len()];
+        let mut result = Vec::with_capacity(ops.len());
+
+        for (i, op) in ops.iter().enumerate() {
+            if live[i] {
+                remap[i] = Register(result.len() as u32);
+                result.push(op.remap(|r| remap[r.0 as usize]));
+            }
+        }
+
+        (result, remap[output.0 as usize])
+    }
+
+    // Map each op onto a physical register, reusing registers as soon as the
+    // value they hold is no longer needed.
+    fn allocate_registers(ops: &[Op], output: Register) -> (Vec<Instruction>, usize, Register) {
+        let mut last_use = vec![0; ops.len()];
+        for (i, op) in ops.iter().enumerate() {
+            for a in op.arguments() {
+                last_use[a.0 as usize] = i;
+            }
+        }
+        last_use[output.0 as usize] = usize::MAX;
+
+        let mut physical = vec![Register(0); ops.len()];
+        let mut free = vec![];
+        let mut register_count = 0;
+        let mut instructions = Vec::with_capacity(ops.len());
+
+        for (i, op) in ops.iter().enumerate() {
+            let op = op.remap(|r| physical[r.0 as usize]);
+
+            // Release the arguments that die here, so that the result may
+            // overwrite one of them
+            for (a, physical_a) in ops[i].arguments().zip(op.arguments()) {
+                if last_use[a.0 as usize] == i && !free.contains(&physical_a) {
+                    free.push(physical_a);
+                }
+            }
+
+            let out = free.pop().unwrap_or_else(|| {
+                register_count += 1;
+                Register(register_count as u32 - 1)
+            });
+            physical[i] = out;
+            instructions.push(Instruction { out, op });
+        }
+
+        (instructions, register_count, physical[output.0 as usize])
+    }
+}
+
+impl ScalarSource for Program {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        if self.register_count <= STACK_REGISTERS {
+            let mut registers = [0.0; STACK_REGISTERS];
+            Signed(self.evaluate(p, &mut registers))
+        } else {
+            let mut registers = vec![0.0; self.register_count];
+            Signed(self.evaluate(p, &mut registers))
+        }
+    }
+}
+
+impl IntervalSource for Program {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.evaluate_interval(min, max)
+    }
+
+    fn specialise(&self, min: Vec3, max: Vec3) -> Option<Program> {
+        let pruned = self.prune(min, max);
+        if pruned.instructions.len() < self.instructions.len() {
+            Some(pruned)
+        } else {
+            None
+        }
+    }
+}
+
+impl BatchScalarSource for Program {
+    fn sample_scalar_batch(&self, points: &[Vec3], distances: &mut [Signed]) {
+        let mut registers = vec![0.0; self.register_count * BATCH_SIZE];
+
+        for (points, distances) in points
+            .chunks(BATCH_SIZE)
+            .zip(distances.chunks_mut(BATCH_SIZE))
+        {
+            self.evaluate_batch(points, distances, &mut registers);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    buffer::IndexType, distance::Signed, error::Error, math::Vec3, mesh::MeshTopology,
+    normals::face_normal, sampler::Sampler, source::ScalarSource, traversal::PrimalGrid,
+};
+
+/// A connected piece of a mesh, i.e. a set of faces linked by shared edges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Component {
+    /// The faces in the component, in the order they appear in the mesh.
+    pub faces: Vec<usize>,
+    pub area: f32,
+    /// The volume enclosed by the component, which is only meaningful if the
+    /// component is closed. Positive for surfaces wound as extracted (i.e.
+    /// clockwise when viewed from outside), and negative for inverted ones.
+    pub volume: f32,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Component {
+    /// The number of triangles in the component.
+    pub fn triangle_count(&self) -> usize {
+        self.faces.len()
+    }
+}
+
+/// Split an indexed triangle mesh into its connected components, ordered by
+/// their first face.
+pub fn label_components<I: IndexType>(positions: &[Vec3], indices: &[I]) -> Vec<Component> {
+    let mut topology = MeshTopology::new();
+    let handles = positions
+        .iter()
+        .map(|_| topology.add_vertex())
+        .collect::<Vec<_>>();
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| handles[face[i].to_index()]);
+        topology.add_face(a, b, c);
+    }
+
+    let mut components: Vec<Component> = vec![];
+    for (f, label) in topology.connected_components().into_iter().enumerate() {
+        let p = [0, 1, 2].map(|i| positions[indices[f * 3 + i].to_index()]);
+        if label == components.len() {
+            components.push(Component {
+                faces: vec![],
+                area: 0.0,
+                volume: 0.0,
+                min: p[0],
+                max: p[0],
+            });
+        }
+
+        let component = &mut components[label];
+        component.faces.push(f);
+        let normal = face_normal(p.iter().copied());
+        component.area += normal.len() * 0.5;
+        // By the divergence theorem, summing the signed volumes of the
+        // tetrahedra from the origin to each face
+        component.volume += p[0].dot(normal) / 6.0;
+        for &p in &p {
+            component.min = component.min.min(p);
+            component.max = component.max.max(p);
+        }
+    }
+    components
+}
+
+/// Remove the faces of every component for which `keep` returns false, and
+/// return the number of components removed. Vertices are left in place, so
+/// indices remain valid.
+///
+/// For example, to remove floating specks of noise:
+/// ```
+/// # use isosurface::{components::retain_components, math::Vec3};
+/// # let positions: Vec<Vec3> = vec![];
+/// # let mut indices: Vec<u32> = vec![];
+/// retain_components(&positions, &mut indices, |c| c.triangle_count() >= 100);
+/// ```
+pub fn retain_components<I, F>(positions: &[Vec3], indices: &mut Vec<I>, mut keep: F) -> usize
+where
+    I: IndexType,
+    F: FnMut(&Component) -> bool,
+{
+    let components = label_components(positions, indices);
+    let mut kept = vec![false; indices.len() / 3];
+    let mut removed = 0;
+    for component in &components {
+        if keep(component) {
+            for &f in &component.faces {
+                kept[f] = true;
+            }
+        } else {
+            removed += 1;
+        }
+    }
+
+    let mut faces = kept.iter();
+    indices.truncate(kept.len() * 3);
+    let retained = indices
+        .chunks_exact(3)
+        .filter(|_| *faces.next().unwrap())
+        .flatten()
+        .copied()
+        .collect();
+    *indices = retained;
+    removed
+}
+
+/// Removes small islands of the inside of a [ScalarSource] before extraction.
+///
+/// The source is sampled at every point of an extractor's [PrimalGrid], and
+/// the inside points are flood-filled into connected regions (through the
+/// faces of each grid cell). Points in regions with fewer than `min_points`
+/// points are then treated as outside, by flipping the sign of their distance
+/// whenever the nearest grid point is one of them.
+///
+/// The filter keeps the distance at every grid point the traversal visits,
+/// which includes one layer past the far side of the unit cube along z, so it
+/// needs `size^2 * (size + 1)` distances worth of memory, but an extractor traversing the same grid reads
+/// them back rather than sampling the source a second time. Samples between
+/// grid points (e.g. for normals or refinement) still go to the source.
+pub struct IslandFilter<'a, S: ScalarSource> {
+    pub source: &'a S,
+    size: usize,
+    layers: usize,
+    values: Vec<Signed>,
+    removed: Vec<bool>,
+}
+
+impl<'a, S: ScalarSource> IslandFilter<'a, S> {
+    /// Sample the source over the given grid and find its islands. To avoid
+    /// sampling twice, the grid should be the one the mesh will be extracted
+    /// from, e.g. [MarchingCubes::primal_grid](crate::MarchingCubes::primal_grid).
+    pub fn new(
+        grid: &mut PrimalGrid<Signed>,
+        source: &'a S,
+        min_points: usize,
+    ) -> Result<Self, Error> {
+        let size = grid.size();
+        // Traversal of the grid also visits a layer of points past z = 1
+        let layers = size + 1;
+        let index = |(x, y, z): (usize, usize, usize)| (z * size + y) * size + x;
+        let mut values = vec![Signed(0.0); size * size * layers];
+        grid.traverse(&Sampler::new(source), |keys, _, corners| {
+            for (&key, &value) in keys.iter().zip(corners) {
+                values[index(key)] = value;
+            }
+        })?;
+
+        let mut removed = vec![false; values.len()];
+        let mut visited = vec![false; values.len()];
+        let inside = |i: usize| values[i].0 <= 0.0;
+        for seed in 0..values.len() {
+            if !inside(seed) || visited[seed] {
+                continue;
+            }
+            visited[seed] = true;
+            let mut region = vec![seed];
+            let mut next = 0;
+            while next < region.len() {
+                let i = region[next];
+                next += 1;
+                let (x, y, z) = (i % size, i / size % size, i / (size * size));
+                let neighbours = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < size).then(|| i + 1),
+                    (y > 0).then(|| i - size),
+                    (y + 1 < size).then(|| i + size),
+                    (z > 0).then(|| i - size * size),
+                    (z + 1 < layers).then(|| i + size * size),
+                ];
+                for n in neighbours.iter().flatten().copied() {
+                    if inside(n) && !visited[n] {
+                        visited[n] = true;
+                        region.push(n);
+                    }
+                }
+            }
+            if region.len() < min_points {
+                for i in region {
+                    removed[i] = true;
+                }
+            }
+        }
+
+        for (value, &removed) in values.iter_mut().zip(&removed) {
+            if removed {
+                *value = outside(*value);
+            }
+        }
+
+        Ok(Self {
+            source,
+            size,
+            layers,
+            values,
+            removed,
+        })
+    }
+
+    // The index of the grid point nearest to p, and whether p lies on it
+    fn nearest(&self, p: Vec3) -> (usize, bool) {
+        let scaled = p * (self.size - 1) as f32;
+        let coordinate = |c: f32, n: usize| (c.round().max(0.0) as usize).min(n - 1);
+        let (x, y, z) = (
+            coordinate(scaled.x, self.size),
+            coordinate(scaled.y, self.size),
+            coordinate(scaled.z, self.layers),
+        );
+        let offset = scaled - Vec3::new(x as f32, y as f32, z as f32);
+        let index = (z * self.size + y) * self.size + x;
+        (index, offset.abs().max_component() < 1e-4)
+    }
+}
+
+// Push the surface just outside of a grid point, so that there's never a
+// crossing between it and its neighbours
+fn outside(d: Signed) -> Signed {
+    Signed(d.0.abs().max(f32::EPSILON))
+}
+
+impl<'a, S: ScalarSource> ScalarSource for IslandFilter<'a, S> {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        let (index, on_grid) = self.nearest(p);
+        if on_grid {
+            return self.values[index];
+        }
+
+        let d = self.source.sample_scalar(p);
+        if self.removed[index] {
+            outside(d)
+        } else {
+            d
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extractor::IndexedVertices,
+        fixtures::{extract_mesh, two_spheres, Counted},
+        sampler::Sampler,
+        MarchingCubes,
+    };
+
+    // A large ball, and a small one, which is barely larger than a grid cell
+    struct Balls;
+
+    impl ScalarSource for Balls {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            let large = (p - Vec3::from_scalar(0.4)).len() - 0.25;
+            let small = (p - Vec3::from_scalar(0.8)).len() - 0.06;
+            Signed(large.min(small))
+        }
+    }
+
+    #[test]
+    fn test_components() {
+        let (positions, mut indices) = extract_mesh(&Balls, 24);
+        let components = label_components(&positions, &indices);
+        assert_eq!(components.len(), 2);
+
+        let (large, small) = if components[0].area > components[1].area {
+            (&components[0], &components[1])
+        } else {
+            (&components[1], &components[0])
+        };
+        let volume = |r: f32| 4.0 / 3.0 * std::f32::consts::PI * r * r * r;
+        assert!((large.volume - volume(0.25)).abs() < volume(0.25) * 0.05);
+        assert!(small.volume > 0.0 && small.volume < large.volume / 10.0);
+        assert!((large.min - Vec3::from_scalar(0.15)).abs().max_component() < 0.01);
+        assert!((large.max - Vec3::from_scalar(0.65)).abs().max_component() < 0.01);
+
+        let faces = large.triangle_count();
+        let removed = retain_components(&positions, &mut indices, |c| c.triangle_count() > 100);
+        assert_eq!(removed, 1);
+        assert_eq!(indices.len(), faces * 3);
+        assert_eq!(label_components(&positions, &indices).len(), 1);
+    }
+
+    #[test]
+    fn test_island_filter() {
+        let filter = IslandFilter::new(&mut PrimalGrid::new(24).unwrap(), &Balls, 20).unwrap();
+        let (positions, indices) = extract_mesh(&filter, 24);
+        let components = label_components(&positions, &indices);
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].faces.len() * 3, indices.len());
+
+        // The large ball is untouched
+        let (unfiltered_positions, unfiltered) = extract_mesh(&Balls, 24);
+        let large = label_components(&unfiltered_positions, &unfiltered)
+            .into_iter()
+            .map(|c| c.area)
+            .fold(0.0, f32::max);
+        assert!(indices.len() < unfiltered.len());
+        assert!((components[0].area - large).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_island_filter_samples_once() {
+        let source = Counted::new(two_spheres(), false);
+        let mut marching_cubes = MarchingCubes::<Signed>::new(24).unwrap();
+        let filter = IslandFilter::new(marching_cubes.primal_grid(), &source, 20).unwrap();
+        assert_eq!(source.samples.get(), 24 * 24 * 25);
+
+        let (mut vertices, mut indices) = (vec![], vec![]);
+        marching_cubes
+            .extract(
+                &Sampler::new(&filter),
+                &mut IndexedVertices::new(&mut vertices, &mut indices),
+            )
+            .unwrap();
+        assert!(!indices.is_empty());
+        assert_eq!(source.samples.get(), 24 * 24 * 25);
+    }
+}
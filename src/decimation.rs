@@ -0,0 +1,372 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    feature::Quadric,
+    half_edge::{HalfEdgeMesh, VertexHandle},
+    math::Vec3,
+    normals::face_normal,
+    sampler::project_onto_surface,
+    source::HermiteSource,
+};
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+/// Simplifies meshes by repeatedly collapsing the edge which introduces the
+/// least error, as measured by the quadric error metric (see [Quadric]).
+///
+/// This is an implementation of the paper [Surface Simplification Using Quadric Error Metrics](https://dl.acm.org/doi/10.1145/258734.258849).
+/// It works well to clean up the small triangle slivers produced by marching
+/// cubes, since collapsing them introduces very little error.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Decimator {
+    /// Stop once the mesh has no more than this many faces.
+    pub target_faces: usize,
+    /// Stop once every remaining collapse would introduce more than this
+    /// error, measured as the sum of squared distances to the original planes.
+    pub max_error: f32,
+    /// Don't move or remove vertices on the boundary of the mesh.
+    pub preserve_boundaries: bool,
+    /// Don't move or remove vertices flagged as features (i.e. by
+    /// [ExtendedMarchingCubes](crate::ExtendedMarchingCubes)).
+    pub preserve_features: bool,
+}
+
+impl Decimator {
+    /// Create a Decimator which removes faces until at most `target_faces`
+    /// remain.
+    pub fn new_with_face_count(target_faces: usize) -> Self {
+        Self {
+            target_faces,
+            max_error: f32::INFINITY,
+            preserve_boundaries: true,
+            preserve_features: true,
+        }
+    }
+
+    /// Create a Decimator which removes faces until any further collapse would
+    /// introduce more than `max_error`.
+    pub fn new_with_max_error(max_error: f32) -> Self {
+        Self {
+            target_faces: 0,
+            max_error,
+            preserve_boundaries: true,
+            preserve_features: true,
+        }
+    }
+
+    /// Decimate the mesh in place. Returns the largest error introduced by any
+    /// single collapse.
+    ///
+    /// Surviving vertices keep their own normals and attributes.
+    pub fn decimate<A>(&self, mesh: &mut HalfEdgeMesh<A>) -> f32 {
+        self.run(mesh, |p| p, |_, _| {})
+    }
+
+    /// Decimate the mesh in place, projecting each moved vertex back onto the
+    /// surface of the source, and resampling its normal. Returns the largest
+    /// error introduced by any single collapse.
+    pub fn decimate_onto<A, S: HermiteSource>(
+        &self,
+        mesh: &mut HalfEdgeMesh<A>,
+        source: &S,
+    ) -> f32 {
+        self.run(
+            mesh,
+            |p| project_onto_surface(source, p),
+            |mesh, v| {
+                let p = mesh.position(v);
+                mesh.vertex_mut(v).normal = source.sample_normal(p).normalised();
+            },
+        )
+    }
+
+    fn run<A, P, F>(&self, mesh: &mut HalfEdgeMesh<A>, project: P, mut moved: F) -> f32
+    where
+        P: Fn(Vec3) -> Vec3,
+        F: FnMut(&mut HalfEdgeMesh<A>, VertexHandle),
+    {
+        let size = mesh.vertices().last().map_or(0, |v| v.index() + 1);
+        let mut quadrics = vec![Quadric::default(); size];
+        let mut generations = vec![0u32; size];
+
+        for f in mesh.faces() {
+            if let Some(normal) = mesh.face_normal(f) {
+                for v in &mesh.face_vertices(f) {
+                    quadrics[v.index()] += Quadric::from_plane(mesh.position(*v), normal);
+                }
+            }
+        }
+
+        let candidate = |mesh: &HalfEdgeMesh<A>,
+                         quadrics: &[Quadric],
+                         generations: &[u32],
+                         a: VertexHandle,
+                         b: VertexHandle| {
+            let (remove, keep, quadric, position) =
+                self.plan_collapse(mesh, quadrics, a, b, &project)?;
+            Some(Candidate {
+                cost: quadric.error(position) as f32,
+                remove,
+                keep,
+                position,
+                generations: [generations[remove.index()], generations[keep.index()]],
+            })
+        };
+
+        let mut heap = BinaryHeap::new();
+        for h in mesh.half_edges() {
+            if mesh.twin(h).is_none_or(|t| h < t) {
+                heap.extend(candidate(
+                    mesh,
+                    &quadrics,
+                    &generations,
+                    mesh.start(h),
+                    mesh.end(h),
+                ));
+            }
+        }
+
+        let mut faces = mesh.face_count();
+        let mut max_error = 0.0f32;
+
+        while faces > self.target_faces {
+            let c = match heap.pop() {
+                Some(c) if c.cost <= self.max_error => c,
+                _ => break,
+            };
+            let (remove, keep) = (c.remove, c.keep);
+
+            // Skip candidates which have been invalidated by earlier collapses
+            if c.generations != [generations[remove.index()], generations[keep.index()]] {
+                continue;
+            }
+            let h = match mesh
+                .find_half_edge(remove, keep)
+                .or_else(|| mesh.find_half_edge(keep, remove))
+            {
+                Some(h) => h,
+                None => continue,
+            };
+            if flips_faces(mesh, remove, keep, c.position) {
+                continue;
+            }
+
+            let sides = if mesh.twin(h).is_some() { 2 } else { 1 };
+            if mesh.collapse_edge_into(h, keep).is_none() {
+                continue;
+            }
+            faces -= sides;
+            max_error = max_error.max(c.cost);
+
+            mesh.vertex_mut(keep).position = c.position;
+            moved(mesh, keep);
+            let removed = quadrics[remove.index()];
+            quadrics[keep.index()] += removed;
+            generations[keep.index()] += 1;
+            generations[remove.index()] += 1;
+
+            let neighbours = mesh.vertex_neighbours(keep).collect::<Vec<_>>();
+            for n in neighbours {
+                heap.extend(candidate(mesh, &quadrics, &generations, keep, n));
+            }
+        }
+
+        max_error
+    }
+
+    // Decide which vertex of the edge survives the collapse, and where it ends
+    // up. Returns None if neither vertex may be removed.
+    fn plan_collapse<A, P>(
+        &self,
+        mesh: &HalfEdgeMesh<A>,
+        quadrics: &[Quadric],
+        a: VertexHandle,
+        b: VertexHandle,
+        project: P,
+    ) -> Option<(VertexHandle, VertexHandle, Quadric, Vec3)>
+    where
+        P: Fn(Vec3) -> Vec3,
+    {
+        let locked = |v: VertexHandle| {
+            (self.preserve_boundaries && mesh.is_boundary_vertex(v))
+                || (self.preserve_features && mesh.vertex(v).feature)
+        };
+        let quadric = quadrics[a.index()] + quadrics[b.index()];
+
+        let (remove, keep, position) = match (locked(a), locked(b)) {
+            (true, true) => return None,
+            (true, false) => (b, a, mesh.position(a)),
+            (false, true) => (a, b, mesh.position(b)),
+            (false, false) => (a, b, project(quadric.minimise())),
+        };
+
+        if position.all(f32::is_finite) {
+            Some((remove, keep, quadric, position))
+        } else {
+            None
+        }
+    }
+}
+
+// A pending edge collapse, ordered so that the cheapest collapse is at the top
+// of the heap
+struct Candidate {
+    cost: f32,
+    remove: VertexHandle,
+    keep: VertexHandle,
+    position: Vec3,
+    generations: [u32; 2],
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+// Test whether moving both vertices of the edge to `position` would flip (or
+// flatten) any of the faces which survive the collapse
+pub(crate) fn flips_faces<A>(
+    mesh: &HalfEdgeMesh<A>,
+    remove: VertexHandle,
+    keep: VertexHandle,
+    position: Vec3,
+) -> bool {
+    for &v in &[remove, keep] {
+        for f in mesh.vertex_faces(v) {
+            let vertices = mesh.face_vertices(f);
+            if vertices.contains(&remove) && vertices.contains(&keep) {
+                continue;
+            }
+
+            let p = vertices.map(|w| if w == v { position } else { mesh.position(w) });
+            let after = face_normal(p.iter().copied()).normalised();
+            match (mesh.face_normal(f), after) {
+                (Some(before), Some(after)) if before.dot(after) > 0.0 => {}
+                (None, _) => {}
+                _ => return true,
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::{Directed, Signed},
+        fixtures::ball,
+        implicit::RectangularPrism,
+        sampler::{Sample, Sampler},
+        source::ScalarSource,
+        ExtendedMarchingCubes, MarchingCubes,
+    };
+
+    fn euler_characteristic<A>(mesh: &HalfEdgeMesh<A>) -> isize {
+        let edges = mesh.half_edges().count() / 2;
+        mesh.vertex_count() as isize + mesh.face_count() as isize - edges as isize
+    }
+
+    #[test]
+    fn test_decimation() {
+        let ball = ball();
+        let sampler = Sampler::new(&ball);
+        let mut original = HalfEdgeMesh::new();
+        MarchingCubes::<Signed>::new(24)
+            .unwrap()
+            .extract(&sampler, &mut original)
+            .unwrap();
+        let target = original.face_count() / 4;
+
+        let mut mesh = original;
+        let error = Decimator::new_with_face_count(target).decimate(&mut mesh);
+        assert!(mesh.face_count() <= target);
+        assert!(mesh.boundary_loops().is_empty());
+        assert_eq!(euler_characteristic(&mesh), 2);
+        assert!(error > 0.0 && error < 0.001);
+        for v in mesh.vertices() {
+            assert!(ball.sample_scalar(mesh.position(v)).0.abs() < 0.02);
+        }
+
+        let mut projected = HalfEdgeMesh::new();
+        MarchingCubes::<Signed>::new(24)
+            .unwrap()
+            .extract(&sampler, &mut projected)
+            .unwrap();
+        Decimator::new_with_face_count(target).decimate_onto(&mut projected, &ball);
+        for v in projected.vertices() {
+            assert!(ball.sample_scalar(projected.position(v)).0.abs() < 1e-4);
+            assert!(projected.vertex(v).normal.is_some());
+        }
+
+        // The sphere is curved everywhere, so very little can be collapsed
+        // without introducing any error
+        let mut mesh = HalfEdgeMesh::new();
+        MarchingCubes::<Signed>::new(24)
+            .unwrap()
+            .extract(&sampler, &mut mesh)
+            .unwrap();
+        let faces = mesh.face_count();
+        Decimator::new_with_max_error(0.0).decimate(&mut mesh);
+        assert!(mesh.face_count() > faces * 9 / 10);
+    }
+
+    #[test]
+    fn test_preserve_features() {
+        let prism = RectangularPrism::new(Vec3::from_scalar(0.2));
+        let sampler = Sampler::new(&prism);
+
+        let mut mesh = HalfEdgeMesh::<()>::new();
+        ExtendedMarchingCubes::new(16)
+            .unwrap()
+            .extract(&sampler, &mut mesh)
+            .unwrap();
+        let features = mesh
+            .vertices()
+            .filter(|&v| mesh.vertex(v).feature)
+            .map(|v| mesh.position(v))
+            .collect::<Vec<_>>();
+        assert!(!features.is_empty());
+
+        Decimator::new_with_max_error(1e-6).decimate(&mut mesh);
+        let remaining = mesh
+            .vertices()
+            .filter(|&v| mesh.vertex(v).feature)
+            .map(|v| mesh.position(v))
+            .collect::<Vec<_>>();
+        assert_eq!(features, remaining);
+
+        // The flat faces of the prism collapse away entirely, leaving only the
+        // preserved vertices
+        for v in mesh.vertices() {
+            assert!(mesh.vertex(v).feature || mesh.is_boundary_vertex(v));
+            let d: Directed = sampler.sample(mesh.position(v));
+            assert!(d.0.abs().min_component() < 1e-4);
+        }
+    }
+}
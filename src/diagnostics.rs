@@ -0,0 +1,356 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    math::{Interval, Vec3},
+    source::{HermiteSource, ScalarSource},
+};
+
+/// A pair of neighbouring sample points between which the distance field
+/// changes faster than the Lipschitz constant allows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LipschitzViolation {
+    pub a: Vec3,
+    pub b: Vec3,
+    /// The rate of change of the field between the two points.
+    pub slope: f32,
+}
+
+/// A point at which the normal provided by a [HermiteSource] disagrees with
+/// the gradient of the distance field.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NormalMismatch {
+    pub point: Vec3,
+    /// The normalised normal provided by the source.
+    pub normal: Vec3,
+    /// The normalised gradient, estimated by central differences.
+    pub gradient: Vec3,
+}
+
+/// The result of validating a source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The number of points sampled.
+    pub samples: usize,
+    /// The range of distances sampled, ignoring any non-finite values. A
+    /// signed distance field which contains the surface should have both
+    /// negative and positive values.
+    pub range: Interval,
+    /// The largest rate of change seen between neighbouring samples.
+    pub max_slope: f32,
+    /// Points at which the distance, or the normal, was NaN or infinite.
+    pub non_finite: Vec<Vec3>,
+    /// Neighbouring points between which the field changes too quickly.
+    pub lipschitz_violations: Vec<LipschitzViolation>,
+    /// Points at which the normal points in a different direction to the
+    /// gradient (but still away from the surface).
+    pub gradient_mismatches: Vec<NormalMismatch>,
+    /// Points at which the normal points into the surface, i.e. is
+    /// inconsistent with the sign of the field.
+    pub inverted_normals: Vec<NormalMismatch>,
+}
+
+impl Report {
+    /// Test if the source passed every check.
+    pub fn is_valid(&self) -> bool {
+        self.non_finite.is_empty()
+            && self.lipschitz_violations.is_empty()
+            && self.gradient_mismatches.is_empty()
+            && self.inverted_normals.is_empty()
+    }
+
+    /// Test if the field changes sign within the sampled region. Fields which
+    /// are never negative may be unsigned distance fields.
+    pub fn contains_surface(&self) -> bool {
+        self.range.min < 0.0 && self.range.max > 0.0
+    }
+}
+
+/// Validates that sources behave like signed distance fields, by sampling them
+/// on a regular grid.
+///
+/// Extraction algorithms silently produce garbage when given a source which
+/// returns unsigned or wrongly-signed distances, or normals which don't match
+/// the field. The [Report] produced by the validator can be asserted on in
+/// tests, to catch such problems before they reach an extractor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Validator {
+    /// The number of samples taken along each axis.
+    pub resolution: usize,
+    /// The maximum permitted rate of change of the field. Euclidean distance
+    /// fields have a Lipschitz constant of 1.
+    pub lipschitz: f32,
+    /// The relative tolerance applied when checking the Lipschitz constant,
+    /// to allow for floating point error.
+    pub tolerance: f32,
+    /// The minimum cosine of the angle between the normal and the gradient.
+    pub min_normal_agreement: f32,
+    /// The step used to estimate the gradient by central differences.
+    pub epsilon: f32,
+}
+
+impl Validator {
+    /// Create a Validator which samples `resolution` points along each axis,
+    /// and expects a Euclidean distance field.
+    pub fn new(resolution: usize) -> Self {
+        Self {
+            resolution,
+            lipschitz: 1.0,
+            tolerance: 0.001,
+            min_normal_agreement: 0.9,
+            epsilon: 0.0001,
+        }
+    }
+
+    /// Validate the distances produced by a [ScalarSource] in the axis-aligned
+    /// box from `min` to `max`.
+    pub fn validate_scalar<S: ScalarSource>(&self, source: &S, min: Vec3, max: Vec3) -> Report {
+        self.validate(source, min, max, |_, _| {})
+    }
+
+    /// Validate both the distances and the normals produced by a
+    /// [HermiteSource] in the axis-aligned box from `min` to `max`.
+    pub fn validate_hermite<S: HermiteSource>(&self, source: &S, min: Vec3, max: Vec3) -> Report {
+        self.validate(source, min, max, |report, p| {
+            self.check_normal(source, report, p);
+        })
+    }
+
+    fn validate<S, F>(&self, source: &S, min: Vec3, max: Vec3, mut check_point: F) -> Report
+    where
+        S: ScalarSource,
+        F: FnMut(&mut Report, Vec3),
+    {
+        let n = self.resolution.max(2);
+        let step = (max - min) / (n - 1) as f32;
+
+        let mut report = Report {
+            samples: 0,
+            range: Interval::new(f32::MAX, f32::MIN),
+            max_slope: 0.0,
+            non_finite: vec![],
+            lipschitz_violations: vec![],
+            gradient_mismatches: vec![],
+            inverted_normals: vec![],
+        };
+
+        let point =
+            |x: usize, y: usize, z: usize| min + Vec3::new(x as f32, y as f32, z as f32) * step;
+
+        // The distances in the previous and current layers of the grid, so
+        // that each point is only sampled once
+        let mut layers = [vec![0.0; n * n], vec![0.0; n * n]];
+
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let p = point(x, y, z);
+                    let d = source.sample_scalar(p).0;
+                    layers[1][y * n + x] = d;
+                    report.samples += 1;
+
+                    if !d.is_finite() {
+                        report.non_finite.push(p);
+                        continue;
+                    }
+                    report.range = report.range.union(Interval::point(d));
+
+                    // Compare against the preceding neighbour along each axis
+                    let neighbours = [
+                        (x > 0, (x.wrapping_sub(1), y, z), 1),
+                        (y > 0, (x, y.wrapping_sub(1), z), 1),
+                        (z > 0, (x, y, z.wrapping_sub(1)), 0),
+                    ];
+                    for &(valid, (nx, ny, nz), layer) in &neighbours {
+                        if valid {
+                            let d_b = layers[layer][ny * n + nx];
+                            self.check_lipschitz(&mut report, p, d, point(nx, ny, nz), d_b);
+                        }
+                    }
+
+                    check_point(&mut report, p);
+                }
+            }
+            layers.swap(0, 1);
+        }
+
+        report
+    }
+
+    fn check_lipschitz(&self, report: &mut Report, a: Vec3, d_a: f32, b: Vec3, d_b: f32) {
+        let length = (a - b).len();
+        if !d_b.is_finite() || length == 0.0 {
+            return;
+        }
+
+        let slope = (d_a - d_b).abs() / length;
+        report.max_slope = report.max_slope.max(slope);
+
+        if slope > self.lipschitz * (1.0 + self.tolerance) {
+            report
+                .lipschitz_violations
+                .push(LipschitzViolation { a, b, slope });
+        }
+    }
+
+    fn check_normal<S: HermiteSource>(&self, source: &S, report: &mut Report, p: Vec3) {
+        let normal = source.sample_normal(p);
+        if !normal.all(f32::is_finite) {
+            report.non_finite.push(p);
+            return;
+        }
+
+        let gradient = self.finite_difference(source, p);
+
+        // Both the normal and the gradient are undefined at some points (i.e.
+        // the center of a sphere), so we can't check those
+        let (normal, gradient) = match (normal.normalised(), gradient.normalised()) {
+            (Some(n), Some(g)) => (n, g),
+            _ => return,
+        };
+
+        let agreement = normal.dot(gradient);
+        let mismatch = NormalMismatch {
+            point: p,
+            normal,
+            gradient,
+        };
+
+        if agreement < 0.0 {
+            report.inverted_normals.push(mismatch);
+        } else if agreement < self.min_normal_agreement {
+            report.gradient_mismatches.push(mismatch);
+        }
+    }
+
+    fn finite_difference<S: ScalarSource>(&self, source: &S, p: Vec3) -> Vec3 {
+        let mut gradient = Vec3::zero();
+        for i in 0..3 {
+            let mut offset = Vec3::zero();
+            offset[i] = self.epsilon;
+            gradient[i] = source.sample_scalar(p + offset).0 - source.sample_scalar(p - offset).0;
+        }
+        gradient / (2.0 * self.epsilon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{distance::Signed, implicit::Sphere};
+    use std::cell::Cell;
+
+    // A sphere which makes a selection of common mistakes
+    struct BadSphere {
+        scale: f32,
+        unsigned: bool,
+        flip_normals: bool,
+    }
+
+    impl ScalarSource for BadSphere {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            let d = (p.len() - 1.0) * self.scale;
+            Signed(if self.unsigned { d.abs() } else { d })
+        }
+    }
+
+    impl HermiteSource for BadSphere {
+        fn sample_normal(&self, p: Vec3) -> Vec3 {
+            if self.flip_normals {
+                -p
+            } else {
+                p
+            }
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let validator = Validator::new(9);
+        let min = Vec3::from_scalar(-2.0);
+        let max = Vec3::from_scalar(2.0);
+
+        let report = validator.validate_hermite(&Sphere::new(1.0), min, max);
+        assert!(report.is_valid());
+        assert!(report.contains_surface());
+        assert_eq!(report.samples, 9 * 9 * 9);
+
+        let good = BadSphere {
+            scale: 1.0,
+            unsigned: false,
+            flip_normals: false,
+        };
+        assert!(validator.validate_hermite(&good, min, max).is_valid());
+
+        let steep = BadSphere { scale: 2.0, ..good };
+        let report = validator.validate_scalar(&steep, min, max);
+        assert!(!report.lipschitz_violations.is_empty());
+        assert!((report.max_slope - 2.0).abs() < 0.01);
+
+        let unsigned = BadSphere {
+            unsigned: true,
+            ..good
+        };
+        let report = validator.validate_hermite(&unsigned, min, max);
+        assert!(!report.contains_surface());
+        assert!(!report.inverted_normals.is_empty());
+
+        let flipped = BadSphere {
+            flip_normals: true,
+            ..good
+        };
+        let report = validator.validate_hermite(&flipped, min, max);
+        assert!(report.lipschitz_violations.is_empty());
+        assert_eq!(report.inverted_normals.len(), 9 * 9 * 9 - 1);
+    }
+
+    #[test]
+    fn test_samples_once() {
+        struct Counted(Sphere, Cell<usize>);
+
+        impl ScalarSource for Counted {
+            fn sample_scalar(&self, p: Vec3) -> Signed {
+                self.1.set(self.1.get() + 1);
+                self.0.sample_scalar(p)
+            }
+        }
+
+        let source = Counted(Sphere::new(1.0), Cell::new(0));
+        let report = Validator::new(9).validate_scalar(
+            &source,
+            Vec3::from_scalar(-2.0),
+            Vec3::from_scalar(2.0),
+        );
+        assert!(report.is_valid());
+        assert_eq!(source.1.get(), 9 * 9 * 9);
+    }
+
+    #[test]
+    fn test_non_finite() {
+        struct Broken;
+
+        impl ScalarSource for Broken {
+            fn sample_scalar(&self, p: Vec3) -> Signed {
+                Signed(if p.x > 0.0 { f32::NAN } else { p.x })
+            }
+        }
+
+        let report = Validator::new(5).validate_scalar(
+            &Broken,
+            Vec3::from_scalar(-1.0),
+            Vec3::from_scalar(1.0),
+        );
+        assert_eq!(report.non_finite.len(), 2 * 5 * 5);
+        assert!(!report.is_valid());
+    }
+}
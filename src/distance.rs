@@ -15,7 +15,7 @@ use crate::math::Vec3;
 
 // Used to compute the diagonal dimension (i.e. 3-dimensional hypotenuse) of a
 // cube.
-const SQRT_OF_3: f32 = 1.732_050_807_57;
+const SQRT_OF_3: f32 = 1.732_050_8;
 
 /// A representation of distance in a specific metric space.
 pub trait Distance: Copy + Clone {
@@ -31,9 +31,20 @@ pub trait Distance: Copy + Clone {
     /// Test if the distance is within a cube of the specified amount.
     fn within_extent(&self, extent: f32) -> bool;
 
+    /// Determine if the distance is neither NaN nor infinite.
+    fn is_finite(&self) -> bool;
+
+    /// Find the fraction of the way along the line between the given grid
+    /// points at which the zero-crossing of the associated distances lies.
+    fn crossing_factor(a: Self, b: Self, p_a: Vec3, p_b: Vec3) -> f32;
+
     /// Find the point along the line between the given grid points,
     /// that lies at the zero-crossing of the associated distances.
-    fn find_crossing_point(a: Self, b: Self, p_a: Vec3, p_b: Vec3) -> Vec3;
+    fn find_crossing_point(a: Self, b: Self, p_a: Vec3, p_b: Vec3) -> Vec3 {
+        let t = Self::crossing_factor(a, b, p_a, p_b);
+
+        p_a * (1.0 - t) + p_b * t
+    }
 }
 
 /// A signed scalar distance.
@@ -61,11 +72,17 @@ impl Distance for Signed {
         self.0.abs() < extent * SQRT_OF_3
     }
 
-    fn find_crossing_point(a: Self, b: Self, p_a: Vec3, p_b: Vec3) -> Vec3 {
-        let delta = b.0 - a.0;
-        let t = if delta == 0.0 { 0.5 } else { -a.0 / delta };
+    fn is_finite(&self) -> bool {
+        self.0.is_finite()
+    }
 
-        p_a * (1.0 - t) + p_b * t
+    fn crossing_factor(a: Self, b: Self, _: Vec3, _: Vec3) -> f32 {
+        let delta = b.0 - a.0;
+        if delta == 0.0 {
+            0.5
+        } else {
+            -a.0 / delta
+        }
     }
 }
 
@@ -87,18 +104,20 @@ impl Distance for Directed {
         self.0.abs().any(|f| f < extent * SQRT_OF_3)
     }
 
-    fn find_crossing_point(a: Self, b: Self, p_a: Vec3, p_b: Vec3) -> Vec3 {
+    fn is_finite(&self) -> bool {
+        self.0.all(f32::is_finite)
+    }
+
+    fn crossing_factor(a: Self, b: Self, p_a: Vec3, p_b: Vec3) -> f32 {
         // Since we're working on a grid, we only care about distance along the dominant
         // axis
         let axis = (p_a - p_b).abs().max_component_index();
 
         let delta = b.0[axis] - a.0[axis];
-        let t = if delta == 0.0 {
+        if delta == 0.0 {
             0.5
         } else {
             -a.0[axis] / delta
-        };
-
-        p_a * (1.0 - t) + p_b * t
+        }
     }
 }
@@ -14,17 +14,20 @@
 
 use crate::{
     distance::Signed,
-    extractor::Extractor,
+    error::Error,
+    extractor::VertexExtractor,
     feature::PlaceFeatureInCell,
     index_cache::GridKey,
     marching_cubes_impl::{
-        classify_corners, find_edge_crossings, march_cube, sample_normals_at_corners,
+        classify_corners, edge_crossing_vertex, find_edge_crossings, march_cube,
+        refine_edge_crossings, sample_normals_at_corners,
     },
     math::Vec3,
     mesh::MeshTopologyBuilder,
-    sampler::Sample,
+    sampler::{Refinement, Sample},
     source::HermiteSource,
     traversal::DualGrid,
+    workspace::Workspace,
 };
 
 #[cfg(doc)]
@@ -39,20 +42,40 @@ use crate::feature::{MinimiseQEF, ParticleBasedMinimisation};
 ///
 /// Cons:
 /// * Feature placement can be very sensitive to the quality of input data.
+/// * Samples the normal at each grid point once for every cell around it. Wrap
+///   expensive sources in a [CachedSource](crate::source::CachedSource) to
+///   share the samples.
 pub struct DualContouring<P: PlaceFeatureInCell> {
     dual_grid: DualGrid<Signed>,
     place_feature: P,
+    refinement: Option<Refinement>,
 }
 
 impl<P: PlaceFeatureInCell> DualContouring<P> {
     /// Create a new DualContouring with the given chunk size.
     ///
     /// For a given `size`, this will evaluate chunks of `size^3` voxels.
-    pub fn new(size: usize, place_feature: P) -> Self {
-        Self {
-            dual_grid: DualGrid::new(size),
+    pub fn new(size: usize, place_feature: P) -> Result<Self, Error> {
+        Ok(Self {
+            dual_grid: DualGrid::new(size)?,
             place_feature,
-        }
+            refinement: None,
+        })
+    }
+
+    /// Create a new DualContouring with the given chunk size, which refines
+    /// the position of each vertex by searching along the edge between
+    /// neighbouring feature points (see [Refinement]).
+    pub fn new_with_refinement(
+        size: usize,
+        place_feature: P,
+        refinement: Refinement,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            dual_grid: DualGrid::new(size)?,
+            place_feature,
+            refinement: Some(refinement),
+        })
     }
 
     /// Extracts a mesh from the given [Sample].
@@ -62,45 +85,82 @@ impl<P: PlaceFeatureInCell> DualContouring<P> {
     ///
     /// The resulting vertex and face data will be returned via the provided
     /// Extractor.
-    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E)
+    ///
+    /// Scratch memory is allocated afresh for each call. To reuse it between
+    /// calls, use [extract_with](DualContouring::extract_with).
+    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
     where
         S: Sample<Signed> + HermiteSource,
-        E: Extractor,
+        E: VertexExtractor,
     {
-        let mut mesh_builder = MeshTopologyBuilder::new(extractor);
+        self.extract_with(&mut Workspace::new(), source, extractor)
+    }
+
+    /// Extracts a mesh from the given [Sample], as per
+    /// [extract](DualContouring::extract), using the scratch memory in the
+    /// given [Workspace].
+    ///
+    /// Once the workspace has warmed up, repeatedly extracting similar
+    /// surfaces doesn't allocate.
+    pub fn extract_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
+    where
+        S: Sample<Signed> + HermiteSource,
+        E: VertexExtractor,
+    {
+        workspace.edges.reset(self.dual_grid.size());
+        let mut mesh_builder =
+            MeshTopologyBuilder::new(&mut workspace.edges, &mut workspace.mesh, extractor);
         let mut normals = [Vec3::zero(); 8];
 
         let dual_grid = &mut self.dual_grid;
         let place_feature = &mut self.place_feature;
+        let refinement = self.refinement;
 
         dual_grid.traverse(
             source,
             Some(|corners: &[Vec3; 8], values: &[Signed; 8]| {
-                let cube_index = classify_corners(&values);
+                let cube_index = classify_corners(values);
                 if cube_index == 0 || cube_index == 255 {
                     return None;
                 }
 
-                sample_normals_at_corners(source, &corners, &mut normals);
+                sample_normals_at_corners(source, corners, &mut normals);
 
                 Some(place_feature.place_feature_in_cell(corners, &normals))
             }),
             |keys, corners, values| {
-                let cube_index = classify_corners(&values);
+                let cube_index = classify_corners(values);
 
                 let mut vertices = [Vec3::zero(); 12];
-                find_edge_crossings(cube_index, &corners, &values, &mut vertices);
+                find_edge_crossings(cube_index, corners, values, &mut vertices);
+                if let Some(refinement) = refinement {
+                    refine_edge_crossings(
+                        cube_index,
+                        corners,
+                        values,
+                        source,
+                        refinement,
+                        &mut vertices,
+                    );
+                }
 
                 march_cube(cube_index, |a, b, c| {
-                    let a = mesh_builder.add_vertex(Some(GridKey::new(keys, a)), vertices[a]);
-                    let b = mesh_builder.add_vertex(Some(GridKey::new(keys, b)), vertices[b]);
-                    let c = mesh_builder.add_vertex(Some(GridKey::new(keys, c)), vertices[c]);
+                    let [a, b, c] = [a, b, c].map(|edge| {
+                        let vertex = edge_crossing_vertex(edge, corners, &vertices);
+                        mesh_builder.add_vertex(Some(GridKey::new(keys, edge)), vertex)
+                    });
 
                     mesh_builder.add_face(a, b, c);
                 });
             },
-        );
+        )?;
 
-        mesh_builder.build().extract_indices(extractor);
+        mesh_builder.build()?.extract_indices(extractor);
+        Ok(())
     }
 }
@@ -0,0 +1,149 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::math::Vec3;
+use std::fmt;
+
+/// The ways in which constructing an extractor, or extracting a mesh, can fail.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The grid size is too small to contain any cells.
+    InvalidSize { size: usize, minimum: usize },
+    /// The octree depth is too large to be represented by a Morton code.
+    ExcessiveDepth { depth: usize, maximum: usize },
+    /// The source produced a NaN or infinite distance at the given point.
+    NonFiniteSample { point: Vec3 },
+    /// The mesh has more vertices than the extractor is able to index.
+    IndexOverflow { index: usize, maximum: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidSize { size, minimum } => {
+                write!(
+                    f,
+                    "grid size {} is less than the minimum of {}",
+                    size, minimum
+                )
+            }
+            Error::ExcessiveDepth { depth, maximum } => write!(
+                f,
+                "octree depth {} exceeds the maximum of {}",
+                depth, maximum
+            ),
+            Error::NonFiniteSample { point } => write!(
+                f,
+                "non-finite distance sampled at ({}, {}, {})",
+                point.x, point.y, point.z
+            ),
+            Error::IndexOverflow { index, maximum } => write!(
+                f,
+                "vertex index {} exceeds the maximum of {}",
+                index, maximum
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed,
+        extractor::{Extractor, IndexedVertices},
+        feature::MinimiseQEF,
+        implicit::Torus,
+        mesh::{Edge, MeshTopology},
+        sampler::Sampler,
+        source::ScalarSource,
+        DualContouring, LinearHashedMarchingCubes, MarchingCubes,
+    };
+
+    struct Broken;
+
+    impl ScalarSource for Broken {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            Signed(if p.z > 0.5 { f32::NAN } else { p.z - 0.25 })
+        }
+    }
+
+    // Can only index a handful of vertices
+    struct Tiny(usize);
+
+    impl Extractor for Tiny {
+        fn extract_vertex(&mut self, _: Vec3) {
+            self.0 += 1;
+        }
+
+        fn extract_index(&mut self, _: usize) {}
+
+        fn max_index(&self) -> usize {
+            9
+        }
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert_eq!(
+            DualContouring::new(0, MinimiseQEF {}).err(),
+            Some(Error::InvalidSize {
+                size: 0,
+                minimum: 2
+            })
+        );
+        assert!(MarchingCubes::<Signed>::new(1).is_err());
+        assert!(LinearHashedMarchingCubes::new(20).is_ok());
+        assert_eq!(
+            LinearHashedMarchingCubes::new(21).err(),
+            Some(Error::ExcessiveDepth {
+                depth: 21,
+                maximum: 20
+            })
+        );
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut extractor = IndexedVertices::new(&mut vertices, &mut indices);
+        let mut marching_cubes = MarchingCubes::<Signed>::new(16).unwrap();
+        match marching_cubes.extract(&Sampler::new(&Broken), &mut extractor) {
+            Err(Error::NonFiniteSample { point }) => assert!(point.z > 0.5),
+            result => panic!("unexpected result {:?}", result),
+        }
+        let mut octree = LinearHashedMarchingCubes::new(4).unwrap();
+        assert!(octree
+            .extract(&Sampler::new(&Broken), &mut extractor)
+            .is_err());
+
+        let torus = Torus::new(0.25, 0.1);
+        let mut tiny = Tiny(0);
+        assert_eq!(
+            marching_cubes.extract(&Sampler::new(&torus), &mut tiny),
+            Err(Error::IndexOverflow {
+                index: 10,
+                maximum: 9
+            })
+        );
+        assert_eq!(tiny.0, 10);
+
+        // Degenerate faces are left alone, rather than panicking
+        let mut mesh = MeshTopology::new();
+        let a = mesh.add_vertex();
+        let b = mesh.add_vertex();
+        mesh.add_face(a, b, b);
+        mesh.rotate_edge(Edge::new(a, b));
+        assert_eq!(mesh.adjoining_faces(Edge::new(a, b)).count(), 2);
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    buffer::IndexType,
+    math::Vec3,
+    normals::face_normal,
+    source::{HermiteSource, ScalarSource},
+};
+
+/// Summary statistics for an error measured at points across a mesh.
+///
+/// The mean and RMS are weighted by the area each sample represents, so they
+/// don't depend on how finely the mesh is triangulated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    /// The number of points sampled.
+    pub samples: usize,
+    pub mean: f32,
+    /// The root mean square error.
+    pub rms: f32,
+    /// The largest error seen. For distances, this is the one-sided
+    /// Hausdorff distance from the mesh to the surface.
+    pub max: f32,
+    /// The number of samples in each of a series of equal-width bins,
+    /// spanning from zero to the largest error seen.
+    pub histogram: Vec<usize>,
+    /// The width of each bin in the histogram.
+    pub bin_width: f32,
+}
+
+impl Statistics {
+    fn new(errors: &[(f32, f32)], bins: usize) -> Self {
+        let max = errors.iter().map(|&(e, _)| e).fold(0.0, f32::max);
+        let (mut sum, mut sum_sq, mut total) = (0.0f64, 0.0f64, 0.0f64);
+        let mut histogram = vec![0; bins.max(1)];
+        let bin_width = max / histogram.len() as f32;
+
+        for &(error, weight) in errors {
+            let (error, weight) = (error as f64, weight as f64);
+            sum += error * weight;
+            sum_sq += error * error * weight;
+            total += weight;
+            let bin = if bin_width > 0.0 {
+                (error as f32 / bin_width) as usize
+            } else {
+                0
+            };
+            histogram[bin.min(bins.max(1) - 1)] += 1;
+        }
+
+        let (mean, rms) = if total > 0.0 {
+            ((sum / total) as f32, (sum_sq / total).sqrt() as f32)
+        } else {
+            (0.0, 0.0)
+        };
+        Self {
+            samples: errors.len(),
+            mean,
+            rms,
+            max,
+            histogram,
+            bin_width,
+        }
+    }
+}
+
+/// Measures how closely an extracted mesh follows the surface of its source,
+/// for comparing extraction algorithms and resolutions.
+///
+/// Points are sampled on a regular pattern across each face of the mesh: the
+/// corners, and evenly spaced points along the edges and interior. Each point
+/// is weighted by the area of the face around it, so points on the edges and
+/// corners, which are also sampled by neighbouring faces, aren't counted twice.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Evaluator {
+    /// The number of segments each edge of a face is divided into when
+    /// placing samples. Each face is sampled at `(n + 1) * (n + 2) / 2` points.
+    pub subdivisions: usize,
+    /// The number of bins in each histogram.
+    pub bins: usize,
+    /// The step used to estimate the gradient of the field by central
+    /// differences, when measuring distances.
+    pub epsilon: f32,
+}
+
+impl Evaluator {
+    /// Create an Evaluator which divides each edge into 4 segments, and
+    /// produces histograms with 16 bins.
+    pub fn new() -> Self {
+        Self {
+            subdivisions: 4,
+            bins: 16,
+            epsilon: 0.0001,
+        }
+    }
+
+    /// Measure the distance from points on the mesh to the surface of the
+    /// source.
+    ///
+    /// The distance at each point is estimated as the sampled distance divided
+    /// by the magnitude of the gradient, which is exact for Euclidean distance
+    /// fields, and a close approximation near the surface of other fields.
+    pub fn evaluate_distance<S, I>(
+        &self,
+        source: &S,
+        positions: &[Vec3],
+        indices: &[I],
+    ) -> Statistics
+    where
+        S: ScalarSource,
+        I: IndexType,
+    {
+        let errors = self.sample(positions, indices, |p, _| {
+            let d = source.sample_scalar(p).0;
+            let gradient = self.gradient(source, p).len();
+            if gradient > 0.0 {
+                d.abs() / gradient
+            } else {
+                d.abs()
+            }
+        });
+        Statistics::new(&errors, self.bins)
+    }
+
+    /// Measure the angle, in degrees, between the normal of the mesh and the
+    /// normal of the source at points on the mesh.
+    pub fn evaluate_normals<S, I>(
+        &self,
+        source: &S,
+        positions: &[Vec3],
+        indices: &[I],
+    ) -> Statistics
+    where
+        S: HermiteSource,
+        I: IndexType,
+    {
+        let errors = self.sample(positions, indices, |p, normal| {
+            match source.sample_normal(p).normalised() {
+                Some(n) => n.dot(normal).clamp(-1.0, 1.0).acos().to_degrees(),
+                None => 0.0,
+            }
+        });
+        Statistics::new(&errors, self.bins)
+    }
+
+    // Measure the error at each sample point, given the point and the normal
+    // of its face, along with the area the sample represents
+    fn sample<I, F>(&self, positions: &[Vec3], indices: &[I], mut error: F) -> Vec<(f32, f32)>
+    where
+        I: IndexType,
+        F: FnMut(Vec3, Vec3) -> f32,
+    {
+        let n = self.subdivisions.max(1);
+        let per_face = (n + 1) * (n + 2) / 2;
+        let mut errors = Vec::with_capacity(indices.len() / 3 * per_face);
+
+        for face in indices.chunks_exact(3) {
+            let p = [0, 1, 2].map(|i| positions[face[i].to_index()]);
+            let normal = face_normal(p.iter().copied());
+            // The face is divided into n^2 triangles, and each point takes a
+            // third of the area of every triangle it is a corner of
+            let share = normal.len() * 0.5 / (n * n * 3) as f32;
+            let normal = match normal.normalised() {
+                Some(normal) => normal,
+                None => continue,
+            };
+
+            for i in 0..=n {
+                for j in 0..=(n - i) {
+                    let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+                    let point = p[0] + (p[1] - p[0]) * u + (p[2] - p[0]) * v;
+                    let sides = [i == 0, j == 0, i + j == n];
+                    let triangles = match sides.iter().filter(|&&side| side).count() {
+                        0 => 6,
+                        1 => 3,
+                        _ => 1,
+                    };
+                    errors.push((error(point, normal), share * triangles as f32));
+                }
+            }
+        }
+        errors
+    }
+
+    fn gradient<S: ScalarSource>(&self, source: &S, p: Vec3) -> Vec3 {
+        let difference = |axis: Vec3| {
+            let offset = axis * self.epsilon;
+            source.sample_scalar(p + offset).0 - source.sample_scalar(p - offset).0
+        };
+        Vec3::new(
+            difference(Vec3::new(1.0, 0.0, 0.0)),
+            difference(Vec3::new(0.0, 1.0, 0.0)),
+            difference(Vec3::new(0.0, 0.0, 1.0)),
+        ) / (2.0 * self.epsilon)
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ball, extract_mesh};
+
+    #[test]
+    fn test_evaluation() {
+        let ball = ball();
+        let evaluator = Evaluator::new();
+        let results = [8, 32]
+            .iter()
+            .map(|&size| {
+                let (positions, indices) = extract_mesh(&ball, size);
+                let distance = evaluator.evaluate_distance(&ball, &positions, &indices);
+                let normals = evaluator.evaluate_normals(&ball, &positions, &indices);
+
+                assert_eq!(distance.samples, indices.len() / 3 * 15);
+                assert_eq!(distance.histogram.iter().sum::<usize>(), distance.samples);
+                assert!(distance.mean <= distance.rms && distance.rms <= distance.max);
+                // No point on the mesh is further than a cell from the surface
+                assert!(distance.max < 1.0 / (size - 1) as f32);
+                (distance, normals)
+            })
+            .collect::<Vec<_>>();
+
+        let (coarse, fine) = (&results[0], &results[1]);
+        assert!(fine.0.rms < coarse.0.rms / 4.0);
+        assert!(fine.0.max < coarse.0.max);
+        assert!(fine.1.rms < coarse.1.rms);
+        assert!(fine.1.max < 10.0);
+    }
+
+    #[test]
+    fn test_sample_weights() {
+        let positions = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let evaluator = Evaluator::new();
+        let errors = evaluator.sample(&positions, &[0u16, 1, 2], |p, _| p.x * p.x);
+
+        // The weights cover the face exactly once
+        let area = errors.iter().map(|&(_, weight)| weight).sum::<f32>();
+        assert!((area - 0.5).abs() < 1e-6);
+
+        // The mean of x^2 over the face is 1/6. Weighting every point equally
+        // would overstate the corners and edges, giving 5/24
+        let mean = Statistics::new(&errors, 1).mean;
+        assert!((mean - 1.0 / 6.0).abs() < 0.015);
+    }
+}
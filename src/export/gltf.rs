@@ -0,0 +1,436 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use super::{ExportAttribute, MeshBuffer};
+use crate::{
+    extractor::{Vertex, VertexExtractor},
+    math::Vec3,
+};
+use std::io::{self, Write};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Writes an extracted mesh to a binary glTF 2.0 (`.glb`) file, containing a
+/// single mesh in a single scene.
+///
+/// Positions and indices are always written. Normals are written as the
+/// `NORMAL` attribute if every vertex has one, and user attributes of up to 4
+/// components as the custom `_ATTRIBUTE` attribute. Since glTF attributes have
+/// at most 4 components, larger user attributes (e.g.
+/// [TextureFrame](crate::texturing::TextureFrame)) are split in order across
+/// `_ATTRIBUTE_0`, `_ATTRIBUTE_1`, and so on, each with 4 components except
+/// the last. The file starts with the size of every buffer, so the mesh is
+/// buffered and written by [finish](GlbWriter::finish).
+pub struct GlbWriter<W: Write> {
+    writer: W,
+    buffer: MeshBuffer,
+}
+
+// A buffer view and its accessor, which are written one-to-one
+struct Accessor {
+    offset: usize,
+    length: usize,
+    count: usize,
+    kind: &'static str,
+    component_type: u32,
+    target: u32,
+    bounds: Option<(Vec3, Vec3)>,
+}
+
+impl<W: Write> GlbWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: MeshBuffer::new(),
+        }
+    }
+
+    /// Write the buffered mesh, and return the underlying writer.
+    ///
+    /// glTF doesn't allow empty buffers, so if no faces were extracted the
+    /// file contains an empty scene, without any mesh.
+    pub fn finish(mut self) -> io::Result<W> {
+        let (json, mut bin) = if self.buffer.indices.is_empty() {
+            (EMPTY_SCENE.to_string(), vec![])
+        } else {
+            encode(&self.buffer)
+        };
+
+        // Chunks must be 4-byte aligned, padded with spaces in the JSON chunk
+        // and zeroes in the binary chunk
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+        let mut chunks = vec![(CHUNK_JSON, &json)];
+        if !bin.is_empty() {
+            chunks.push((CHUNK_BIN, &bin));
+        }
+
+        let w = &mut self.writer;
+        let length = 12 + chunks.iter().map(|(_, c)| 8 + c.len()).sum::<usize>();
+        for word in &[GLB_MAGIC, GLB_VERSION, length as u32] {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        for (kind, chunk) in &chunks {
+            w.write_all(&(chunk.len() as u32).to_le_bytes())?;
+            w.write_all(&kind.to_le_bytes())?;
+            w.write_all(chunk)?;
+        }
+
+        w.flush()?;
+        Ok(self.writer)
+    }
+}
+
+const EMPTY_SCENE: &str = concat!(
+    "{\"asset\":{\"version\":\"2.0\",\"generator\":\"isosurface\"},",
+    "\"scene\":0,\"scenes\":[{}]}"
+);
+
+// The JSON and binary chunks for a mesh with at least one face
+fn encode(mesh: &MeshBuffer) -> (String, Vec<u8>) {
+    let mut bin = vec![];
+    let mut accessors = vec![];
+    let mut attributes = vec![];
+
+    let mut add = |name: Option<&str>, accessor: Accessor, data: Vec<u8>| {
+        if let Some(name) = name {
+            attributes.push(format!("\"{}\":{}", name, accessors.len()));
+        }
+        accessors.push(Accessor {
+            offset: bin.len(),
+            length: data.len(),
+            ..accessor
+        });
+        bin.extend(data);
+    };
+    let vertex_data = |count, kind, bounds| Accessor {
+        offset: 0,
+        length: 0,
+        count,
+        kind,
+        component_type: FLOAT,
+        target: ARRAY_BUFFER,
+        bounds,
+    };
+    let vertices = mesh.positions.len();
+
+    let bounds = mesh.positions.iter().fold(None, |bounds, &p| match bounds {
+        None => Some((p, p)),
+        Some((min, max)) => Some((p.min(min), p.max(max))),
+    });
+    add(
+        Some("POSITION"),
+        vertex_data(vertices, "VEC3", bounds),
+        vector_bytes(&mesh.positions),
+    );
+    if let Some(normals) = mesh.normals() {
+        add(
+            Some("NORMAL"),
+            vertex_data(vertices, "VEC3", None),
+            vector_bytes(normals),
+        );
+    }
+    let parts = mesh.components.div_ceil(4);
+    for part in 0..parts {
+        let start = part * 4;
+        let width = (mesh.components - start).min(4);
+        let kind = ["SCALAR", "VEC2", "VEC3", "VEC4"][width - 1];
+        let name = match parts {
+            1 => "_ATTRIBUTE".to_string(),
+            _ => format!("_ATTRIBUTE_{}", part),
+        };
+        add(
+            Some(&name),
+            vertex_data(vertices, kind, None),
+            mesh.attributes
+                .chunks(mesh.components)
+                .flat_map(|a| &a[start..start + width])
+                .flat_map(|a| a.to_le_bytes())
+                .collect(),
+        );
+    }
+    add(
+        None,
+        Accessor {
+            offset: 0,
+            length: 0,
+            count: mesh.indices.len(),
+            kind: "SCALAR",
+            component_type: UNSIGNED_INT,
+            target: ELEMENT_ARRAY_BUFFER,
+            bounds: None,
+        },
+        mesh.indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+    );
+
+    let views = accessors
+        .iter()
+        .map(|a| {
+            format!(
+                "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}",
+                a.offset, a.length, a.target
+            )
+        })
+        .collect::<Vec<_>>();
+    let accessor_json = accessors
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            let bounds = match a.bounds {
+                Some((min, max)) => format!(
+                    ",\"min\":[{},{},{}],\"max\":[{},{},{}]",
+                    min.x, min.y, min.z, max.x, max.y, max.z
+                ),
+                None => String::new(),
+            };
+            format!(
+                "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"{}\"{}}}",
+                i, a.component_type, a.count, a.kind, bounds
+            )
+        })
+        .collect::<Vec<_>>();
+    let json = format!(
+        concat!(
+            "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"isosurface\"}},",
+            "\"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],",
+            "\"meshes\":[{{\"primitives\":[{{\"attributes\":{{{}}},\"indices\":{}}}]}}],",
+            "\"buffers\":[{{\"byteLength\":{}}}],",
+            "\"bufferViews\":[{}],\"accessors\":[{}]}}"
+        ),
+        attributes.join(","),
+        accessors.len() - 1,
+        bin.len(),
+        views.join(","),
+        accessor_json.join(",")
+    );
+
+    (json, bin)
+}
+
+// The little-endian bytes of each component of each vector
+fn vector_bytes(vectors: &[Vec3]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(vectors.len() * 12);
+    for v in vectors {
+        for component in &[v.x, v.y, v.z] {
+            bytes.extend_from_slice(&component.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+impl<W: Write, A: ExportAttribute> VertexExtractor<A> for GlbWriter<W> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        self.buffer.add_vertex(&vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.buffer.add_index(index);
+    }
+
+    fn max_index(&self) -> usize {
+        u32::MAX as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        export::tests::{assert_outward, expected, extract_ball, test_mesh},
+        extractor::VertexOrigin,
+        math::Vec2,
+        texturing::TextureFrame,
+    };
+
+    // Every number following the given key in the JSON chunk, in order
+    fn numbers(json: &str, key: &str) -> Vec<usize> {
+        json.match_indices(&format!("\"{}\":", key))
+            .map(|(i, m)| {
+                let rest = &json[i + m.len()..];
+                let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap();
+                rest[..end].parse().unwrap()
+            })
+            .collect()
+    }
+
+    // Read back the contents of each buffer view, as the JSON chunk and the
+    // raw little-endian words of each view
+    fn read_glb(bytes: &[u8]) -> (String, Vec<Vec<[u8; 4]>>) {
+        let word = |offset: usize| {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[offset..offset + 4]);
+            word
+        };
+        let u32_at = |offset: usize| u32::from_le_bytes(word(offset)) as usize;
+
+        assert_eq!(u32_at(0), GLB_MAGIC as usize);
+        assert_eq!(u32_at(4), GLB_VERSION as usize);
+        assert_eq!(u32_at(8), bytes.len());
+
+        let json_length = u32_at(12);
+        assert_eq!(u32_at(16), CHUNK_JSON as usize);
+        let json = std::str::from_utf8(&bytes[20..20 + json_length]).unwrap();
+        let bin = 20 + json_length;
+        assert_eq!(u32_at(bin + 4), CHUNK_BIN as usize);
+        assert_eq!(bin + 8 + u32_at(bin), bytes.len());
+
+        let offsets = numbers(json, "byteOffset");
+        let lengths = numbers(json, "byteLength");
+        // The first byteLength belongs to the buffer itself
+        let views = offsets
+            .iter()
+            .zip(&lengths[1..])
+            .map(|(&offset, &length)| {
+                (0..length / 4)
+                    .map(|i| word(bin + 8 + offset + i * 4))
+                    .collect()
+            })
+            .collect();
+        (json.to_string(), views)
+    }
+
+    #[test]
+    fn test_glb() {
+        let mesh = test_mesh();
+        let expected = expected(&mesh);
+
+        let mut writer = GlbWriter::new(vec![]);
+        mesh.extract(&mut writer).unwrap();
+        let (json, views) = read_glb(&writer.finish().unwrap());
+        assert!(json.contains("\"POSITION\":0,\"NORMAL\":1,\"_ATTRIBUTE\":2"));
+        assert_eq!(views.len(), 4);
+
+        let floats = |view: &Vec<[u8; 4]>| {
+            view.iter()
+                .map(|&w| f32::from_le_bytes(w))
+                .collect::<Vec<_>>()
+        };
+        let vectors = |vectors: &[Vec3]| {
+            vectors
+                .iter()
+                .flat_map(|v| [v.x, v.y, v.z])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(floats(&views[0]), vectors(&expected.positions));
+        assert_eq!(floats(&views[1]), vectors(&expected.normals));
+        assert_eq!(floats(&views[2]), expected.attributes);
+        let indices = views[3]
+            .iter()
+            .map(|&w| u32::from_le_bytes(w))
+            .collect::<Vec<_>>();
+        assert_eq!(indices, expected.indices);
+        assert_eq!(
+            numbers(&json, "count"),
+            vec![expected.positions.len(); 3]
+                .into_iter()
+                .chain(Some(expected.indices.len()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_glb_large_attributes() {
+        let frame = |i: usize| TextureFrame {
+            blend_weights: Vec3::new(1.0, 2.0, 3.0) * i as f32,
+            uv: Vec2::new(4.0, 5.0) * i as f32,
+            tangent: Vec3::new(6.0, 7.0, 8.0) * i as f32,
+            bitangent_sign: 9.0 * i as f32,
+        };
+        let mut writer = GlbWriter::new(vec![]);
+        for i in 0..3 {
+            let p = Vec3::new(i as f32, 0.0, 0.0);
+            let origin = VertexOrigin::Cell { min: p, max: p };
+            writer.extract_vertex(Vertex::new(p, origin).with_attributes(frame(i)));
+        }
+        for i in 0..3 {
+            VertexExtractor::<TextureFrame>::extract_index(&mut writer, i);
+        }
+        let (json, views) = read_glb(&writer.finish().unwrap());
+
+        // The 9 components are split into 4, 4 and 1
+        assert!(json
+            .contains("\"POSITION\":0,\"_ATTRIBUTE_0\":1,\"_ATTRIBUTE_1\":2,\"_ATTRIBUTE_2\":3"));
+        let kinds = json
+            .match_indices("\"type\":\"")
+            .map(|(i, m)| {
+                let rest = &json[i + m.len()..];
+                &rest[..rest.find('"').unwrap()]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec!["VEC3", "VEC4", "VEC4", "SCALAR", "SCALAR"]);
+
+        let mut attributes = vec![];
+        for i in 0..3 {
+            for view in &views[1..4] {
+                let width = view.len() / 3;
+                let words = &view[i * width..(i + 1) * width];
+                attributes.extend(words.iter().map(|&w| f32::from_le_bytes(w)));
+            }
+        }
+        let mut expected = vec![];
+        for i in 0..3 {
+            frame(i).write_components(&mut expected);
+        }
+        assert_eq!(attributes, expected);
+    }
+
+    #[test]
+    fn test_empty_glb() {
+        let bytes = GlbWriter::new(vec![]).finish().unwrap();
+        let u32_at = |offset: usize| {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[offset..offset + 4]);
+            u32::from_le_bytes(word) as usize
+        };
+
+        // Only a JSON chunk, describing a scene without any mesh or buffer
+        assert_eq!(u32_at(8), bytes.len());
+        assert_eq!(u32_at(16), CHUNK_JSON as usize);
+        assert_eq!(20 + u32_at(12), bytes.len());
+        let json = std::str::from_utf8(&bytes[20..]).unwrap();
+        assert_eq!(json.trim_end(), EMPTY_SCENE);
+    }
+
+    #[test]
+    fn test_glb_winding() {
+        let mut writer = GlbWriter::new(vec![]);
+        extract_ball(&mut writer);
+        let (_, views) = read_glb(&writer.finish().unwrap());
+
+        let positions = views[0]
+            .chunks(3)
+            .map(|v| {
+                let [x, y, z] = [0, 1, 2].map(|i| f32::from_le_bytes(v[i]));
+                Vec3::new(x, y, z)
+            })
+            .collect::<Vec<_>>();
+        let indices = views[views.len() - 1]
+            .iter()
+            .map(|&w| u32::from_le_bytes(w))
+            .collect::<Vec<_>>();
+        assert_outward(&positions, &indices);
+    }
+}
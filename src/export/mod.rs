@@ -0,0 +1,236 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#[cfg(feature = "gltf")]
+mod gltf;
+#[cfg(feature = "obj")]
+mod obj;
+#[cfg(feature = "ply")]
+mod ply;
+#[cfg(feature = "stl")]
+mod stl;
+
+#[cfg(feature = "gltf")]
+pub use self::gltf::*;
+#[cfg(feature = "obj")]
+pub use self::obj::*;
+#[cfg(feature = "ply")]
+pub use self::ply::*;
+#[cfg(feature = "stl")]
+pub use self::stl::*;
+
+use crate::math::{Vec2, Vec3};
+
+#[cfg(any(feature = "gltf", feature = "ply", feature = "stl"))]
+use crate::extractor::Vertex;
+
+/// Vertex attributes which can be written to file formats that support
+/// custom per-vertex data.
+pub trait ExportAttribute {
+    /// The number of floats written for each vertex.
+    const COMPONENTS: usize;
+
+    /// Append the components of the attribute to `components`.
+    fn write_components(&self, components: &mut Vec<f32>);
+}
+
+impl ExportAttribute for () {
+    const COMPONENTS: usize = 0;
+
+    fn write_components(&self, _: &mut Vec<f32>) {}
+}
+
+impl ExportAttribute for f32 {
+    const COMPONENTS: usize = 1;
+
+    fn write_components(&self, components: &mut Vec<f32>) {
+        components.push(*self);
+    }
+}
+
+impl ExportAttribute for Vec2 {
+    const COMPONENTS: usize = 2;
+
+    fn write_components(&self, components: &mut Vec<f32>) {
+        components.extend_from_slice(&[self.x, self.y]);
+    }
+}
+
+impl ExportAttribute for Vec3 {
+    const COMPONENTS: usize = 3;
+
+    fn write_components(&self, components: &mut Vec<f32>) {
+        components.extend_from_slice(&[self.x, self.y, self.z]);
+    }
+}
+
+impl<const N: usize> ExportAttribute for [f32; N] {
+    const COMPONENTS: usize = N;
+
+    fn write_components(&self, components: &mut Vec<f32>) {
+        components.extend_from_slice(self);
+    }
+}
+
+// Formats with a header describing the size of the mesh can't be written until
+// the whole mesh has been extracted, so buffer it
+#[cfg(any(feature = "gltf", feature = "ply", feature = "stl"))]
+pub(crate) struct MeshBuffer {
+    pub positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    missing_normals: bool,
+    pub attributes: Vec<f32>,
+    pub components: usize,
+    pub indices: Vec<u32>,
+}
+
+#[cfg(any(feature = "gltf", feature = "ply", feature = "stl"))]
+impl MeshBuffer {
+    pub fn new() -> Self {
+        Self {
+            positions: vec![],
+            normals: vec![],
+            missing_normals: false,
+            attributes: vec![],
+            components: 0,
+            indices: vec![],
+        }
+    }
+
+    pub fn add_vertex<A: ExportAttribute>(&mut self, vertex: &Vertex<A>) {
+        self.positions.push(vertex.position);
+        match vertex.normal {
+            Some(normal) => self.normals.push(normal),
+            None => self.missing_normals = true,
+        }
+        self.components = A::COMPONENTS;
+        vertex.attributes.write_components(&mut self.attributes);
+    }
+
+    // Extracted faces wind clockwise when viewed from outside, while file
+    // formats expect front faces to wind counter-clockwise, so the last two
+    // vertices of each face are swapped
+    pub fn add_index(&mut self, index: usize) {
+        self.indices.push(index as u32);
+        let len = self.indices.len();
+        if len.is_multiple_of(3) {
+            self.indices.swap(len - 2, len - 1);
+        }
+    }
+
+    /// The vertex normals, if every vertex has one.
+    pub fn normals(&self) -> Option<&[Vec3]> {
+        if self.missing_normals || self.positions.is_empty() {
+            None
+        } else {
+            Some(&self.normals)
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "gltf", feature = "obj", feature = "ply", feature = "stl")
+))]
+pub(crate) mod tests {
+    use crate::{
+        distance::Signed,
+        extractor::{Vertex, VertexExtractor, WithAttributes},
+        fixtures::ball,
+        half_edge::HalfEdgeMesh,
+        implicit::Sphere,
+        math::Vec3,
+        sampler::Sampler,
+        source::AttributeSource,
+        MarchingCubes,
+    };
+
+    struct Height;
+
+    impl AttributeSource<f32> for Height {
+        fn sample_attribute(&self, p: Vec3) -> f32 {
+            p.y
+        }
+    }
+
+    /// A small mesh with normals and a scalar attribute on every vertex.
+    pub fn test_mesh() -> HalfEdgeMesh<f32> {
+        let sphere = Sphere::new(0.6);
+        let sampler = Sampler::new(&sphere);
+
+        let mut mesh = HalfEdgeMesh::new();
+        MarchingCubes::<Signed>::new(6)
+            .unwrap()
+            .extract(&sampler, &mut WithAttributes::new(&Height, &mut mesh))
+            .unwrap();
+
+        let vertices = mesh.vertices().collect::<Vec<_>>();
+        for v in vertices {
+            let p = mesh.position(v);
+            mesh.vertex_mut(v).normal = p.normalised();
+        }
+        mesh
+    }
+
+    /// Everything a writer was given, for comparison with what it wrote.
+    #[derive(Default)]
+    pub struct Expected {
+        pub positions: Vec<Vec3>,
+        pub normals: Vec<Vec3>,
+        pub attributes: Vec<f32>,
+        pub indices: Vec<u32>,
+    }
+
+    impl VertexExtractor<f32> for Expected {
+        fn extract_vertex(&mut self, vertex: Vertex<f32>) {
+            self.positions.push(vertex.position);
+            self.normals.push(vertex.normal.unwrap());
+            self.attributes.push(vertex.attributes);
+        }
+
+        fn extract_index(&mut self, index: usize) {
+            self.indices.push(index as u32);
+        }
+    }
+
+    /// What a writer was given, with each face wound counter-clockwise as
+    /// file formats expect.
+    pub fn expected(mesh: &HalfEdgeMesh<f32>) -> Expected {
+        let mut expected = Expected::default();
+        mesh.extract(&mut expected).unwrap();
+        assert!(!expected.indices.is_empty());
+        for face in expected.indices.chunks_exact_mut(3) {
+            face.swap(1, 2);
+        }
+        expected
+    }
+
+    /// Extract a closed ball, centered in the unit cube.
+    pub fn extract_ball<E: VertexExtractor>(writer: &mut E) {
+        MarchingCubes::<Signed>::new(16)
+            .unwrap()
+            .extract(&Sampler::new(&ball()), writer)
+            .unwrap();
+    }
+
+    /// Check that every face of a ball extracted by [extract_ball] winds
+    /// counter-clockwise when viewed from outside.
+    pub fn assert_outward(positions: &[Vec3], indices: &[u32]) {
+        assert!(!indices.is_empty());
+        for face in indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[face[i] as usize]);
+            let outward = (a + b + c) / 3.0 - Vec3::from_scalar(0.5);
+            assert!((b - a).cross(c - a).dot(outward) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::extractor::{Vertex, VertexExtractor};
+use std::io::{self, Write};
+
+/// Streams an extracted mesh to a Wavefront OBJ file as it is extracted.
+///
+/// Vertex normals are written alongside positions, and referenced by each face
+/// as long as every vertex so far has had a normal. OBJ has no standard way to
+/// store other per-vertex data, so attributes are ignored.
+pub struct ObjWriter<W: Write> {
+    writer: W,
+    vertices: usize,
+    normals: usize,
+    face: Vec<usize>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> ObjWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            vertices: 0,
+            normals: 0,
+            face: Vec::with_capacity(3),
+            error: None,
+        }
+    }
+
+    /// Flush the output and return the underlying writer, or the first error
+    /// encountered while writing.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    // Extractors can't fail, so hold onto the first error until finish
+    fn write(&mut self, f: impl FnOnce(&mut W) -> io::Result<()>) {
+        if self.error.is_none() {
+            if let Err(error) = f(&mut self.writer) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+impl<W: Write, A> VertexExtractor<A> for ObjWriter<W> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        let p = vertex.position;
+        self.write(|w| writeln!(w, "v {} {} {}", p.x, p.y, p.z));
+        if let Some(n) = vertex.normal {
+            self.write(|w| writeln!(w, "vn {} {} {}", n.x, n.y, n.z));
+            self.normals += 1;
+        }
+        self.vertices += 1;
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        // OBJ indices start at 1
+        self.face.push(index + 1);
+        if self.face.len() < 3 {
+            return;
+        }
+
+        // Extracted faces wind clockwise when viewed from outside, while OBJ
+        // expects front faces to wind counter-clockwise
+        let [a, b, c] = [self.face[0], self.face[2], self.face[1]];
+        self.face.clear();
+        if self.normals == self.vertices {
+            self.write(|w| writeln!(w, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c));
+        } else {
+            self.write(|w| writeln!(w, "f {} {} {}", a, b, c));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        export::tests::{assert_outward, expected, extract_ball, test_mesh},
+        math::Vec3,
+    };
+
+    // Read back vertices, normals and (zero-based) indices
+    fn read_obj(text: &str) -> (Vec<Vec3>, Vec<Vec3>, Vec<u32>) {
+        let (mut positions, mut normals, mut indices) = (vec![], vec![], vec![]);
+        let vector = |fields: &[&str]| {
+            let v = fields
+                .iter()
+                .map(|f| f.parse().unwrap())
+                .collect::<Vec<f32>>();
+            Vec3::new(v[0], v[1], v[2])
+        };
+        for line in text.lines() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields[0] {
+                "v" => positions.push(vector(&fields[1..])),
+                "vn" => normals.push(vector(&fields[1..])),
+                "f" => {
+                    for field in &fields[1..] {
+                        let mut references = field.split("//");
+                        let v = references.next().unwrap();
+                        if let Some(n) = references.next() {
+                            assert_eq!(v, n);
+                        }
+                        indices.push(v.parse::<u32>().unwrap() - 1);
+                    }
+                }
+                _ => panic!("unexpected line {}", line),
+            }
+        }
+        (positions, normals, indices)
+    }
+
+    #[test]
+    fn test_obj() {
+        let mesh = test_mesh();
+        let expected = expected(&mesh);
+
+        let mut writer = ObjWriter::new(vec![]);
+        mesh.extract(&mut writer).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let (positions, normals, indices) = read_obj(std::str::from_utf8(&bytes).unwrap());
+        assert_eq!(positions, expected.positions);
+        assert_eq!(normals, expected.normals);
+        assert_eq!(indices, expected.indices);
+    }
+
+    #[test]
+    fn test_obj_winding() {
+        let mut writer = ObjWriter::new(vec![]);
+        extract_ball(&mut writer);
+        let bytes = writer.finish().unwrap();
+
+        let (positions, _, indices) = read_obj(std::str::from_utf8(&bytes).unwrap());
+        assert_outward(&positions, &indices);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use super::{ExportAttribute, MeshBuffer};
+use crate::extractor::{Vertex, VertexExtractor};
+use std::io::{self, Write};
+
+/// The encoding of a PLY file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlyFormat {
+    /// Human-readable, one element per line.
+    Ascii,
+    /// Little-endian binary.
+    Binary,
+}
+
+/// Writes an extracted mesh to a Stanford PLY file.
+///
+/// The PLY header records the number of vertices and faces, so the mesh is
+/// buffered and written by [finish](PlyWriter::finish). Normals are written as
+/// `nx`, `ny` and `nz` if every vertex has one, and the components of any
+/// attributes as `attribute_0`, `attribute_1`, and so on.
+pub struct PlyWriter<W: Write> {
+    writer: W,
+    format: PlyFormat,
+    buffer: MeshBuffer,
+}
+
+impl<W: Write> PlyWriter<W> {
+    pub fn new(writer: W, format: PlyFormat) -> Self {
+        Self {
+            writer,
+            format,
+            buffer: MeshBuffer::new(),
+        }
+    }
+
+    /// Write the buffered mesh, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mesh = &self.buffer;
+        let normals = mesh.normals();
+        let w = &mut self.writer;
+
+        let format = match self.format {
+            PlyFormat::Ascii => "ascii",
+            PlyFormat::Binary => "binary_little_endian",
+        };
+        writeln!(w, "ply")?;
+        writeln!(w, "format {} 1.0", format)?;
+        writeln!(w, "element vertex {}", mesh.positions.len())?;
+        for axis in &["x", "y", "z"] {
+            writeln!(w, "property float {}", axis)?;
+        }
+        if normals.is_some() {
+            for axis in &["nx", "ny", "nz"] {
+                writeln!(w, "property float {}", axis)?;
+            }
+        }
+        for i in 0..mesh.components {
+            writeln!(w, "property float attribute_{}", i)?;
+        }
+        writeln!(w, "element face {}", mesh.indices.len() / 3)?;
+        writeln!(w, "property list uchar uint vertex_indices")?;
+        writeln!(w, "end_header")?;
+
+        for (i, p) in mesh.positions.iter().enumerate() {
+            let mut values = vec![p.x, p.y, p.z];
+            if let Some(normals) = normals {
+                values.extend_from_slice(&[normals[i].x, normals[i].y, normals[i].z]);
+            }
+            values.extend_from_slice(
+                &mesh.attributes[i * mesh.components..(i + 1) * mesh.components],
+            );
+
+            match self.format {
+                PlyFormat::Ascii => {
+                    let line = values.iter().map(f32::to_string).collect::<Vec<_>>();
+                    writeln!(w, "{}", line.join(" "))?;
+                }
+                PlyFormat::Binary => {
+                    for value in values {
+                        w.write_all(&value.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+
+        for face in mesh.indices.chunks(3) {
+            match self.format {
+                PlyFormat::Ascii => writeln!(w, "3 {} {} {}", face[0], face[1], face[2])?,
+                PlyFormat::Binary => {
+                    w.write_all(&[3])?;
+                    for index in face {
+                        w.write_all(&index.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+
+        w.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write, A: ExportAttribute> VertexExtractor<A> for PlyWriter<W> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        self.buffer.add_vertex(&vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.buffer.add_index(index);
+    }
+
+    fn max_index(&self) -> usize {
+        u32::MAX as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        export::tests::{assert_outward, expected, extract_ball, test_mesh},
+        math::Vec3,
+    };
+
+    // Read back the vertex properties and face indices, given the number of
+    // properties per vertex
+    fn read_ply(bytes: &[u8], properties: usize) -> (Vec<f32>, Vec<u32>) {
+        let end = b"end_header\n";
+        let start = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = std::str::from_utf8(&bytes[..start]).unwrap();
+        let count = |element: &str| {
+            header
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("element {} ", element)))
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+        let (vertices, faces) = (count("vertex"), count("face"));
+        assert_eq!(header.matches("property float").count(), properties);
+
+        let (mut values, mut indices) = (vec![], vec![]);
+        let body = &bytes[start..];
+        if header.contains("format ascii") {
+            let lines = std::str::from_utf8(body)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>();
+            assert_eq!(lines.len(), vertices + faces);
+            for line in &lines[..vertices] {
+                values.extend(line.split(' ').map(|v| v.parse::<f32>().unwrap()));
+            }
+            for line in &lines[vertices..] {
+                let fields = line.split(' ').collect::<Vec<_>>();
+                assert_eq!(fields[0], "3");
+                indices.extend(fields[1..].iter().map(|i| i.parse::<u32>().unwrap()));
+            }
+        } else {
+            let word = |offset: usize| {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&body[offset..offset + 4]);
+                bytes
+            };
+            let floats = vertices * properties;
+            values.extend((0..floats).map(|i| f32::from_le_bytes(word(i * 4))));
+            for face in 0..faces {
+                let offset = floats * 4 + face * 13;
+                assert_eq!(body[offset], 3);
+                indices.extend((0..3).map(|i| u32::from_le_bytes(word(offset + 1 + i * 4))));
+            }
+            assert_eq!(body.len(), floats * 4 + faces * 13);
+        }
+        (values, indices)
+    }
+
+    #[test]
+    fn test_ply() {
+        let mesh = test_mesh();
+        let expected = expected(&mesh);
+        let vertices = expected
+            .positions
+            .iter()
+            .zip(&expected.normals)
+            .zip(&expected.attributes)
+            .flat_map(|((p, n), &a)| vec![p.x, p.y, p.z, n.x, n.y, n.z, a])
+            .collect::<Vec<_>>();
+
+        for &format in &[PlyFormat::Ascii, PlyFormat::Binary] {
+            let mut writer = PlyWriter::new(vec![], format);
+            mesh.extract(&mut writer).unwrap();
+            let bytes = writer.finish().unwrap();
+
+            let (values, indices) = read_ply(&bytes, 7);
+            assert_eq!(values, vertices);
+            assert_eq!(indices, expected.indices);
+        }
+    }
+
+    #[test]
+    fn test_ply_winding() {
+        let mut writer = PlyWriter::new(vec![], PlyFormat::Binary);
+        extract_ball(&mut writer);
+        let (values, indices) = read_ply(&writer.finish().unwrap(), 3);
+
+        let positions = values
+            .chunks(3)
+            .map(|v| Vec3::new(v[0], v[1], v[2]))
+            .collect::<Vec<_>>();
+        assert_outward(&positions, &indices);
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use super::{ExportAttribute, MeshBuffer};
+use crate::{
+    extractor::{Vertex, VertexExtractor},
+    math::Vec3,
+};
+use std::io::{self, Write};
+
+/// Writes an extracted mesh to a binary STL file.
+///
+/// STL stores each triangle separately, with a face normal and no shared
+/// vertices, so vertex normals and attributes are discarded. The header
+/// records the number of triangles, so the mesh is buffered and written by
+/// [finish](StlWriter::finish).
+pub struct StlWriter<W: Write> {
+    writer: W,
+    buffer: MeshBuffer,
+}
+
+impl<W: Write> StlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: MeshBuffer::new(),
+        }
+    }
+
+    /// Write the buffered mesh, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mesh = &self.buffer;
+        let w = &mut self.writer;
+
+        let mut header = [0u8; 80];
+        let name = b"isosurface";
+        header[..name.len()].copy_from_slice(name);
+        w.write_all(&header)?;
+        w.write_all(&(mesh.indices.len() as u32 / 3).to_le_bytes())?;
+
+        let write_vector = |w: &mut W, v: Vec3| -> io::Result<()> {
+            for component in &[v.x, v.y, v.z] {
+                w.write_all(&component.to_le_bytes())?;
+            }
+            Ok(())
+        };
+        for face in mesh.indices.chunks(3) {
+            let p = [0, 1, 2].map(|i| mesh.positions[face[i] as usize]);
+            let normal = (p[1] - p[0]).cross(p[2] - p[0]).normalised();
+            write_vector(w, normal.unwrap_or_default())?;
+            for &v in &p {
+                write_vector(w, v)?;
+            }
+            // The attribute byte count, which is unused
+            w.write_all(&[0, 0])?;
+        }
+
+        w.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write, A: ExportAttribute> VertexExtractor<A> for StlWriter<W> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        self.buffer.add_vertex(&vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.buffer.add_index(index);
+    }
+
+    fn max_index(&self) -> usize {
+        u32::MAX as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{assert_outward, expected, extract_ball, test_mesh};
+
+    // Read back the normal and vertices of each triangle
+    fn read_stl(bytes: &[u8]) -> Vec<[Vec3; 4]> {
+        let word = |offset: usize| {
+            let mut word = [0; 4];
+            word.copy_from_slice(&bytes[offset..offset + 4]);
+            word
+        };
+        let vector = |offset: usize| {
+            let v = [0, 1, 2].map(|i| f32::from_le_bytes(word(offset + i * 4)));
+            Vec3::new(v[0], v[1], v[2])
+        };
+
+        let count = u32::from_le_bytes(word(80)) as usize;
+        assert_eq!(bytes.len(), 84 + count * 50);
+        (0..count)
+            .map(|i| [0, 1, 2, 3].map(|j| vector(84 + i * 50 + j * 12)))
+            .collect()
+    }
+
+    #[test]
+    fn test_stl() {
+        let mesh = test_mesh();
+        let expected = expected(&mesh);
+
+        let mut writer = StlWriter::new(vec![]);
+        mesh.extract(&mut writer).unwrap();
+        let triangles = read_stl(&writer.finish().unwrap());
+
+        assert_eq!(triangles.len(), expected.indices.len() / 3);
+        for (triangle, face) in triangles.iter().zip(expected.indices.chunks(3)) {
+            for i in 0..3 {
+                assert_eq!(triangle[i + 1], expected.positions[face[i] as usize]);
+            }
+            // Facet normals follow the winding of the vertices
+            let [n, a, b, c] = *triangle;
+            let normal = (b - a).cross(c - a).normalised().unwrap_or_default();
+            assert!((n - normal).len() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_stl_winding() {
+        let mut writer = StlWriter::new(vec![]);
+        extract_ball(&mut writer);
+        let triangles = read_stl(&writer.finish().unwrap());
+
+        let positions = triangles
+            .iter()
+            .flat_map(|t| t[1..].to_vec())
+            .collect::<Vec<_>>();
+        assert_outward(&positions, &(0..positions.len() as u32).collect::<Vec<_>>());
+        for &[n, a, b, c] in &triangles {
+            assert!(n.dot((a + b + c) / 3.0 - Vec3::from_scalar(0.5)) > 0.0);
+        }
+    }
+}
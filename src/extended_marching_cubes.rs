@@ -13,18 +13,21 @@
 // limitations under the License.
 use crate::{
     distance::Directed,
-    extractor::Extractor,
+    error::Error,
+    extractor::{Vertex, VertexExtractor},
     feature::{LocalTopology, MinimiseQEF, TangentPlanes},
-    index_cache::GridKey,
+    index_cache::{EdgeCache, GridKey},
     marching_cubes_impl::{
-        classify_corners, find_edge_crossings, march_cube, sample_normals_at_edge_crossings,
+        cell_vertex, classify_corners, edge_crossing_vertex, find_edge_crossings, march_cube,
+        refine_edge_crossings, sample_normals_at_edge_crossings,
     },
     marching_cubes_tables::EDGE_LOOPS,
     math::Vec3,
-    mesh::{MeshTopology, MeshTopologyBuilder, VertexHandle},
-    sampler::Sample,
+    mesh::{Edge, MeshTopology, MeshTopologyBuilder, VertexHandle},
+    sampler::{Refinement, Sample},
     source::HermiteSource,
     traversal::PrimalGrid,
+    workspace::Workspace,
 };
 use std::collections::HashSet;
 
@@ -42,16 +45,28 @@ use std::collections::HashSet;
 ///   edge-flipping step.
 pub struct ExtendedMarchingCubes {
     primal_grid: PrimalGrid<Directed>,
+    refinement: Option<Refinement>,
 }
 
 impl ExtendedMarchingCubes {
     /// Create a new ExtendedMarchingCubes with the given chunk size.
     ///
     /// For a given `size`, this will evaluate chunks of `size^3` voxels.
-    pub fn new(size: usize) -> Self {
-        Self {
-            primal_grid: PrimalGrid::new(size),
-        }
+    pub fn new(size: usize) -> Result<Self, Error> {
+        Ok(Self {
+            primal_grid: PrimalGrid::new(size)?,
+            refinement: None,
+        })
+    }
+
+    /// Create a new ExtendedMarchingCubes with the given chunk size, which
+    /// refines the position of each edge crossing by searching along its grid
+    /// edge (see [Refinement]).
+    pub fn new_with_refinement(size: usize, refinement: Refinement) -> Result<Self, Error> {
+        Ok(Self {
+            primal_grid: PrimalGrid::new(size)?,
+            refinement: Some(refinement),
+        })
     }
 
     /// Extracts a mesh from the given [Sample].
@@ -61,55 +76,107 @@ impl ExtendedMarchingCubes {
     ///
     /// The resulting vertex and face data will be returned via the provided
     /// Extractor.
-    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E)
+    ///
+    /// Scratch memory is allocated afresh for each call. To reuse it between
+    /// calls, use [extract_with](ExtendedMarchingCubes::extract_with).
+    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
     where
         S: Sample<Directed> + HermiteSource,
-        E: Extractor,
+        E: VertexExtractor,
     {
-        let mut mesh_builder = MeshTopologyBuilder::new(extractor);
-        let mut features = HashSet::new();
-
-        self.primal_grid.traverse(source, |keys, corners, values| {
-            let mut vertices = [Vec3::zero(); 12];
-            let mut normals = [Vec3::zero(); 12];
+        self.extract_with(&mut Workspace::new(), source, extractor)
+    }
 
-            let cube_index = classify_corners(&values);
-            find_edge_crossings(cube_index, &corners, &values, &mut vertices);
-            sample_normals_at_edge_crossings(cube_index, source, &vertices, &mut normals);
+    /// Extracts a mesh from the given [Sample], as per
+    /// [extract](ExtendedMarchingCubes::extract), using the scratch memory in
+    /// the given [Workspace].
+    ///
+    /// Once the workspace has warmed up, repeatedly extracting similar
+    /// surfaces doesn't allocate.
+    pub fn extract_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
+    where
+        S: Sample<Directed> + HermiteSource,
+        E: VertexExtractor,
+    {
+        let Workspace {
+            edges,
+            mesh,
+            features,
+            flips,
+            ..
+        } = workspace;
+        edges.reset(self.primal_grid.size());
+        let mut mesh_builder = MeshTopologyBuilder::new(edges, mesh, extractor);
+        features.clear();
+        let refinement = self.refinement;
 
+        self.primal_grid.traverse(source, |keys, corners, values| {
             Self::march_cube_extended(
-                &mut features,
+                features,
                 &mut mesh_builder,
-                cube_index,
-                vertices,
-                normals,
+                source,
+                refinement,
                 keys,
+                corners,
+                values,
             );
-        });
+        })?;
 
-        let mut mesh = mesh_builder.build();
-        Self::flip_feature_edges(features, &mut mesh);
+        let mesh = mesh_builder.build()?;
+        Self::flip_feature_edges(features, flips, mesh);
         mesh.extract_indices(extractor);
+        Ok(())
     }
 
-    fn march_cube_extended<E>(
+    fn march_cube_extended<S, E>(
         features: &mut HashSet<VertexHandle>,
-        mesh_builder: &mut MeshTopologyBuilder<GridKey, E>,
-        cube_index: usize,
-        vertices: [Vec3; 12],
-        normals: [Vec3; 12],
+        mesh_builder: &mut MeshTopologyBuilder<EdgeCache<VertexHandle>, E>,
+        source: &S,
+        refinement: Option<Refinement>,
         keys: &[(usize, usize, usize); 8],
+        corners: &[Vec3; 8],
+        values: &[Directed; 8],
     ) where
-        E: Extractor,
+        S: Sample<Directed> + HermiteSource,
+        E: VertexExtractor,
     {
+        let mut vertices = [Vec3::zero(); 12];
+        let mut normals = [Vec3::zero(); 12];
+
+        let cube_index = classify_corners(values);
+        find_edge_crossings(cube_index, corners, values, &mut vertices);
+        if let Some(refinement) = refinement {
+            refine_edge_crossings(
+                cube_index,
+                corners,
+                values,
+                source,
+                refinement,
+                &mut vertices,
+            );
+        }
+        sample_normals_at_edge_crossings(cube_index, source, &vertices, &mut normals);
+
         let tangent_planes = TangentPlanes::from_edge_crossings(cube_index, &vertices, &normals);
 
+        let add_edge_vertex =
+            |mesh_builder: &mut MeshTopologyBuilder<EdgeCache<VertexHandle>, E>, edge| {
+                let vertex = Vertex {
+                    normal: Some(normals[edge]),
+                    ..edge_crossing_vertex(edge, corners, &vertices)
+                };
+                mesh_builder.add_vertex(Some(GridKey::new(keys, edge)), vertex)
+            };
+
         if let LocalTopology::Planar = tangent_planes.feature {
             // No feature detected, so we can just use traditional marching cubes
             march_cube(cube_index, |a, b, c| {
-                let a = mesh_builder.add_vertex(Some(GridKey::new(keys, a)), vertices[a]);
-                let b = mesh_builder.add_vertex(Some(GridKey::new(keys, b)), vertices[b]);
-                let c = mesh_builder.add_vertex(Some(GridKey::new(keys, c)), vertices[c]);
+                let [a, b, c] = [a, b, c].map(|edge| add_edge_vertex(mesh_builder, edge));
 
                 mesh_builder.add_face(a, b, c);
             });
@@ -122,7 +189,13 @@ impl ExtendedMarchingCubes {
             let feature_point = MinimiseQEF::place_feature_with_tangents(&tangent_planes);
 
             // Spawn a new vertex at the feature point
-            let center_index = mesh_builder.add_vertex(None, feature_point);
+            let center_index = mesh_builder.add_vertex(
+                None,
+                Vertex {
+                    feature: true,
+                    ..cell_vertex(feature_point, corners)
+                },
+            );
 
             // extract_vertex(feature_point);
             // let center_index = work.mesh.add_vertex();
@@ -142,10 +215,10 @@ impl ExtendedMarchingCubes {
                     let j = (i + 1) % vertex_count;
 
                     let e0 = EDGE_LOOPS[cube_index][offset + i] as usize;
-                    let a = mesh_builder.add_vertex(Some(GridKey::new(keys, e0)), vertices[e0]);
+                    let a = add_edge_vertex(mesh_builder, e0);
 
                     let e1 = EDGE_LOOPS[cube_index][offset + j] as usize;
-                    let b = mesh_builder.add_vertex(Some(GridKey::new(keys, e1)), vertices[e1]);
+                    let b = add_edge_vertex(mesh_builder, e1);
 
                     mesh_builder.add_face(a, b, center_index);
                 }
@@ -154,11 +227,15 @@ impl ExtendedMarchingCubes {
         }
     }
 
-    fn flip_feature_edges(features: HashSet<VertexHandle>, mesh: &mut MeshTopology) {
+    fn flip_feature_edges(
+        features: &HashSet<VertexHandle>,
+        flips: &mut Vec<Edge>,
+        mesh: &mut MeshTopology,
+    ) {
         // We can't modify the mesh while iterating it (it would anger the borrow
         // checker), so accumulate edges that need flipping and process them
         // afterwards
-        let mut flips = HashSet::new();
+        flips.clear();
 
         for &edge in mesh.edges() {
             // If this edge already joins two features, don't flip it
@@ -166,21 +243,22 @@ impl ExtendedMarchingCubes {
                 continue;
             }
 
-            let faces = mesh.adjoining_faces(edge);
+            let mut faces = mesh.adjoining_faces(edge);
 
             // Only try to flip edges that adjoin exactly two faces
-            if let [face_a, face_b] = faces[..] {
+            if let (2, Some(face_a), Some(face_b)) = (faces.len(), faces.next(), faces.next()) {
                 // If flipping this edge will connect two features, add it to the list
-                if features.contains(&face_a.vertex_opposite(edge))
-                    && features.contains(&face_b.vertex_opposite(edge))
+                let is_feature = |v: Option<VertexHandle>| v.is_some_and(|v| features.contains(&v));
+                if is_feature(face_a.vertex_opposite(edge))
+                    && is_feature(face_b.vertex_opposite(edge))
                 {
-                    flips.insert(edge);
+                    flips.push(edge);
                 }
             }
         }
 
         // Now we can flip all the edges we found earlier
-        for edge in flips {
+        for &edge in flips.iter() {
             mesh.rotate_edge(edge);
         }
     }
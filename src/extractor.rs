@@ -11,12 +11,160 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::{math::Vec3, source::HermiteSource};
+use crate::{
+    math::Vec3,
+    source::{Attribute, AttributeSource, HermiteSource},
+};
+use std::marker::PhantomData;
+
+/// Where in the sampling grid (or octree) a vertex was generated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VertexOrigin {
+    /// The vertex lies at the surface crossing on the edge from `start` to
+    /// `end`, a fraction `factor` of the way along the edge.
+    Edge { start: Vec3, end: Vec3, factor: f32 },
+    /// The vertex was placed somewhere inside the cell spanning `min` to `max`.
+    Cell { min: Vec3, max: Vec3 },
+}
+
+/// Everything an extraction algorithm knows about a single vertex.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vertex<A = ()> {
+    pub position: Vec3,
+    /// The normalised surface normal, if the extraction algorithm sampled one.
+    pub normal: Option<Vec3>,
+    pub origin: VertexOrigin,
+    /// Whether the vertex was placed on a sharp feature (an edge or corner)
+    /// of the surface.
+    pub feature: bool,
+    /// User attributes, as provided by an [AttributeSource].
+    pub attributes: A,
+}
+
+impl Vertex {
+    /// Create a vertex with no normal, attributes, or feature.
+    pub fn new(position: Vec3, origin: VertexOrigin) -> Self {
+        Self {
+            position,
+            normal: None,
+            origin,
+            feature: false,
+            attributes: (),
+        }
+    }
+}
+
+impl<A> Vertex<A> {
+    /// Replace the attributes of this vertex.
+    pub fn with_attributes<B>(self, attributes: B) -> Vertex<B> {
+        Vertex {
+            position: self.position,
+            normal: self.normal,
+            origin: self.origin,
+            feature: self.feature,
+            attributes,
+        }
+    }
+}
+
+/// Trait for outputting complete vertex records and indices.
+///
+/// Extraction algorithms output vertices via this trait. Every [Extractor]
+/// implements it, by way of [Extractor::extract_record].
+pub trait VertexExtractor<A = ()> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>);
+    fn extract_index(&mut self, index: usize);
+
+    /// The largest vertex index this extractor can represent. Extraction fails
+    /// rather than produce a mesh with more vertices than this.
+    fn max_index(&self) -> usize {
+        usize::MAX
+    }
+}
+
+impl<E: Extractor> VertexExtractor for E {
+    fn extract_vertex(&mut self, vertex: Vertex) {
+        Extractor::extract_record(self, vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        Extractor::extract_index(self, index);
+    }
+
+    fn max_index(&self) -> usize {
+        Extractor::max_index(self)
+    }
+}
+
+/// Sample user attributes for each vertex from an [AttributeSource], and pass
+/// the vertices on to another [VertexExtractor].
+///
+/// Vertices which lie on an edge take the attributes at either end of the
+/// edge, interpolated by the same factor used to place the vertex. For the
+/// dual methods, the ends of each edge are the feature points placed inside
+/// each cell. Vertices placed inside a cell sample the attributes at the
+/// vertex itself.
+pub struct WithAttributes<'a, T, S: AttributeSource<T>, E: VertexExtractor<T>> {
+    source: &'a S,
+    extractor: &'a mut E,
+    attribute: PhantomData<T>,
+}
+
+impl<'a, T, S: AttributeSource<T>, E: VertexExtractor<T>> WithAttributes<'a, T, S, E> {
+    pub fn new(source: &'a S, extractor: &'a mut E) -> Self {
+        Self {
+            source,
+            extractor,
+            attribute: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, S, E> VertexExtractor for WithAttributes<'a, T, S, E>
+where
+    T: Attribute,
+    S: AttributeSource<T>,
+    E: VertexExtractor<T>,
+{
+    fn extract_vertex(&mut self, vertex: Vertex) {
+        let attributes = match vertex.origin {
+            VertexOrigin::Edge { start, end, factor } => {
+                let a = self.source.sample_attribute(start);
+                let b = self.source.sample_attribute(end);
+                a.lerp(&b, factor)
+            }
+            VertexOrigin::Cell { .. } => self.source.sample_attribute(vertex.position),
+        };
+        self.extractor
+            .extract_vertex(vertex.with_attributes(attributes));
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.extractor.extract_index(index);
+    }
+
+    fn max_index(&self) -> usize {
+        self.extractor.max_index()
+    }
+}
 
 /// Trait for outputting mesh vertices and indices.
 pub trait Extractor {
     fn extract_vertex(&mut self, vertex: Vec3);
     fn extract_index(&mut self, index: usize);
+
+    /// The largest vertex index this extractor can represent. Extraction fails
+    /// rather than produce a mesh with more vertices than this.
+    fn max_index(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Output a complete vertex record. By default everything but the vertex
+    /// position is discarded, but extractors may override this to make use of
+    /// the rest of the record.
+    fn extract_record(&mut self, vertex: Vertex) {
+        self.extract_vertex(vertex.position);
+    }
 }
 
 /// Output vertices as a tightly packed array of floats, discarding any face
@@ -41,8 +189,9 @@ impl<'a> Extractor for OnlyVertices<'a> {
     fn extract_index(&mut self, _: usize) {}
 }
 
-/// Output vertices as a tightly packed array of floats, discarding any face
-/// data.
+/// Output vertices interleaved with their normals as a tightly packed array of
+/// floats, discarding any face data. Normals are sampled from the source for
+/// any vertex that doesn't already have one.
 pub struct OnlyInterleavedNormals<'a, S: HermiteSource> {
     vertices: &'a mut Vec<f32>,
     source: &'a S,
@@ -52,11 +201,8 @@ impl<'a, S: HermiteSource> OnlyInterleavedNormals<'a, S> {
     pub fn new(vertices: &'a mut Vec<f32>, source: &'a S) -> Self {
         Self { vertices, source }
     }
-}
 
-impl<'a, S: HermiteSource> Extractor for OnlyInterleavedNormals<'a, S> {
-    fn extract_vertex(&mut self, v: Vec3) {
-        let n = self.source.sample_normal(v);
+    fn push(&mut self, v: Vec3, n: Vec3) {
         self.vertices.push(v.x);
         self.vertices.push(v.y);
         self.vertices.push(v.z);
@@ -64,8 +210,26 @@ impl<'a, S: HermiteSource> Extractor for OnlyInterleavedNormals<'a, S> {
         self.vertices.push(n.y);
         self.vertices.push(n.z);
     }
+}
+
+impl<'a, S: HermiteSource> Extractor for OnlyInterleavedNormals<'a, S> {
+    fn extract_vertex(&mut self, v: Vec3) {
+        let n = self.source.sample_normal(v);
+        self.push(v, n);
+    }
 
     fn extract_index(&mut self, _: usize) {}
+
+    fn extract_record(&mut self, vertex: Vertex) {
+        let v = vertex.position;
+        let n = vertex.normal.unwrap_or_else(|| {
+            self.source
+                .sample_normal(v)
+                .normalised()
+                .unwrap_or_default()
+        });
+        self.push(v, n);
+    }
 }
 
 /// Output vertices as a tightly packed array of floats.
@@ -90,10 +254,15 @@ impl<'a> Extractor for IndexedVertices<'a> {
     fn extract_index(&mut self, index: usize) {
         self.indices.push(index as u32);
     }
+
+    fn max_index(&self) -> usize {
+        u32::MAX as usize
+    }
 }
 
-/// Sample normals from an implicit surface and output them interleaved with
-/// vertices, as a tightly packed array of floats.
+/// Output vertices interleaved with their normals, as a tightly packed array of
+/// floats. Normals are sampled from the source for any vertex that doesn't
+/// already have one.
 pub struct IndexedInterleavedNormals<'a, S: HermiteSource> {
     vertices: &'a mut Vec<f32>,
     indices: &'a mut Vec<u32>,
@@ -108,11 +277,8 @@ impl<'a, S: HermiteSource> IndexedInterleavedNormals<'a, S> {
             source,
         }
     }
-}
 
-impl<'a, S: HermiteSource> Extractor for IndexedInterleavedNormals<'a, S> {
-    fn extract_vertex(&mut self, v: Vec3) {
-        let n = self.source.sample_normal(v);
+    fn push(&mut self, v: Vec3, n: Vec3) {
         self.vertices.push(v.x);
         self.vertices.push(v.y);
         self.vertices.push(v.z);
@@ -120,8 +286,195 @@ impl<'a, S: HermiteSource> Extractor for IndexedInterleavedNormals<'a, S> {
         self.vertices.push(n.y);
         self.vertices.push(n.z);
     }
+}
+
+impl<'a, S: HermiteSource> Extractor for IndexedInterleavedNormals<'a, S> {
+    fn extract_vertex(&mut self, v: Vec3) {
+        let n = self.source.sample_normal(v);
+        self.push(v, n);
+    }
 
     fn extract_index(&mut self, index: usize) {
         self.indices.push(index as u32);
     }
+
+    fn max_index(&self) -> usize {
+        u32::MAX as usize
+    }
+
+    fn extract_record(&mut self, vertex: Vertex) {
+        let v = vertex.position;
+        let n = vertex.normal.unwrap_or_else(|| {
+            self.source
+                .sample_normal(v)
+                .normalised()
+                .unwrap_or_default()
+        });
+        self.push(v, n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed, implicit::RectangularPrism, sampler::Sampler, ExtendedMarchingCubes,
+        MarchingCubes,
+    };
+
+    struct Records(Vec<Vertex<f32>>);
+
+    impl VertexExtractor<f32> for Records {
+        fn extract_vertex(&mut self, vertex: Vertex<f32>) {
+            self.0.push(vertex);
+        }
+
+        fn extract_index(&mut self, _: usize) {}
+    }
+
+    struct Height;
+
+    impl AttributeSource<f32> for Height {
+        fn sample_attribute(&self, p: Vec3) -> f32 {
+            p.y
+        }
+    }
+
+    fn assert_origin(vertex: &Vertex<f32>) {
+        match vertex.origin {
+            VertexOrigin::Edge { start, end, factor } => {
+                assert!((start.lerp(end, factor) - vertex.position).len() < 1e-5);
+            }
+            VertexOrigin::Cell { min, max } => {
+                // Feature points may stray a little outside their cell
+                let center = min.lerp(max, 0.5);
+                assert!((vertex.position - center).len() < (max - min).len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_vertex_records() {
+        let prism = RectangularPrism::new(Vec3::from_scalar(0.2));
+        let sampler = Sampler::new(&prism);
+
+        let mut records = Records(vec![]);
+        let mut extractor = WithAttributes::new(&Height, &mut records);
+        MarchingCubes::<Signed>::new(16)
+            .unwrap()
+            .extract(&sampler, &mut extractor)
+            .unwrap();
+
+        assert!(!records.0.is_empty());
+        for vertex in &records.0 {
+            assert_origin(vertex);
+            assert!((vertex.attributes - vertex.position.y).abs() < 1e-5);
+            assert!(!vertex.feature && vertex.normal.is_none());
+        }
+
+        let mut records = Records(vec![]);
+        let mut extractor = WithAttributes::new(&Height, &mut records);
+        ExtendedMarchingCubes::new(16)
+            .unwrap()
+            .extract(&sampler, &mut extractor)
+            .unwrap();
+
+        let features = records.0.iter().filter(|v| v.feature).count();
+        assert!(features > 0 && features < records.0.len());
+        for vertex in &records.0 {
+            assert_origin(vertex);
+            if vertex.feature {
+                assert!(matches!(vertex.origin, VertexOrigin::Cell { .. }));
+            } else {
+                assert!(vertex.normal.is_some());
+            }
+        }
+
+        // Plain extractors still work, and normal extractors reuse the normals
+        // found during extraction
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        ExtendedMarchingCubes::new(16)
+            .unwrap()
+            .extract(
+                &sampler,
+                &mut IndexedInterleavedNormals::new(&mut vertices, &mut indices, &sampler),
+            )
+            .unwrap();
+        assert_eq!(vertices.len(), records.0.len() * 6);
+    }
+
+    fn extract_plain<E: Extractor>(extractor: &mut E) {
+        let prism = RectangularPrism::new(Vec3::from_scalar(0.2));
+        let sampler = Sampler::new(&prism);
+        ExtendedMarchingCubes::new(16)
+            .unwrap()
+            .extract(&sampler, extractor)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_plain_extractors() {
+        let prism = RectangularPrism::new(Vec3::from_scalar(0.2));
+        let sampler = Sampler::new(&prism);
+
+        let mut positions = vec![];
+        let mut position_indices = vec![];
+        extract_plain(&mut IndexedVertices::new(
+            &mut positions,
+            &mut position_indices,
+        ));
+        assert!(!positions.is_empty());
+
+        let mut only = vec![];
+        extract_plain(&mut OnlyInterleavedNormals::new(&mut only, &sampler));
+        assert_eq!(only.len(), positions.len() * 2);
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        extract_plain(&mut IndexedInterleavedNormals::new(
+            &mut vertices,
+            &mut indices,
+            &sampler,
+        ));
+        assert_eq!(vertices, only);
+        assert_eq!(indices, position_indices);
+
+        // Plain vertices sample their normal from the source
+        let mut vertices = vec![];
+        let mut extractor = OnlyInterleavedNormals::new(&mut vertices, &sampler);
+        let p = Vec3::new(0.2, 0.0, 0.0);
+        Extractor::extract_vertex(&mut extractor, p);
+        let n = sampler.sample_normal(p);
+        assert_eq!(vertices, vec![p.x, p.y, p.z, n.x, n.y, n.z]);
+    }
+
+    #[test]
+    fn test_sampled_normals_are_normalised() {
+        let prism = RectangularPrism::new(Vec3::from_scalar(0.2));
+        let sampler = Sampler::new(&prism);
+
+        // Marching cubes finds no normals, so they're sampled from the source
+        let mut only = vec![];
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut marching_cubes = MarchingCubes::<Signed>::new(16).unwrap();
+        marching_cubes
+            .extract(
+                &sampler,
+                &mut OnlyInterleavedNormals::new(&mut only, &sampler),
+            )
+            .unwrap();
+        marching_cubes
+            .extract(
+                &sampler,
+                &mut IndexedInterleavedNormals::new(&mut vertices, &mut indices, &sampler),
+            )
+            .unwrap();
+
+        assert!(!only.is_empty() && !vertices.is_empty());
+        for v in only.chunks(6).chain(vertices.chunks(6)) {
+            assert!((Vec3::new(v[3], v[4], v[5]).len() - 1.0).abs() < 1e-5);
+        }
+    }
 }
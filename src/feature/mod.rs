@@ -19,7 +19,9 @@ pub use qef::*;
 
 use crate::{marching_cubes_tables::EDGE_CROSSING_MASK, math::Vec3};
 
-const FEATURE_ANGLE: f32 = 0.8660254037844387; // cos(30º)
+/// The cosine of the angle (30º) between two surface normals beyond which the
+/// surface is considered to have a sharp feature.
+pub const FEATURE_ANGLE: f32 = 0.866_025_4;
 
 /// Place a mesh vertex at a feature point within a grid cell
 pub trait PlaceFeatureInCell {
@@ -41,6 +43,7 @@ pub enum LocalTopology {
     Corner,
 }
 
+#[derive(Copy, Clone, Default)]
 pub(crate) struct Plane {
     normal: Vec3,
     d: f32,
@@ -59,7 +62,10 @@ impl Plane {
 
 /// The set of planes tangent to the surface within a given grid cell.
 pub struct TangentPlanes {
-    pub(crate) planes: Vec<Plane>,
+    // One plane for each of at most 12 edge crossings, stored inline to
+    // avoid allocating for every cell
+    planes: [Plane; 12],
+    plane_count: usize,
     pub(crate) center_of_mass: Vec3,
     pub(crate) feature: LocalTopology,
 }
@@ -80,18 +86,26 @@ impl TangentPlanes {
     ) -> Self {
         let edges = EDGE_CROSSING_MASK[cube_index];
 
-        let indices: Vec<usize> = (0..12).filter(|i| (edges & (1 << i)) != 0).collect();
+        let mut crossings = [Vec3::zero(); 12];
+        let mut crossing_normals = [Vec3::zero(); 12];
+        let mut count = 0;
+        for i in (0..12).filter(|i| (edges & (1 << i)) != 0) {
+            crossings[count] = vertices[i];
+            crossing_normals[count] = normals[i];
+            count += 1;
+        }
 
-        let vertices: Vec<Vec3> = indices.iter().map(|&i| vertices[i]).collect();
-        let normals: Vec<Vec3> = indices.iter().map(|&i| normals[i]).collect();
+        Self::new(&crossings[..count], &crossing_normals[..count])
+    }
 
-        Self::new(&vertices, &normals)
+    pub(crate) fn planes(&self) -> &[Plane] {
+        &self.planes[..self.plane_count]
     }
 
     fn new(vertices: &[Vec3], normals: &[Vec3]) -> Self {
         let mut center_of_mass = Vec3::zero();
         let mut axis = Vec3::zero();
-        let mut min_angle = std::f32::MAX;
+        let mut min_angle = f32::MAX;
 
         let mut count = 0.0;
 
@@ -130,18 +144,17 @@ impl TangentPlanes {
             }
         };
 
-        let planes = (0..vertices.len())
-            .into_iter()
-            .map(|i| {
-                let normal = normals[i];
-                let d = (vertices[i] - center_of_mass).dot(normal);
+        let mut planes = [Plane::default(); 12];
+        for i in 0..vertices.len() {
+            let normal = normals[i];
+            let d = (vertices[i] - center_of_mass).dot(normal);
 
-                Plane { normal, d }
-            })
-            .collect();
+            planes[i] = Plane { normal, d };
+        }
 
         Self {
             planes,
+            plane_count: vertices.len(),
             center_of_mass,
             feature,
         }
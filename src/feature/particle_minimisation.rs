@@ -32,7 +32,7 @@ impl PlaceFeatureInCell for ParticleBasedMinimisation {
         let mut forces = [Vec3::zero(); 8];
         for i in 0..8 {
             for j in 0..8 {
-                forces[i] += corners[i] - t.planes[j].point_closest_to(corners[i]);
+                forces[i] += corners[i] - t.planes()[j].point_closest_to(corners[i]);
             }
         }
 
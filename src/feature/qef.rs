@@ -15,6 +15,7 @@ use crate::{
     feature::{LocalTopology, PlaceFeatureInCell, TangentPlanes},
     math::{svd::SVD, Vec3},
 };
+use std::ops::{Add, AddAssign};
 
 /// The feature placement algorithm used by Extended Marching Cubes and
 /// traditional Dual Contouring. Uses Singular Value Decomposition to minimise
@@ -38,18 +39,20 @@ impl MinimiseQEF {
             return t.center_of_mass;
         }
 
-        let a: Vec<[f64; 3]> = t
-            .planes
-            .iter()
-            .map(|p| [p.normal.x as f64, p.normal.y as f64, p.normal.z as f64])
-            .collect();
+        let planes = t.planes();
+        let mut a = [[0.0; 3]; 12];
+        let mut b = [0.0; 12];
+        for (i, p) in planes.iter().enumerate() {
+            a[i] = [p.normal.x as f64, p.normal.y as f64, p.normal.z as f64];
+            b[i] = p.d as f64;
+        }
 
-        let mut svd = SVD::new(&a);
+        let mut svd = SVD::new(&a[..planes.len()]);
 
         // The system of equations is underspecified for edges, so
         // we zero the minimum singular value to reduce the rank
         if let LocalTopology::Edge = t.feature {
-            let mut s_min = std::f64::MAX;
+            let mut s_min = f64::MAX;
             let mut s_min_id = 0;
 
             for i in 0..3 {
@@ -62,8 +65,108 @@ impl MinimiseQEF {
             svd.diagonal()[s_min_id] = 0.0;
         }
 
-        let b: Vec<f64> = t.planes.iter().map(|p| p.d as f64).collect();
+        t.center_of_mass + svd.solve(&b[..planes.len()])
+    }
+}
+
+/// An accumulated quadratic error function, measuring the sum of the squared
+/// distances from a point to a set of planes.
+///
+/// This is the quadric error metric of [Surface Simplification Using Quadric Error Metrics](https://dl.acm.org/doi/10.1145/258734.258849),
+/// stored compactly as the normal equations of the system of planes, so that
+/// quadrics can be summed as planes are merged.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Quadric {
+    // The upper triangle of the symmetric matrix A^T A
+    ata: [f64; 6],
+    atb: [f64; 3],
+    btb: f64,
+    mass_point: Vec3,
+    count: f32,
+}
+
+impl Quadric {
+    /// Create a quadric for the plane through `point` with the given normal.
+    pub fn from_plane(point: Vec3, normal: Vec3) -> Self {
+        let n = [normal.x as f64, normal.y as f64, normal.z as f64];
+        let d = normal.dot(point) as f64;
+
+        Self {
+            ata: [
+                n[0] * n[0],
+                n[0] * n[1],
+                n[0] * n[2],
+                n[1] * n[1],
+                n[1] * n[2],
+                n[2] * n[2],
+            ],
+            atb: [n[0] * d, n[1] * d, n[2] * d],
+            btb: d * d,
+            mass_point: point,
+            count: 1.0,
+        }
+    }
+
+    /// The sum of the squared distances from `p` to each plane.
+    pub fn error(&self, p: Vec3) -> f64 {
+        let p = [p.x as f64, p.y as f64, p.z as f64];
+        let ap = self.multiply(p);
+        let pap = p[0] * ap[0] + p[1] * ap[1] + p[2] * ap[2];
+        let bp = self.atb[0] * p[0] + self.atb[1] * p[1] + self.atb[2] * p[2];
+
+        (pap - 2.0 * bp + self.btb).max(0.0)
+    }
+
+    /// Find the point which minimises the error. Where the planes don't
+    /// constrain the point (i.e. along the direction of a flat surface or an
+    /// edge), the point is placed as close as possible to the average of the
+    /// points used to construct the planes.
+    pub fn minimise(&self) -> Vec3 {
+        if self.count == 0.0 {
+            return Vec3::zero();
+        }
+        let center = self.mass_point / self.count;
 
-        t.center_of_mass + svd.solve(&b)
+        let m = &self.ata;
+        let a = [[m[0], m[1], m[2]], [m[1], m[3], m[4]], [m[2], m[4], m[5]]];
+
+        // Solve relative to the mass point, so that discarding small singular
+        // values pulls the result towards it
+        let ac = self.multiply([center.x as f64, center.y as f64, center.z as f64]);
+        let b = [0, 1, 2].map(|i| self.atb[i] - ac[i]);
+
+        center + SVD::new(&a).solve(&b)
+    }
+
+    fn multiply(&self, p: [f64; 3]) -> [f64; 3] {
+        let m = &self.ata;
+        [
+            m[0] * p[0] + m[1] * p[1] + m[2] * p[2],
+            m[1] * p[0] + m[3] * p[1] + m[4] * p[2],
+            m[2] * p[0] + m[4] * p[1] + m[5] * p[2],
+        ]
+    }
+}
+
+impl Add for Quadric {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for Quadric {
+    fn add_assign(&mut self, other: Self) {
+        for i in 0..6 {
+            self.ata[i] += other.ata[i];
+        }
+        for i in 0..3 {
+            self.atb[i] += other.atb[i];
+        }
+        self.btb += other.btb;
+        self.mass_point += other.mass_point;
+        self.count += other.count;
     }
 }
@@ -0,0 +1,122 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    bytecode::{Compile, Compiler, Program},
+    distance::Signed,
+    extractor::IndexedVertices,
+    implicit::Sphere,
+    math::{Interval, Vec3},
+    sampler::Sampler,
+    source::{HermiteSource, IntervalSource, ScalarSource},
+    MarchingCubes,
+};
+use std::cell::Cell;
+
+/// Moves a shape from the origin to the center of the unit cube, which is the
+/// region extraction samples.
+pub struct Centered<S>(pub S);
+
+impl<S: ScalarSource> ScalarSource for Centered<S> {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        self.0.sample_scalar(p - Vec3::from_scalar(0.5))
+    }
+}
+
+impl<S: IntervalSource> IntervalSource for Centered<S> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let offset = Vec3::from_scalar(0.5);
+        self.0.sample_interval(min - offset, max - offset)
+    }
+}
+
+impl<S: HermiteSource> HermiteSource for Centered<S> {
+    fn sample_normal(&self, p: Vec3) -> Vec3 {
+        self.0.sample_normal(p - Vec3::from_scalar(0.5))
+    }
+}
+
+/// A ball in the center of the unit cube, clear of its faces, so that
+/// extracted meshes are closed.
+pub fn ball() -> Centered<Sphere> {
+    Centered(Sphere::new(0.3))
+}
+
+/// Extract a mesh with marching cubes of the given size, as positions and
+/// indices.
+pub fn extract_mesh<S: ScalarSource>(source: &S, size: usize) -> (Vec<Vec3>, Vec<u32>) {
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    MarchingCubes::<Signed>::new(size)
+        .unwrap()
+        .extract(
+            &Sampler::new(source),
+            &mut IndexedVertices::new(&mut vertices, &mut indices),
+        )
+        .unwrap();
+    let positions = vertices
+        .chunks(3)
+        .map(|v| Vec3::new(v[0], v[1], v[2]))
+        .collect();
+    (positions, indices)
+}
+
+/// Two spheres in opposite corners of the unit cube, compiled to a program.
+/// Most regions of the cube only need one sphere or the other.
+pub fn two_spheres() -> Program {
+    let mut compiler = Compiler::new();
+    let p = compiler.point();
+    let p_a = compiler.translate(p, Vec3::from_scalar(0.25));
+    let a = Sphere::new(0.2).compile(&mut compiler, p_a);
+    let p_b = compiler.translate(p, Vec3::from_scalar(0.75));
+    let b = Sphere::new(0.2).compile(&mut compiler, p_b);
+    let output = compiler.min(a, b);
+    compiler.finish(output)
+}
+
+/// Counts samples of a program, which is only specialised if requested.
+pub struct Counted {
+    pub program: Program,
+    pub specialise: bool,
+    pub samples: Cell<usize>,
+}
+
+impl Counted {
+    pub fn new(program: Program, specialise: bool) -> Self {
+        Self {
+            program,
+            specialise,
+            samples: Cell::new(0),
+        }
+    }
+}
+
+impl ScalarSource for Counted {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        self.samples.set(self.samples.get() + 1);
+        self.program.sample_scalar(p)
+    }
+}
+
+impl IntervalSource for Counted {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.program.sample_interval(min, max)
+    }
+
+    fn specialise(&self, min: Vec3, max: Vec3) -> Option<Program> {
+        if self.specialise {
+            self.program.specialise(min, max)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,747 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    error::Error,
+    extractor::{Vertex, VertexExtractor, VertexOrigin},
+    math::Vec3,
+    normals::face_normal,
+    source::Attribute,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    iter::{once, successors},
+};
+
+/// A handle to a vertex within a [HalfEdgeMesh].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct VertexHandle(usize);
+
+/// A handle to a half-edge within a [HalfEdgeMesh]. Each half-edge runs from
+/// one vertex of a face to the next, following the winding of the face.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct HalfEdgeHandle(usize);
+
+/// A handle to a face within a [HalfEdgeMesh].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FaceHandle(usize);
+
+impl VertexHandle {
+    /// The index of the vertex within the mesh's vertex storage.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl HalfEdgeHandle {
+    /// The index of the half-edge within the mesh's half-edge storage.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl FaceHandle {
+    /// The index of the face within the mesh's face storage.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// A triangle mesh which owns its vertices, and stores enough connectivity to
+/// walk the mesh in any direction.
+///
+/// The mesh implements [VertexExtractor], so it can be built directly by any
+/// of the extraction algorithms, and then written out to another extractor
+/// via [extract](HalfEdgeMesh::extract) once post-processing is complete.
+///
+/// Faces keep the winding they were extracted with, which is clockwise when
+/// viewed from outside the surface, and rotational orders around faces and
+/// vertices are likewise clockwise when viewed from outside.
+///
+/// Every edge is shared by at most two faces with opposing winding. Faces that
+/// would break this (i.e. the non-manifold output which dual methods may
+/// produce) are rejected when added, and counted by
+/// [rejected_faces](HalfEdgeMesh::rejected_faces).
+///
+/// The three half-edges of each face are stored consecutively, so there is no
+/// need to store the `next` and `prev` links. Removing faces or vertices
+/// leaves a gap in the storage, so handles remain valid until the mesh is
+/// extracted.
+pub struct HalfEdgeMesh<A = ()> {
+    vertices: Vec<Vertex<A>>,
+    removed_vertices: Vec<bool>,
+    outgoing: Vec<Option<HalfEdgeHandle>>,
+    faces: Vec<Option<[VertexHandle; 3]>>,
+    twins: Vec<Option<HalfEdgeHandle>>,
+    half_edges: HashMap<(VertexHandle, VertexHandle), HalfEdgeHandle>,
+    pending_indices: Vec<usize>,
+    rejected_faces: usize,
+}
+
+impl<A> HalfEdgeMesh<A> {
+    /// Create a new empty HalfEdgeMesh
+    pub fn new() -> Self {
+        Self {
+            vertices: vec![],
+            removed_vertices: vec![],
+            outgoing: vec![],
+            faces: vec![],
+            twins: vec![],
+            half_edges: HashMap::new(),
+            pending_indices: vec![],
+            rejected_faces: 0,
+        }
+    }
+
+    /// Add a vertex, which is not yet connected to any faces.
+    pub fn add_vertex(&mut self, vertex: Vertex<A>) -> VertexHandle {
+        self.vertices.push(vertex);
+        self.removed_vertices.push(false);
+        self.outgoing.push(None);
+        VertexHandle(self.vertices.len() - 1)
+    }
+
+    /// Add a new face, given 3 vertices in clockwise order when viewed from
+    /// outside.
+    ///
+    /// Returns None, and leaves the mesh untouched, if the face is degenerate,
+    /// or if one of its edges already has a face with the same winding.
+    pub fn add_face(
+        &mut self,
+        a: VertexHandle,
+        b: VertexHandle,
+        c: VertexHandle,
+    ) -> Option<FaceHandle> {
+        let vertices = [a, b, c];
+        if a == b || b == c || c == a || vertices.iter().any(|&v| self.removed_vertices[v.0]) {
+            return None;
+        }
+        if (0..3).any(|i| {
+            self.half_edges
+                .contains_key(&(vertices[i], vertices[(i + 1) % 3]))
+        }) {
+            return None;
+        }
+
+        let face = FaceHandle(self.faces.len());
+        self.faces.push(None);
+        self.twins.extend_from_slice(&[None; 3]);
+        self.attach_face(face, vertices);
+        Some(face)
+    }
+
+    /// The number of faces rejected (while extracting, or by
+    /// [add_face](HalfEdgeMesh::add_face)) because they were degenerate or
+    /// non-manifold.
+    pub fn rejected_faces(&self) -> usize {
+        self.rejected_faces
+    }
+
+    /// The number of vertices in the mesh, excluding removed vertices.
+    pub fn vertex_count(&self) -> usize {
+        self.removed_vertices.iter().filter(|&&r| !r).count()
+    }
+
+    /// The number of faces in the mesh, excluding removed faces.
+    pub fn face_count(&self) -> usize {
+        self.faces.iter().filter(|f| f.is_some()).count()
+    }
+
+    /// An iterator over the vertices in the mesh.
+    pub fn vertices(&self) -> impl Iterator<Item = VertexHandle> + '_ {
+        (0..self.vertices.len())
+            .filter(move |&i| !self.removed_vertices[i])
+            .map(VertexHandle)
+    }
+
+    /// An iterator over the faces in the mesh.
+    pub fn faces(&self) -> impl Iterator<Item = FaceHandle> + '_ {
+        (0..self.faces.len())
+            .filter(move |&i| self.faces[i].is_some())
+            .map(FaceHandle)
+    }
+
+    /// An iterator over the half-edges in the mesh.
+    pub fn half_edges(&self) -> impl Iterator<Item = HalfEdgeHandle> + '_ {
+        self.faces().flat_map(move |f| self.face_half_edges(f))
+    }
+
+    /// The vertex record for a given vertex.
+    pub fn vertex(&self, v: VertexHandle) -> &Vertex<A> {
+        &self.vertices[v.0]
+    }
+
+    /// Mutable access to the vertex record for a given vertex.
+    pub fn vertex_mut(&mut self, v: VertexHandle) -> &mut Vertex<A> {
+        &mut self.vertices[v.0]
+    }
+
+    /// The position of a given vertex.
+    pub fn position(&self, v: VertexHandle) -> Vec3 {
+        self.vertices[v.0].position
+    }
+
+    /// The 3 vertices of a face, in clockwise order when viewed from outside.
+    pub fn face_vertices(&self, f: FaceHandle) -> [VertexHandle; 3] {
+        self.faces[f.0].expect("face has been removed")
+    }
+
+    /// The 3 half-edges of a face, in clockwise order when viewed from
+    /// outside.
+    pub fn face_half_edges(&self, f: FaceHandle) -> [HalfEdgeHandle; 3] {
+        [0, 1, 2].map(|i| HalfEdgeHandle(3 * f.0 + i))
+    }
+
+    /// The normalised outward normal of a face, or None if the face has no
+    /// area.
+    pub fn face_normal(&self, f: FaceHandle) -> Option<Vec3> {
+        let p = self.face_vertices(f).map(|v| self.position(v));
+        face_normal(p.iter().copied()).normalised()
+    }
+
+    /// Find the half-edge running from `a` to `b`, if there is one.
+    pub fn find_half_edge(&self, a: VertexHandle, b: VertexHandle) -> Option<HalfEdgeHandle> {
+        self.half_edges.get(&(a, b)).copied()
+    }
+
+    /// The face which a half-edge borders.
+    pub fn face(&self, h: HalfEdgeHandle) -> FaceHandle {
+        FaceHandle(h.0 / 3)
+    }
+
+    /// The next half-edge around the same face.
+    pub fn next(&self, h: HalfEdgeHandle) -> HalfEdgeHandle {
+        HalfEdgeHandle(h.0 - h.0 % 3 + (h.0 + 1) % 3)
+    }
+
+    /// The previous half-edge around the same face.
+    pub fn prev(&self, h: HalfEdgeHandle) -> HalfEdgeHandle {
+        HalfEdgeHandle(h.0 - h.0 % 3 + (h.0 + 2) % 3)
+    }
+
+    /// The half-edge running in the opposite direction along the same edge,
+    /// or None if the edge is on the boundary of the mesh.
+    pub fn twin(&self, h: HalfEdgeHandle) -> Option<HalfEdgeHandle> {
+        self.twins[h.0]
+    }
+
+    /// The vertex at which a half-edge starts.
+    pub fn start(&self, h: HalfEdgeHandle) -> VertexHandle {
+        self.face_vertices(self.face(h))[h.0 % 3]
+    }
+
+    /// The vertex at which a half-edge ends.
+    pub fn end(&self, h: HalfEdgeHandle) -> VertexHandle {
+        self.start(self.next(h))
+    }
+
+    /// The vertex of the half-edge's face which is not on the half-edge.
+    pub fn opposite_vertex(&self, h: HalfEdgeHandle) -> VertexHandle {
+        self.start(self.prev(h))
+    }
+
+    /// Test if a half-edge lies on the boundary of the mesh.
+    pub fn is_boundary_edge(&self, h: HalfEdgeHandle) -> bool {
+        self.twin(h).is_none()
+    }
+
+    /// Test if a vertex lies on the boundary of the mesh. Isolated vertices
+    /// are not considered to be on the boundary.
+    pub fn is_boundary_vertex(&self, v: VertexHandle) -> bool {
+        self.first_outgoing(v)
+            .is_some_and(|h| self.is_boundary_edge(h))
+    }
+
+    /// An iterator over the half-edges which start at a given vertex, in
+    /// clockwise order when viewed from outside. For vertices on the
+    /// boundary, iteration starts from the boundary.
+    ///
+    /// Vertices where several fans of faces meet at a single point are not
+    /// manifold, and only one of the fans will be visited.
+    pub fn outgoing_half_edges(
+        &self,
+        v: VertexHandle,
+    ) -> impl Iterator<Item = HalfEdgeHandle> + '_ {
+        let first = self.first_outgoing(v);
+        successors(first, move |&h| {
+            self.twin(self.prev(h)).filter(|&t| Some(t) != first)
+        })
+    }
+
+    /// An iterator over the faces surrounding a given vertex, in clockwise
+    /// order when viewed from outside.
+    pub fn vertex_faces(&self, v: VertexHandle) -> impl Iterator<Item = FaceHandle> + '_ {
+        self.outgoing_half_edges(v).map(move |h| self.face(h))
+    }
+
+    /// An iterator over the one-ring of a given vertex (i.e. the vertices
+    /// which share an edge with it), in clockwise order when viewed from
+    /// outside.
+    pub fn vertex_neighbours(&self, v: VertexHandle) -> impl Iterator<Item = VertexHandle> + '_ {
+        self.outgoing_half_edges(v).flat_map(move |h| {
+            // The last face around a boundary vertex contributes both of its
+            // other vertices
+            let prev = self.prev(h);
+            let last = Some(self.start(prev)).filter(|_| self.is_boundary_edge(prev));
+            once(self.end(h)).chain(last)
+        })
+    }
+
+    /// An iterator over the faces which share an edge with a given face.
+    pub fn face_neighbours(&self, f: FaceHandle) -> impl Iterator<Item = FaceHandle> + '_ {
+        IntoIterator::into_iter(self.face_half_edges(f))
+            .filter_map(move |h| self.twin(h).map(|t| self.face(t)))
+    }
+
+    /// Find every loop of boundary half-edges in the mesh. A closed mesh has
+    /// no boundary loops, while each hole in an open mesh produces one loop.
+    pub fn boundary_loops(&self) -> Vec<Vec<HalfEdgeHandle>> {
+        let mut visited = HashSet::new();
+        let mut loops = vec![];
+
+        for h in self.half_edges() {
+            if !self.is_boundary_edge(h) || visited.contains(&h) {
+                continue;
+            }
+
+            let mut boundary = vec![];
+            let mut current = h;
+            while visited.insert(current) {
+                boundary.push(current);
+                current = self.next_boundary_edge(current);
+            }
+            loops.push(boundary);
+        }
+
+        loops
+    }
+
+    /// Split an edge in two, by inserting a new vertex a fraction `factor` of
+    /// the way along the half-edge. The faces on either side of the edge are
+    /// split in two as well.
+    pub fn split_edge(&mut self, h: HalfEdgeHandle, factor: f32) -> VertexHandle
+    where
+        A: Attribute,
+    {
+        let (u, v) = (self.start(h), self.end(h));
+        let vertex = Self::interpolate_vertex(&self.vertices[u.0], &self.vertices[v.0], factor);
+        let m = self.add_vertex(vertex);
+
+        let sides = once(h).chain(self.twin(h)).collect::<Vec<_>>();
+        for &h in &sides {
+            let (a, b, c) = (self.start(h), self.end(h), self.opposite_vertex(h));
+            let face = self.face(h);
+            self.detach_face(face);
+            self.attach_face(face, [a, m, c]);
+            self.add_face(m, b, c);
+        }
+
+        m
+    }
+
+    /// Collapse an edge, by merging the vertex at the start of the half-edge
+    /// into the vertex at the end. The faces on either side of the edge are
+    /// removed.
+    ///
+    /// Returns the surviving vertex, or None (and leaves the mesh untouched)
+    /// if collapsing the edge would make the mesh non-manifold.
+    pub fn collapse_edge(&mut self, h: HalfEdgeHandle) -> Option<VertexHandle> {
+        self.collapse_edge_into(h, self.end(h))
+    }
+
+    /// Collapse an edge, by merging the other vertex on the half-edge into
+    /// `survivor`, which must be one of the vertices on the half-edge. This
+    /// allows either vertex of a boundary edge to survive the collapse.
+    pub fn collapse_edge_into(
+        &mut self,
+        h: HalfEdgeHandle,
+        survivor: VertexHandle,
+    ) -> Option<VertexHandle> {
+        let v = survivor;
+        let u = if self.start(h) == v {
+            self.end(h)
+        } else {
+            self.start(h)
+        };
+        let sides = once(h).chain(self.twin(h)).collect::<Vec<_>>();
+        let opposite = sides
+            .iter()
+            .map(|&h| self.opposite_vertex(h))
+            .collect::<HashSet<_>>();
+        let removed = sides.iter().map(|&h| self.face(h)).collect::<Vec<_>>();
+
+        // The only vertices shared by both one-rings may be those opposite the
+        // edge, or we'd end up with two faces sharing three vertices
+        let ring = self.vertex_neighbours(u).collect::<HashSet<_>>();
+        let shared = self.vertex_neighbours(v).filter(|n| ring.contains(n));
+        if shared.collect::<HashSet<_>>() != opposite {
+            return None;
+        }
+        // Merging two boundaries through an interior edge would pinch the mesh
+        if sides.len() == 2 && self.is_boundary_vertex(u) && self.is_boundary_vertex(v) {
+            return None;
+        }
+
+        // Find replacement outgoing half-edges for the vertices which lose faces
+        let survivors = once(v).chain(opposite.iter().copied()).collect::<Vec<_>>();
+        let replacements = survivors
+            .iter()
+            .map(|&x| {
+                self.outgoing_half_edges(x)
+                    .find(|&o| !removed.contains(&self.face(o)))
+            })
+            .collect::<Vec<_>>();
+
+        let moved = self
+            .vertex_faces(u)
+            .filter(|f| !removed.contains(f))
+            .collect::<Vec<_>>();
+
+        for &face in &removed {
+            self.detach_face(face);
+        }
+        for face in moved {
+            let vertices = self.face_vertices(face).map(|x| if x == u { v } else { x });
+            self.detach_face(face);
+            self.attach_face(face, vertices);
+        }
+
+        for (x, replacement) in survivors.into_iter().zip(replacements) {
+            if !self.is_outgoing(x, self.outgoing[x.0]) {
+                self.outgoing[x.0] = replacement;
+            }
+        }
+        self.removed_vertices[u.0] = true;
+        self.outgoing[u.0] = None;
+
+        Some(v)
+    }
+
+    /// Flip an edge shared by two faces, so that it connects the two vertices
+    /// opposite the edge instead:
+    /// ```text
+    /// *---*      *---*
+    /// |\  |      |  /|
+    /// | \ |  ==> | / |
+    /// |  \|      |/  |
+    /// *---*      *---*
+    /// ```
+    /// Returns false (and leaves the mesh untouched) if the edge is on the
+    /// boundary, or if the flipped edge already exists.
+    pub fn flip_edge(&mut self, h: HalfEdgeHandle) -> bool {
+        let t = match self.twin(h) {
+            Some(t) => t,
+            None => return false,
+        };
+        let (u, v) = (self.start(h), self.end(h));
+        let (c, d) = (self.opposite_vertex(h), self.opposite_vertex(t));
+        if c == d || self.find_half_edge(c, d).is_some() || self.find_half_edge(d, c).is_some() {
+            return false;
+        }
+
+        let (f, g) = (self.face(h), self.face(t));
+        self.detach_face(f);
+        self.detach_face(g);
+        self.attach_face(f, [u, d, c]);
+        self.attach_face(g, [d, v, c]);
+        true
+    }
+
+    /// Output the mesh to an extractor, skipping any removed vertices and
+    /// faces.
+    ///
+    /// Fails without outputting anything if the mesh has more vertices than
+    /// the extractor is able to index.
+    pub fn extract<E>(&self, extractor: &mut E) -> Result<(), Error>
+    where
+        A: Clone,
+        E: VertexExtractor<A>,
+    {
+        let count = self.vertex_count();
+        let maximum = extractor.max_index();
+        if count > 0 && count - 1 > maximum {
+            return Err(Error::IndexOverflow {
+                index: maximum + 1,
+                maximum,
+            });
+        }
+
+        let mut indices = vec![0; self.vertices.len()];
+        for (index, v) in self.vertices().enumerate() {
+            indices[v.0] = index;
+            extractor.extract_vertex(self.vertices[v.0].clone());
+        }
+        for f in self.faces() {
+            for v in &self.face_vertices(f) {
+                extractor.extract_index(indices[v.0]);
+            }
+        }
+        Ok(())
+    }
+
+    // Rotate around the vertex against the order of iteration until we reach
+    // the boundary, or come back to where we started
+    fn first_outgoing(&self, v: VertexHandle) -> Option<HalfEdgeHandle> {
+        let start = self.outgoing[v.0]?;
+        let mut h = start;
+        while let Some(t) = self.twin(h) {
+            h = self.next(t);
+            if h == start {
+                break;
+            }
+        }
+        Some(h)
+    }
+
+    // The boundary half-edge which follows the given boundary half-edge
+    fn next_boundary_edge(&self, h: HalfEdgeHandle) -> HalfEdgeHandle {
+        let mut current = self.next(h);
+        while let Some(t) = self.twin(current) {
+            current = self.next(t);
+        }
+        current
+    }
+
+    fn is_outgoing(&self, v: VertexHandle, h: Option<HalfEdgeHandle>) -> bool {
+        h.is_some_and(|h| self.faces[self.face(h).0].is_some() && self.start(h) == v)
+    }
+
+    fn attach_face(&mut self, face: FaceHandle, vertices: [VertexHandle; 3]) {
+        self.faces[face.0] = Some(vertices);
+        for (i, &h) in self.face_half_edges(face).iter().enumerate() {
+            let (a, b) = (vertices[i], vertices[(i + 1) % 3]);
+            self.half_edges.insert((a, b), h);
+            if let Some(&t) = self.half_edges.get(&(b, a)) {
+                self.twins[h.0] = Some(t);
+                self.twins[t.0] = Some(h);
+            }
+            self.outgoing[a.0] = Some(h);
+        }
+    }
+
+    fn detach_face(&mut self, face: FaceHandle) {
+        for h in self.face_half_edges(face) {
+            self.half_edges.remove(&(self.start(h), self.end(h)));
+            if let Some(t) = self.twins[h.0].take() {
+                self.twins[t.0] = None;
+            }
+        }
+        self.faces[face.0] = None;
+    }
+
+    fn interpolate_vertex(a: &Vertex<A>, b: &Vertex<A>, factor: f32) -> Vertex<A>
+    where
+        A: Attribute,
+    {
+        let normal = match (a.normal, b.normal) {
+            (Some(n), Some(m)) => n.lerp(m, factor).normalised(),
+            _ => None,
+        };
+        Vertex {
+            position: a.position.lerp(b.position, factor),
+            normal,
+            origin: VertexOrigin::Cell {
+                min: a.position.min(b.position),
+                max: a.position.max(b.position),
+            },
+            feature: a.feature && b.feature,
+            attributes: a.attributes.lerp(&b.attributes, factor),
+        }
+    }
+}
+
+impl<A> Default for HalfEdgeMesh<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> VertexExtractor<A> for HalfEdgeMesh<A> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        self.add_vertex(vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.pending_indices.push(index);
+        if let [a, b, c] = self.pending_indices[..] {
+            self.pending_indices.clear();
+            if self
+                .add_face(VertexHandle(a), VertexHandle(b), VertexHandle(c))
+                .is_none()
+            {
+                self.rejected_faces += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed, extractor::IndexedVertices, fixtures::ball, sampler::Sampler,
+        MarchingCubes,
+    };
+
+    fn grid(size: usize) -> HalfEdgeMesh {
+        let mut mesh = HalfEdgeMesh::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                let p = Vec3::new(x as f32, y as f32, 0.0);
+                mesh.add_vertex(Vertex::new(p, VertexOrigin::Cell { min: p, max: p }));
+            }
+        }
+        let v = |x: usize, y: usize| VertexHandle(y * (size + 1) + x);
+        for y in 0..size {
+            for x in 0..size {
+                mesh.add_face(v(x, y), v(x + 1, y), v(x + 1, y + 1));
+                mesh.add_face(v(x, y), v(x + 1, y + 1), v(x, y + 1));
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn test_connectivity() {
+        let mut mesh = grid(3);
+        assert_eq!(mesh.vertex_count(), 16);
+        assert_eq!(mesh.face_count(), 18);
+
+        let center = VertexHandle(5);
+        assert!(!mesh.is_boundary_vertex(center));
+        assert_eq!(mesh.vertex_neighbours(center).count(), 6);
+        assert_eq!(mesh.vertex_faces(center).count(), 6);
+
+        let corner = VertexHandle(3);
+        assert!(mesh.is_boundary_vertex(corner));
+        let ring = mesh.vertex_neighbours(corner).collect::<Vec<_>>();
+        assert_eq!(ring, vec![VertexHandle(7), VertexHandle(2)]);
+
+        let loops = mesh.boundary_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].len(), 12);
+
+        // Degenerate and non-manifold faces are rejected
+        assert!(mesh.add_face(center, center, corner).is_none());
+        assert!(mesh
+            .add_face(VertexHandle(0), VertexHandle(1), VertexHandle(5))
+            .is_none());
+
+        let h = mesh
+            .find_half_edge(VertexHandle(0), VertexHandle(5))
+            .unwrap();
+        assert!(mesh.flip_edge(h));
+        assert!(mesh
+            .find_half_edge(VertexHandle(0), VertexHandle(5))
+            .is_none());
+        assert!(mesh
+            .find_half_edge(VertexHandle(1), VertexHandle(4))
+            .is_some());
+        assert!(!mesh.flip_edge(
+            mesh.find_half_edge(VertexHandle(0), VertexHandle(1))
+                .unwrap()
+        ));
+
+        let h = mesh
+            .find_half_edge(VertexHandle(5), VertexHandle(6))
+            .unwrap();
+        let m = mesh.split_edge(h, 0.5);
+        assert_eq!(mesh.position(m), Vec3::new(1.5, 1.0, 0.0));
+        assert_eq!(mesh.vertex_neighbours(m).count(), 4);
+        assert_eq!(mesh.face_count(), 20);
+
+        let h = mesh.find_half_edge(m, VertexHandle(6)).unwrap();
+        assert_eq!(mesh.collapse_edge(h), Some(VertexHandle(6)));
+        assert_eq!(mesh.vertex_count(), 16);
+        assert_eq!(mesh.face_count(), 18);
+        assert_eq!(mesh.boundary_loops()[0].len(), 12);
+        // The grid winds clockwise when viewed from below
+        for f in mesh.faces() {
+            assert!(mesh.face_normal(f).unwrap().z < 0.0);
+        }
+
+        // Collapsing an interior edge between two boundary vertices would
+        // pinch the mesh
+        let h = mesh
+            .find_half_edge(VertexHandle(1), VertexHandle(4))
+            .unwrap();
+        assert_eq!(mesh.collapse_edge(h), None);
+    }
+
+    #[test]
+    fn test_extract_index_overflow() {
+        // Can index at most ten vertices
+        #[derive(Default)]
+        struct Small(usize, usize);
+
+        impl VertexExtractor for Small {
+            fn extract_vertex(&mut self, _: Vertex) {
+                self.0 += 1;
+            }
+
+            fn extract_index(&mut self, _: usize) {
+                self.1 += 1;
+            }
+
+            fn max_index(&self) -> usize {
+                9
+            }
+        }
+
+        let mut small = Small::default();
+        assert_eq!(
+            grid(3).extract(&mut small),
+            Err(Error::IndexOverflow {
+                index: 10,
+                maximum: 9
+            })
+        );
+        assert_eq!((small.0, small.1), (0, 0));
+
+        let mut small = Small::default();
+        grid(2).extract(&mut small).unwrap();
+        assert_eq!((small.0, small.1), (9, 24));
+    }
+
+    #[test]
+    fn test_extracted_mesh() {
+        let ball = ball();
+        let sampler = Sampler::new(&ball);
+
+        let mut mesh = HalfEdgeMesh::new();
+        MarchingCubes::<Signed>::new(16)
+            .unwrap()
+            .extract(&sampler, &mut mesh)
+            .unwrap();
+
+        assert_eq!(mesh.rejected_faces(), 0);
+        assert!(mesh.boundary_loops().is_empty());
+        // A closed mesh of genus 0 has an Euler characteristic of 2
+        let edges = mesh.half_edges().count() / 2;
+        assert_eq!(mesh.vertex_count() + mesh.face_count() - edges, 2);
+
+        // Every face points away from the center of the ball
+        for f in mesh.faces() {
+            let p = mesh.face_vertices(f).map(|v| mesh.position(v));
+            let outward = (p[0] + p[1] + p[2]) / 3.0 - Vec3::from_scalar(0.5);
+            assert!(mesh.face_normal(f).unwrap().dot(outward) > 0.0);
+        }
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        mesh.extract(&mut IndexedVertices::new(&mut vertices, &mut indices))
+            .unwrap();
+        assert_eq!(vertices.len(), mesh.vertex_count() * 3);
+        assert_eq!(indices.len(), mesh.face_count() * 3);
+    }
+}
@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::{Compile, Compiler, Point, Register},
     distance::{Directed, Signed},
-    math::Vec3,
-    source::{HermiteSource, ScalarSource, VectorSource},
+    math::{Interval, Vec3},
+    source::{AttributeSource, HermiteSource, IntervalSource, ScalarSource, VectorSource},
 };
 
 /// The CSG union operation. An implicit function that is solid where either of
@@ -44,12 +45,43 @@ impl<A: VectorSource, B: VectorSource> VectorSource for Union<A, B> {
     }
 }
 
+impl<A: IntervalSource, B: IntervalSource> IntervalSource for Union<A, B> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.a
+            .sample_interval(min, max)
+            .min(self.b.sample_interval(min, max))
+    }
+}
+
+impl<A: Compile, B: Compile> Compile for Union<A, B> {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let a = self.a.compile(compiler, p);
+        let b = self.b.compile(compiler, p);
+        compiler.min(a, b)
+    }
+}
+
 impl<A: HermiteSource, B: HermiteSource> HermiteSource for Union<A, B> {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
         self.a.sample_normal(p).min(self.b.sample_normal(p))
     }
 }
 
+/// Attributes are taken from whichever function is nearest the surface.
+impl<T, A, B> AttributeSource<T> for Union<A, B>
+where
+    A: ScalarSource + AttributeSource<T>,
+    B: ScalarSource + AttributeSource<T>,
+{
+    fn sample_attribute(&self, p: Vec3) -> T {
+        if self.a.sample_scalar(p).0 <= self.b.sample_scalar(p).0 {
+            self.a.sample_attribute(p)
+        } else {
+            self.b.sample_attribute(p)
+        }
+    }
+}
+
 /// The CSG intersection operation. An implicit function that is solid only
 /// where both of the provided implicit functions are solid.
 pub struct Intersection<A, B> {
@@ -77,6 +109,37 @@ impl<A: VectorSource, B: VectorSource> VectorSource for Intersection<A, B> {
     }
 }
 
+impl<A: IntervalSource, B: IntervalSource> IntervalSource for Intersection<A, B> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.a
+            .sample_interval(min, max)
+            .max(self.b.sample_interval(min, max))
+    }
+}
+
+impl<A: Compile, B: Compile> Compile for Intersection<A, B> {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let a = self.a.compile(compiler, p);
+        let b = self.b.compile(compiler, p);
+        compiler.max(a, b)
+    }
+}
+
+/// Attributes are taken from whichever function bounds the intersection.
+impl<T, A, B> AttributeSource<T> for Intersection<A, B>
+where
+    A: ScalarSource + AttributeSource<T>,
+    B: ScalarSource + AttributeSource<T>,
+{
+    fn sample_attribute(&self, p: Vec3) -> T {
+        if self.a.sample_scalar(p).0 >= self.b.sample_scalar(p).0 {
+            self.a.sample_attribute(p)
+        } else {
+            self.b.sample_attribute(p)
+        }
+    }
+}
+
 /// The CSG difference operation. Subtracts the first provided implicit function
 /// from the second, i.e. the result is solid where the second
 /// function is solid, except where the first is solid.
@@ -105,18 +168,57 @@ impl<A: VectorSource, B: VectorSource> VectorSource for Difference<A, B> {
     }
 }
 
+impl<A: IntervalSource, B: IntervalSource> IntervalSource for Difference<A, B> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.b
+            .sample_interval(min, max)
+            .max(-self.a.sample_interval(min, max))
+    }
+}
+
+impl<A: Compile, B: Compile> Compile for Difference<A, B> {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let a = self.a.compile(compiler, p);
+        let b = self.b.compile(compiler, p);
+        let not_a = compiler.neg(a);
+        compiler.max(b, not_a)
+    }
+}
+
+/// Attributes are taken from the second function, except where the first
+/// function carves into it, which takes the attributes of the first function.
+impl<T, A, B> AttributeSource<T> for Difference<A, B>
+where
+    A: ScalarSource + AttributeSource<T>,
+    B: ScalarSource + AttributeSource<T>,
+{
+    fn sample_attribute(&self, p: Vec3) -> T {
+        if self.b.sample_scalar(p).0 >= -self.a.sample_scalar(p).0 {
+            self.b.sample_attribute(p)
+        } else {
+            self.a.sample_attribute(p)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::implicit::RectangularPrism;
-    use std::f32::MAX;
+    use crate::{
+        distance::Signed,
+        extractor::{Vertex, VertexExtractor, WithAttributes},
+        implicit::{RectangularPrism, Sphere},
+        sampler::Sampler,
+        source::Painted,
+        MarchingCubes,
+    };
 
     #[test]
     fn test_csg() {
         let a = RectangularPrism::new(Vec3::new(4.0, 4.0, 1.0));
         let b = RectangularPrism::new(Vec3::new(2.0, 2.0, 4.0));
 
-        let u = Union::new(a.clone(), b.clone());
+        let u = Union::new(a, b);
 
         assert_eq!(u.sample_scalar(Vec3::zero()).0, -2.0);
         assert_eq!(u.sample_scalar(Vec3::new(4.0, 4.0, 1.0)).0, 0.0);
@@ -127,11 +229,11 @@ mod tests {
         assert_eq!(u.sample_vector(Vec3::new(4.0, 4.0, 1.0)).0, Vec3::zero());
         assert_eq!(
             u.sample_vector(Vec3::new(0.0, 0.0, 8.0)).0,
-            Vec3::new(MAX, MAX, 4.0)
+            Vec3::new(f32::MAX, f32::MAX, 4.0)
         );
         assert_eq!(
             u.sample_vector(Vec3::new(8.0, 0.0, 0.0)).0,
-            Vec3::new(4.0, MAX, MAX)
+            Vec3::new(4.0, f32::MAX, f32::MAX)
         );
 
         assert_eq!(
@@ -147,7 +249,7 @@ mod tests {
             Vec3::new(1.0, 0.0, 0.0)
         );
 
-        let i = Intersection::new(a.clone(), b.clone());
+        let i = Intersection::new(a, b);
 
         assert_eq!(i.sample_scalar(Vec3::zero()).0, -1.0);
         assert_eq!(i.sample_scalar(Vec3::new(2.0, 2.0, 1.0)).0, 0.0);
@@ -158,11 +260,53 @@ mod tests {
         assert_eq!(i.sample_vector(Vec3::new(2.0, 2.0, 1.0)).0, Vec3::zero());
         assert_eq!(
             i.sample_vector(Vec3::new(0.0, 0.0, 8.0)).0,
-            Vec3::new(MAX, MAX, 7.0)
+            Vec3::new(f32::MAX, f32::MAX, 7.0)
         );
         assert_eq!(
             i.sample_vector(Vec3::new(8.0, 0.0, 0.0)).0,
-            Vec3::new(6.0, MAX, MAX)
+            Vec3::new(6.0, f32::MAX, f32::MAX)
         );
+
+        let d = Difference::new(b, a);
+        let bounds = d.sample_interval(Vec3::new(3.0, 3.0, -0.5), Vec3::new(3.5, 3.5, 0.5));
+        assert!(bounds.is_negative());
+        assert!(bounds.contains(d.sample_scalar(Vec3::new(3.25, 3.25, 0.0)).0));
+    }
+
+    struct Paint(Vec<u8>);
+
+    impl VertexExtractor<u8> for Paint {
+        fn extract_vertex(&mut self, vertex: Vertex<u8>) {
+            self.0.push(vertex.attributes);
+        }
+
+        fn extract_index(&mut self, _: usize) {}
+    }
+
+    #[test]
+    fn test_attribute_propagation() {
+        let sphere = || Painted::new(Sphere::new(0.8), 1u8);
+        let carver = || Painted::new(RectangularPrism::new(Vec3::new(0.3, 0.3, 2.0)), 2u8);
+
+        let u = Union::new(sphere(), carver());
+        assert_eq!(u.sample_attribute(Vec3::new(0.8, 0.0, 0.0)), 1);
+        assert_eq!(u.sample_attribute(Vec3::new(0.0, 0.0, 2.0)), 2);
+
+        let i = Intersection::new(sphere(), carver());
+        assert_eq!(i.sample_attribute(Vec3::new(0.3, 0.0, 0.0)), 2);
+        assert_eq!(i.sample_attribute(Vec3::new(0.0, 0.0, 0.8)), 1);
+
+        // The carved sphere keeps its own paint, except on the carved walls
+        let d = Difference::new(carver(), sphere());
+        assert_eq!(d.sample_attribute(Vec3::new(0.8, 0.0, 0.0)), 1);
+        assert_eq!(d.sample_attribute(Vec3::new(0.3, 0.0, 0.0)), 2);
+
+        let sampler = Sampler::new(&d);
+        let mut paint = Paint(vec![]);
+        MarchingCubes::<Signed>::new(32)
+            .unwrap()
+            .extract(&sampler, &mut WithAttributes::new(&sampler, &mut paint))
+            .unwrap();
+        assert!(paint.0.contains(&1) && paint.0.contains(&2));
     }
 }
@@ -12,11 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::{Compile, Compiler, Point, Register},
     distance::{Directed, Signed},
-    math::Vec3,
-    source::{HermiteSource, ScalarSource, VectorSource},
+    math::{Interval, Vec3},
+    source::{HermiteSource, IntervalSource, ScalarSource, VectorSource},
 };
-use std::f32::MAX;
 
 /// A capped cylinder.
 pub struct Cylinder {
@@ -53,17 +53,17 @@ impl VectorSource for Cylinder {
         let a = p.abs();
         Directed(Vec3::new(
             if a.z > self.half_length || a.y > self.radius {
-                MAX
+                f32::MAX
             } else {
                 a.x - (self.radius * self.radius - a.y * a.y).sqrt()
             },
             if a.z > self.half_length || a.x > self.radius {
-                MAX
+                f32::MAX
             } else {
                 a.y - (self.radius * self.radius - a.x * a.x).sqrt()
             },
             if a.xy().len_sq() > self.radius * self.radius {
-                MAX
+                f32::MAX
             } else {
                 a.z - self.half_length
             },
@@ -71,6 +71,39 @@ impl VectorSource for Cylinder {
     }
 }
 
+impl IntervalSource for Cylinder {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let x = Interval::new(min.x, max.x);
+        let y = Interval::new(min.y, max.y);
+        let z = Interval::new(min.z, max.z);
+        let zero = Interval::point(0.0);
+
+        let q_x = (x.square() + y.square()).sqrt() - self.radius;
+        let q_z = z.abs() - self.half_length;
+        let outside = (q_x.max(zero).square() + q_z.max(zero).square()).sqrt();
+        q_x.max(q_z).min(zero) + outside
+    }
+}
+
+impl Compile for Cylinder {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let len_xy = compiler.length(&[p.x, p.y]);
+        let q_x = compiler.sub_constant(len_xy, self.radius);
+        let abs_z = compiler.abs(p.z);
+        let q_z = compiler.sub_constant(abs_z, self.half_length);
+
+        let zero = compiler.constant(0.0);
+        let inside = compiler.max(q_x, q_z);
+        let inside = compiler.min(inside, zero);
+
+        let d_x = compiler.max(q_x, zero);
+        let d_z = compiler.max(q_z, zero);
+        let outside = compiler.length(&[d_x, d_z]);
+
+        compiler.add(inside, outside)
+    }
+}
+
 impl HermiteSource for Cylinder {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
         let z = p.z.abs() / self.half_length;
@@ -116,11 +149,11 @@ mod tests {
         );
         assert_eq!(
             cylinder.sample_vector(Vec3::new(0.0, 0.0, 8.0)).0,
-            Vec3::new(MAX, MAX, 4.0)
+            Vec3::new(f32::MAX, f32::MAX, 4.0)
         );
         assert_eq!(
             cylinder.sample_vector(Vec3::new(8.0, 0.0, 0.0)).0,
-            Vec3::new(6.0, MAX, MAX)
+            Vec3::new(6.0, f32::MAX, f32::MAX)
         );
 
         assert_eq!(
@@ -137,5 +170,12 @@ mod tests {
                 .unwrap(),
             Vec3::new(1.0, 0.0, 0.0)
         );
+
+        assert!(cylinder
+            .sample_interval(Vec3::new(1.0, -1.0, 3.0), Vec3::new(3.0, 1.0, 5.0))
+            .contains(0.0));
+        assert!(cylinder
+            .sample_interval(Vec3::new(4.0, 4.0, 4.0), Vec3::new(5.0, 5.0, 5.0))
+            .is_positive());
     }
 }
@@ -12,11 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::{Compile, Compiler, Point, Register},
     distance::{Directed, Signed},
-    math::Vec3,
-    source::{HermiteSource, ScalarSource, VectorSource},
+    math::{Interval, Vec3},
+    source::{HermiteSource, IntervalSource, ScalarSource, VectorSource},
 };
-use std::f32::MAX;
 
 /// A rectangular prism, or box.
 #[derive(Copy, Clone)]
@@ -60,7 +60,7 @@ impl VectorSource for RectangularPrism {
             } else {
                 -1.0
             },
-        ) * MAX;
+        ) * f32::MAX;
         // The closest point on a box is just the point clamped to the box bounds
         let closest_point_on_cube =
             if a.x < self.half_extent.x && a.y < self.half_extent.y && a.z < self.half_extent.z {
@@ -73,6 +73,38 @@ impl VectorSource for RectangularPrism {
     }
 }
 
+impl IntervalSource for RectangularPrism {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let zero = Interval::point(0.0);
+        let q = [0, 1, 2].map(|i| Interval::new(min[i], max[i]).abs() - self.half_extent[i]);
+
+        let outside =
+            (q[0].max(zero).square() + q[1].max(zero).square() + q[2].max(zero).square()).sqrt();
+        outside + q[0].max(q[1]).max(q[2]).min(zero)
+    }
+}
+
+impl Compile for RectangularPrism {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let zero = compiler.constant(0.0);
+
+        let mut q = [p.x, p.y, p.z];
+        let mut d = [zero; 3];
+        for i in 0..3 {
+            let abs = compiler.abs(q[i]);
+            q[i] = compiler.sub_constant(abs, self.half_extent[i]);
+            d[i] = compiler.max(q[i], zero);
+        }
+
+        let outside = compiler.length(&d);
+        let max_q = compiler.max(q[1], q[2]);
+        let max_q = compiler.max(q[0], max_q);
+        let inside = compiler.min(max_q, zero);
+
+        compiler.add(outside, inside)
+    }
+}
+
 impl HermiteSource for RectangularPrism {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
         p.clamp_to_cardinal_axis()
@@ -102,15 +134,15 @@ mod tests {
         );
         assert_eq!(
             prism.sample_vector(Vec3::new(0.0, 0.0, 8.0)).0,
-            Vec3::new(MAX, MAX, 4.0)
+            Vec3::new(f32::MAX, f32::MAX, 4.0)
         );
         assert_eq!(
             prism.sample_vector(Vec3::new(8.0, 0.0, 0.0)).0,
-            Vec3::new(7.0, MAX, MAX)
+            Vec3::new(7.0, f32::MAX, f32::MAX)
         );
         assert_eq!(
             prism.sample_vector(Vec3::new(8.0, 8.0, 8.0)).0,
-            Vec3::from_scalar(MAX)
+            Vec3::from_scalar(f32::MAX)
         );
 
         assert_eq!(
@@ -127,5 +159,13 @@ mod tests {
                 .unwrap(),
             Vec3::new(1.0, 0.0, 0.0)
         );
+
+        assert_eq!(
+            prism.sample_interval(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 8.0)),
+            Interval::new(1.0, 4.0)
+        );
+        assert!(prism
+            .sample_interval(Vec3::from_scalar(-0.5), Vec3::from_scalar(0.5))
+            .is_negative());
     }
 }
@@ -12,11 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::{Compile, Compiler, Point, Register},
     distance::{Directed, Signed},
-    math::Vec3,
-    source::{HermiteSource, ScalarSource, VectorSource},
+    math::{Interval, Vec3},
+    source::{HermiteSource, IntervalSource, ScalarSource, VectorSource},
 };
-use std::f32::MAX;
 
 /// A sphere.
 #[derive(Copy, Clone)]
@@ -49,13 +49,41 @@ impl VectorSource for Sphere {
         let l_xy = r2 - a.xy().len_sq();
 
         Directed(Vec3::new(
-            if l_yz < 0.0 { MAX } else { a.x - l_yz.sqrt() },
-            if l_xz < 0.0 { MAX } else { a.y - l_xz.sqrt() },
-            if l_xy < 0.0 { MAX } else { a.z - l_xy.sqrt() },
+            if l_yz < 0.0 {
+                f32::MAX
+            } else {
+                a.x - l_yz.sqrt()
+            },
+            if l_xz < 0.0 {
+                f32::MAX
+            } else {
+                a.y - l_xz.sqrt()
+            },
+            if l_xy < 0.0 {
+                f32::MAX
+            } else {
+                a.z - l_xy.sqrt()
+            },
         ))
     }
 }
 
+impl IntervalSource for Sphere {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let x = Interval::new(min.x, max.x);
+        let y = Interval::new(min.y, max.y);
+        let z = Interval::new(min.z, max.z);
+        (x.square() + y.square() + z.square()).sqrt() - self.radius
+    }
+}
+
+impl Compile for Sphere {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let len = compiler.length(&[p.x, p.y, p.z]);
+        compiler.sub_constant(len, self.radius)
+    }
+}
+
 impl HermiteSource for Sphere {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
         p
@@ -85,11 +113,11 @@ mod tests {
         );
         assert_eq!(
             sphere.sample_vector(Vec3::new(0.0, 0.0, 8.0)).0,
-            Vec3::new(MAX, MAX, 6.0)
+            Vec3::new(f32::MAX, f32::MAX, 6.0)
         );
         assert_eq!(
             sphere.sample_vector(Vec3::new(8.0, 0.0, 0.0)).0,
-            Vec3::new(6.0, MAX, MAX)
+            Vec3::new(6.0, f32::MAX, f32::MAX)
         );
 
         assert_eq!(
@@ -106,5 +134,14 @@ mod tests {
                 .unwrap(),
             Vec3::new(1.0, 0.0, 0.0)
         );
+
+        assert_eq!(
+            sphere.sample_interval(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)),
+            Interval::new(-2.0, 3.0f32.sqrt() - 2.0)
+        );
+        assert_eq!(
+            sphere.sample_interval(Vec3::new(3.0, 0.0, 0.0), Vec3::new(4.0, 0.0, 0.0)),
+            Interval::new(1.0, 2.0)
+        );
     }
 }
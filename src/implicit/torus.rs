@@ -12,11 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::{Compile, Compiler, Point, Register},
     distance::{Directed, Signed},
-    math::{Vec2, Vec3},
-    source::{HermiteSource, ScalarSource, VectorSource},
+    math::{Interval, Vec2, Vec3},
+    source::{HermiteSource, IntervalSource, ScalarSource, VectorSource},
 };
-use std::f32::MAX;
 
 /// A torus, or doughnut-shape.
 #[derive(Copy, Clone)]
@@ -73,21 +73,21 @@ impl VectorSource for Torus {
         );
         Directed(Vec3::new(
             if a.z > self.tube_radius || a.y > self.radius + tube_radius_at_z {
-                MAX
+                f32::MAX
             } else if a.x == 0.0 {
                 (a.y - self.radius).abs() - self.tube_radius
             } else {
                 (a.x - (r.x * r.x - a.y * a.y).sqrt()).max((r.y * r.y - a.y * a.y).sqrt() - a.x)
             },
             if a.z > self.tube_radius || a.x > self.radius + tube_radius_at_z {
-                MAX
+                f32::MAX
             } else if a.y == 0.0 {
                 (a.x - self.radius).abs() - self.tube_radius
             } else {
                 (a.y - (r.x * r.x - a.x * a.x).sqrt()).max((r.y * r.y - a.x * a.x).sqrt() - a.y)
             },
             if l_xy.abs() > self.tube_radius {
-                MAX
+                f32::MAX
             } else {
                 a.z - (self.tube_radius * self.tube_radius - l_xy * l_xy).sqrt()
             },
@@ -95,6 +95,25 @@ impl VectorSource for Torus {
     }
 }
 
+impl IntervalSource for Torus {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        let x = Interval::new(min.x, max.x);
+        let y = Interval::new(min.y, max.y);
+        let z = Interval::new(min.z, max.z);
+        let q_x = (x.square() + y.square()).sqrt() - self.radius;
+        (q_x.square() + z.square()).sqrt() - self.tube_radius
+    }
+}
+
+impl Compile for Torus {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        let len_xy = compiler.length(&[p.x, p.y]);
+        let q_x = compiler.sub_constant(len_xy, self.radius);
+        let len = compiler.length(&[q_x, p.z]);
+        compiler.sub_constant(len, self.tube_radius)
+    }
+}
+
 impl HermiteSource for Torus {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
         // Find the closest point on the major radius of the torus
@@ -123,7 +142,7 @@ mod tests {
 
         assert_eq!(
             torus.sample_vector(Vec3::zero()).0,
-            Vec3::new(6.0, 6.0, MAX)
+            Vec3::new(6.0, 6.0, f32::MAX)
         );
         assert_eq!(
             torus.sample_vector(Vec3::new(8.0, 0.0, 0.0)).0,
@@ -135,23 +154,23 @@ mod tests {
         );
         assert_eq!(
             torus.sample_vector(Vec3::new(12.0, 0.0, 0.0)).0,
-            Vec3::new(2.0, MAX, MAX)
+            Vec3::new(2.0, f32::MAX, f32::MAX)
         );
         assert_eq!(
             torus.sample_vector(Vec3::new(12.0, 12.0, 0.0)).0,
-            Vec3::from_scalar(MAX)
+            Vec3::from_scalar(f32::MAX)
         );
         assert_eq!(
             torus.sample_vector(Vec3::new(9.0, 9.0, 0.0)).0,
-            Vec2::from_scalar(9.0 - (10.0 * 10.0 - 9.0 * 9.0f32).sqrt()).extend(MAX)
+            Vec2::from_scalar(9.0 - (10.0 * 10.0 - 9.0 * 9.0f32).sqrt()).extend(f32::MAX)
         );
         assert_eq!(
             torus.sample_vector(Vec3::new(2.0, 2.0, 0.0)).0,
-            Vec2::from_scalar((6.0 * 6.0 - 2.0 * 2.0f32).sqrt() - 2.0).extend(MAX)
+            Vec2::from_scalar((6.0 * 6.0 - 2.0 * 2.0f32).sqrt() - 2.0).extend(f32::MAX)
         );
         assert_eq!(
             torus.sample_vector(Vec3::new(8.0, 0.0, 8.0)).0,
-            Vec3::new(MAX, MAX, 6.0)
+            Vec3::new(f32::MAX, f32::MAX, 6.0)
         );
 
         assert_eq!(
@@ -168,5 +187,13 @@ mod tests {
                 .unwrap(),
             Vec3::new(1.0, 0.0, 0.0)
         );
+
+        assert_eq!(
+            torus.sample_interval(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)),
+            Interval::new(6.0 - 2.0f32.sqrt(), 65.0f32.sqrt() - 2.0)
+        );
+        assert!(torus
+            .sample_interval(Vec3::new(9.0, -1.0, -1.0), Vec3::new(11.0, 1.0, 1.0))
+            .contains(0.0));
     }
 }
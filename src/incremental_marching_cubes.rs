@@ -0,0 +1,420 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    distance::Distance,
+    error::Error,
+    marching_cubes_impl::{classify_corners, march_cube},
+    marching_cubes_tables::{CORNERS, EDGE_CONNECTION},
+    math::Vec3,
+    sampler::{sample_finite, Sample},
+};
+use std::ops::Range;
+
+// Marks a grid edge without a vertex
+const NONE: u32 = u32::MAX;
+
+/// The parts of the vertex and index buffers changed by an update of
+/// [IncrementalMarchingCubes].
+///
+/// Ranges are clamped to the current length of each buffer. If a buffer has
+/// shrunk, the entries beyond its new length should be discarded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangedRanges {
+    /// The vertices which were added or moved.
+    pub vertices: Range<usize>,
+    /// The indices which were added or rewritten.
+    pub indices: Range<usize>,
+}
+
+/// Convert isosurfaces to meshes using marching cubes, and keep the mesh up
+/// to date as the isosurface is edited.
+///
+/// Extraction works as per [MarchingCubes](crate::MarchingCubes), but the
+/// samples, vertices and triangles are retained. When part of the source is
+/// edited, [update](IncrementalMarchingCubes::update) resamples only the grid
+/// points within the edited box, and re-marches only the cells touching them.
+///
+/// Each vertex belongs to the grid edge it lies on, and keeps its index for as
+/// long as the surface crosses that edge. Cells outside the edited box which
+/// share an edge with it therefore continue to reference the same vertex, and
+/// the mesh stays connected across the boundary of the edit. The vertex and
+/// index buffers are patched in place, and each update reports which ranges of
+/// them changed, so that they may be re-uploaded piecemeal.
+///
+/// Removed vertices leave holes in the vertex buffer, which are reused by
+/// later updates. Holes aren't referenced by any triangle, but their positions
+/// are left unchanged.
+pub struct IncrementalMarchingCubes<D: Distance> {
+    size: usize,
+    values: Vec<D>,
+    positions: Vec<Vec3>,
+    indices: Vec<u32>,
+    // The vertex on each grid edge, indexed by (x, y, z, axis)
+    edge_vertices: Vec<u32>,
+    free_vertices: Vec<u32>,
+    // The triangles generated by each cell, and the cell which generated each
+    // triangle, so that triangles can be removed by swapping with the last
+    cell_triangles: Vec<([u32; 5], usize)>,
+    triangle_cells: Vec<usize>,
+    extracted: bool,
+    // New samples, held until every sample succeeds
+    scratch: Vec<D>,
+}
+
+impl<D: Distance> IncrementalMarchingCubes<D> {
+    /// Create a new IncrementalMarchingCubes with the given chunk size.
+    ///
+    /// For a given `size`, this will evaluate chunks of `size^3` voxels.
+    pub fn new(size: usize) -> Result<Self, Error> {
+        if size < 2 {
+            return Err(Error::InvalidSize { size, minimum: 2 });
+        }
+        let cells = (size - 1) * (size - 1) * (size - 1);
+
+        Ok(Self {
+            size,
+            values: vec![D::zero(); size * size * size],
+            positions: vec![],
+            indices: vec![],
+            edge_vertices: vec![NONE; size * size * size * 3],
+            free_vertices: vec![],
+            cell_triangles: vec![([0; 5], 0); cells],
+            triangle_cells: vec![],
+            extracted: false,
+            scratch: vec![],
+        })
+    }
+
+    /// The position of each vertex in the mesh.
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// The indices of the mesh, with each triplet of indices forming one
+    /// triangle.
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// Extract the whole mesh from the given [Sample], discarding any
+    /// previous mesh.
+    ///
+    /// The Source will be sampled in the range (0,0,0) to (1,1,1), with the
+    /// number of steps determined by the size provided to the constructor.
+    pub fn extract<S>(&mut self, source: &S) -> Result<ChangedRanges, Error>
+    where
+        S: Sample<D>,
+    {
+        self.update(source, Vec3::zero(), Vec3::from_scalar(1.0))
+    }
+
+    /// Update the mesh after the source has changed within the box from `min`
+    /// to `max`.
+    ///
+    /// Every grid point within the box is resampled, and every cell touching
+    /// one of those points is re-marched. The source must not have changed
+    /// outside the box. The first update after construction resamples the
+    /// whole grid, regardless of the box.
+    ///
+    /// If the source produces a non-finite distance, the mesh is left as it
+    /// was before the update.
+    pub fn update<S>(&mut self, source: &S, min: Vec3, max: Vec3) -> Result<ChangedRanges, Error>
+    where
+        S: Sample<D>,
+    {
+        let n = self.size;
+        let (lower, upper) = if self.extracted {
+            let scale = (n - 1) as f32;
+            let floor = |v: f32| ((v * scale).floor().max(0.0) as usize).min(n - 1);
+            let ceil = |v: f32| ((v * scale).ceil().max(0.0) as usize).min(n - 1);
+            (
+                [floor(min.x), floor(min.y), floor(min.z)],
+                [ceil(max.x), ceil(max.y), ceil(max.z)],
+            )
+        } else {
+            ([0; 3], [n - 1; 3])
+        };
+        if (0..3).any(|i| lower[i] > upper[i]) {
+            return Ok(ChangedRanges::default());
+        }
+
+        // Sample everything before changing anything, so that an error leaves
+        // the mesh intact
+        self.scratch.clear();
+        for p in points(lower, upper) {
+            let value = sample_finite(source, self.point(p))?;
+            self.scratch.push(value);
+        }
+        for (p, i) in points(lower, upper).zip(0..) {
+            let index = self.point_index(p);
+            self.values[index] = self.scratch[i];
+        }
+        self.extracted = true;
+
+        let mut changed_vertices = None;
+        self.update_edges(lower, upper, &mut changed_vertices);
+
+        // Re-march every cell with a corner among the resampled points
+        let cell_lower = lower.map(|l| l.saturating_sub(1));
+        let cell_upper = upper.map(|u| u.min(n - 2));
+        let mut first_triangle = self.triangle_cells.len();
+        for cell in points(cell_lower, cell_upper) {
+            first_triangle = first_triangle.min(self.remove_cell(cell));
+        }
+        first_triangle = first_triangle.min(self.triangle_cells.len());
+        for cell in points(cell_lower, cell_upper) {
+            self.march_cell(cell);
+        }
+
+        let vertex_count = self.positions.len();
+        let index_count = self.indices.len();
+        Ok(ChangedRanges {
+            vertices: changed_vertices
+                .map_or(0..0, |r: Range<usize>| r.start..r.end.min(vertex_count)),
+            indices: (first_triangle * 3).min(index_count)..index_count,
+        })
+    }
+
+    // Add, move or remove the vertex on each edge touching a resampled point
+    fn update_edges(
+        &mut self,
+        lower: [usize; 3],
+        upper: [usize; 3],
+        changed: &mut Option<Range<usize>>,
+    ) {
+        let n = self.size;
+        for axis in 0..3 {
+            let mut edge_lower = lower;
+            let mut edge_upper = upper;
+            edge_lower[axis] = edge_lower[axis].saturating_sub(1);
+            edge_upper[axis] = edge_upper[axis].min(n - 2);
+
+            for p in points(edge_lower, edge_upper) {
+                let mut q = p;
+                q[axis] += 1;
+                let (a, b) = (
+                    self.values[self.point_index(p)],
+                    self.values[self.point_index(q)],
+                );
+                let edge = self.point_index(p) * 3 + axis;
+
+                if a.is_positive() != b.is_positive() {
+                    let position = D::find_crossing_point(a, b, self.point(p), self.point(q));
+                    let vertex = match self.edge_vertices[edge] {
+                        NONE => self.allocate_vertex(),
+                        vertex => vertex,
+                    };
+                    self.edge_vertices[edge] = vertex;
+                    self.positions[vertex as usize] = position;
+
+                    let vertex = vertex as usize;
+                    *changed = Some(match changed.take() {
+                        Some(r) => r.start.min(vertex)..r.end.max(vertex + 1),
+                        None => vertex..vertex + 1,
+                    });
+                } else if self.edge_vertices[edge] != NONE {
+                    self.free_vertices.push(self.edge_vertices[edge]);
+                    self.edge_vertices[edge] = NONE;
+                }
+            }
+        }
+    }
+
+    fn allocate_vertex(&mut self) -> u32 {
+        self.free_vertices.pop().unwrap_or_else(|| {
+            self.positions.push(Vec3::zero());
+            (self.positions.len() - 1) as u32
+        })
+    }
+
+    // Remove the triangles generated by a cell, and return the lowest
+    // triangle slot which was overwritten
+    fn remove_cell(&mut self, cell: [usize; 3]) -> usize {
+        let cell = self.cell_index(cell);
+        let mut first = usize::MAX;
+
+        while self.cell_triangles[cell].1 > 0 {
+            let count = self.cell_triangles[cell].1 - 1;
+            let triangle = self.cell_triangles[cell].0[count] as usize;
+            self.cell_triangles[cell].1 = count;
+
+            // Move the last triangle into the hole
+            let last = self.triangle_cells.len() - 1;
+            if triangle != last {
+                let moved = self.triangle_cells[last];
+                self.indices
+                    .copy_within(last * 3..last * 3 + 3, triangle * 3);
+                self.triangle_cells[triangle] = moved;
+
+                let (slots, count) = &mut self.cell_triangles[moved];
+                for slot in slots[..*count].iter_mut() {
+                    if *slot as usize == last {
+                        *slot = triangle as u32;
+                    }
+                }
+            }
+            self.indices.truncate(last * 3);
+            self.triangle_cells.truncate(last);
+            first = first.min(triangle);
+        }
+        first
+    }
+
+    fn march_cell(&mut self, cell: [usize; 3]) {
+        let mut values = [D::zero(); 8];
+        for (i, value) in values.iter_mut().enumerate() {
+            let corner = [0, 1, 2].map(|a| cell[a] + CORNERS[i][a]);
+            *value = self.values[self.point_index(corner)];
+        }
+
+        let cell_index = self.cell_index(cell);
+        march_cube(classify_corners(&values), |a, b, c| {
+            for edge in [a, b, c] {
+                let vertex = self.edge_vertices[self.edge_index(cell, edge)];
+                debug_assert_ne!(vertex, NONE);
+                self.indices.push(vertex);
+            }
+
+            let (slots, count) = &mut self.cell_triangles[cell_index];
+            slots[*count] = self.triangle_cells.len() as u32;
+            *count += 1;
+            self.triangle_cells.push(cell_index);
+        });
+    }
+
+    fn point(&self, p: [usize; 3]) -> Vec3 {
+        let one_over_size = 1.0 / (self.size - 1) as f32;
+        Vec3::new(
+            p[0] as f32 * one_over_size,
+            p[1] as f32 * one_over_size,
+            p[2] as f32 * one_over_size,
+        )
+    }
+
+    fn point_index(&self, p: [usize; 3]) -> usize {
+        (p[2] * self.size + p[1]) * self.size + p[0]
+    }
+
+    fn cell_index(&self, cell: [usize; 3]) -> usize {
+        let n = self.size - 1;
+        (cell[2] * n + cell[1]) * n + cell[0]
+    }
+
+    // The global index of one of the 12 edges of a cell
+    fn edge_index(&self, cell: [usize; 3], edge: usize) -> usize {
+        let [u, v] = EDGE_CONNECTION[edge];
+        let lower = [0, 1, 2].map(|a| cell[a] + CORNERS[u][a].min(CORNERS[v][a]));
+        let axis = (0..3).find(|&a| CORNERS[u][a] != CORNERS[v][a]).unwrap();
+        self.point_index(lower) * 3 + axis
+    }
+}
+
+// Every grid coordinate in the inclusive range from lower to upper
+fn points(lower: [usize; 3], upper: [usize; 3]) -> impl Iterator<Item = [usize; 3]> {
+    (lower[2]..=upper[2]).flat_map(move |z| {
+        (lower[1]..=upper[1]).flat_map(move |y| (lower[0]..=upper[0]).map(move |x| [x, y, z]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{distance::Signed, fixtures::extract_mesh, sampler::Sampler, source::ScalarSource};
+
+    // A ball, optionally with a bump edited onto it within a box
+    struct Edited {
+        bump: Option<(Vec3, Vec3)>,
+    }
+
+    impl ScalarSource for Edited {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            let ball = (p - Vec3::from_scalar(0.5)).len() - 0.3;
+            match self.bump {
+                Some((min, max)) if p.max(min) == p && p.min(max) == p => {
+                    let bump = (p - Vec3::new(0.5, 0.5, 0.8)).len() - 0.08;
+                    Signed(ball.min(bump))
+                }
+                _ => Signed(ball),
+            }
+        }
+    }
+
+    type Triangle = [Vec3; 3];
+
+    fn triangles(positions: &[Vec3], indices: &[u32]) -> Vec<Triangle> {
+        indices
+            .chunks(3)
+            .map(|t| [0, 1, 2].map(|i| positions[t[i] as usize]))
+            .collect()
+    }
+
+    fn extract(source: &Edited) -> Vec<Triangle> {
+        let (positions, indices) = extract_mesh(source, 32);
+        triangles(&positions, &indices)
+    }
+
+    // Crossings may be computed from either end of an edge, so allow for
+    // rounding
+    fn assert_same_triangles(a: &[Triangle], b: &[Triangle]) {
+        assert_eq!(a.len(), b.len());
+        for s in a {
+            assert!(b.iter().any(|t| (0..3).all(|i| (s[i] - t[i]).len() < 1e-5)));
+        }
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let mut incremental = IncrementalMarchingCubes::<Signed>::new(32).unwrap();
+
+        let ball = Edited { bump: None };
+        let changed = incremental.extract(&Sampler::new(&ball)).unwrap();
+        assert_eq!(changed.vertices, 0..incremental.positions().len());
+        assert_eq!(changed.indices, 0..incremental.indices().len());
+        assert_same_triangles(
+            &triangles(incremental.positions(), incremental.indices()),
+            &extract(&ball),
+        );
+
+        // Patching the old buffers with the changed ranges reproduces the
+        // new buffers
+        let mut positions = incremental.positions().to_vec();
+        let mut indices = incremental.indices().to_vec();
+
+        let (min, max) = (Vec3::new(0.4, 0.4, 0.7), Vec3::new(0.6, 0.6, 0.9));
+        let edited = Edited {
+            bump: Some((min, max)),
+        };
+        let changed = incremental
+            .update(&Sampler::new(&edited), min, max)
+            .unwrap();
+        assert!(changed.indices.start > 0);
+
+        positions.resize(incremental.positions().len(), Vec3::zero());
+        indices.resize(incremental.indices().len(), 0);
+        positions[changed.vertices.clone()]
+            .copy_from_slice(&incremental.positions()[changed.vertices.clone()]);
+        indices[changed.indices.clone()]
+            .copy_from_slice(&incremental.indices()[changed.indices.clone()]);
+        assert_eq!(positions, incremental.positions());
+        assert_eq!(indices, incremental.indices());
+        assert_same_triangles(&triangles(&positions, &indices), &extract(&edited));
+
+        // And undoing the edit restores the original surface
+        incremental.update(&Sampler::new(&ball), min, max).unwrap();
+        assert_same_triangles(
+            &triangles(incremental.positions(), incremental.indices()),
+            &extract(&ball),
+        );
+    }
+}
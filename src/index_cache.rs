@@ -22,6 +22,21 @@ pub struct MortonKey(Morton, Morton);
 
 /// Tracks vertex indices to avoid emitting duplicate vertices during marching
 /// cubes mesh generation
+pub trait VertexCache<I> {
+    type Key: Copy;
+
+    /// Put an index in the cache at the given key
+    fn put(&mut self, key: Self::Key, index: I);
+
+    /// Retrieve an index from the cache at the given key
+    fn get(&self, key: Self::Key) -> Option<I>;
+
+    /// Remove every index from the cache, retaining the allocated memory
+    fn clear(&mut self);
+}
+
+/// A [VertexCache] for arbitrary keys, backed by a hash map. Used where cells
+/// are visited in no particular order, as in the octree.
 pub struct IndexCache<K: Eq + Hash, I: Clone> {
     indices: HashMap<K, I>,
 }
@@ -33,16 +48,102 @@ impl<K: Eq + Hash, I: Clone> IndexCache<K, I> {
             indices: HashMap::new(),
         }
     }
+}
 
-    /// Put an index in the cache at the given (x, y, z, edge) coordinate
-    pub fn put(&mut self, key: K, index: I) {
+impl<K: Eq + Hash + Copy, I: Clone> VertexCache<I> for IndexCache<K, I> {
+    type Key = K;
+
+    fn put(&mut self, key: K, index: I) {
         self.indices.insert(key, index);
     }
 
-    /// Retrieve an index from the cache at the given (x, y, z, edge) coordinate
-    pub fn get(&self, key: K) -> Option<I> {
+    fn get(&self, key: K) -> Option<I> {
         self.indices.get(&key).cloned()
     }
+
+    fn clear(&mut self) {
+        self.indices.clear();
+    }
+}
+
+/// A [VertexCache] for the edges of a regular grid, backed by flat arrays.
+///
+/// The grid must be traversed in order of increasing z, so that only the two
+/// planes of grid points bounding the current slab of cells need be stored.
+/// Each plane holds the x and y edges lying in that plane, as well as the z
+/// edges rising from it, indexed by (x, y, axis).
+pub struct EdgeCache<I: Copy> {
+    size: usize,
+    planes: [Vec<Option<I>>; 2],
+    // The z coordinate of the plane currently stored in each slot
+    stamps: [usize; 2],
+}
+
+impl<I: Copy> EdgeCache<I> {
+    /// Create an EdgeCache for a grid with `size` points along the x and y
+    /// axes. The z axis is unbounded. Memory for each plane is allocated
+    /// when it is first used.
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            planes: [vec![], vec![]],
+            stamps: [usize::MAX; 2],
+        }
+    }
+
+    /// Remove every index from the cache, and prepare it for a grid with
+    /// `size` points along the x and y axes. Memory is retained.
+    pub fn reset(&mut self, size: usize) {
+        self.size = size;
+        self.clear();
+    }
+
+    // Find the plane and offset of the edge within that plane
+    fn locate(&self, key: GridKey) -> (usize, usize) {
+        let (a, b) = (key.0, key.1);
+        let axis = if a.0 != b.0 {
+            0
+        } else if a.1 != b.1 {
+            1
+        } else {
+            2
+        };
+        (a.2, (a.1 * self.size + a.0) * 3 + axis)
+    }
+}
+
+impl<I: Copy> VertexCache<I> for EdgeCache<I> {
+    type Key = GridKey;
+
+    fn put(&mut self, key: GridKey, index: I) {
+        let (z, offset) = self.locate(key);
+        let slot = z % 2;
+
+        // Moving on to a new plane, so the plane it replaces is behind us
+        if self.stamps[slot] != z {
+            let plane = &mut self.planes[slot];
+            plane.clear();
+            plane.resize(self.size * self.size * 3, None);
+            self.stamps[slot] = z;
+        }
+        self.planes[slot][offset] = Some(index);
+    }
+
+    fn get(&self, key: GridKey) -> Option<I> {
+        let (z, offset) = self.locate(key);
+        let slot = z % 2;
+
+        if self.stamps[slot] == z {
+            self.planes[slot][offset]
+        } else {
+            None
+        }
+    }
+
+    fn clear(&mut self) {
+        // Planes are cleared lazily, once they are next used
+        self.stamps = [usize::MAX; 2];
+    }
 }
 
 impl GridKey {
@@ -72,3 +173,44 @@ impl MortonKey {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_cache() {
+        let mut cache = EdgeCache::new(4);
+
+        // Every edge of the cell in the first slab has its own slot
+        let first = [
+            (0, 0, 0),
+            (1, 0, 0),
+            (1, 1, 0),
+            (0, 1, 0),
+            (0, 0, 1),
+            (1, 0, 1),
+            (1, 1, 1),
+            (0, 1, 1),
+        ];
+        for edge in 0..12 {
+            cache.put(GridKey::new(&first, edge), edge);
+        }
+        for edge in 0..12 {
+            assert_eq!(cache.get(GridKey::new(&first, edge)), Some(edge));
+        }
+
+        // Moving to the next slab keeps the shared plane, and forgets the rest
+        let second = first.map(|(x, y, z)| (x, y, z + 1));
+        cache.put(GridKey::new(&second, 4), 12);
+        for edge in 0..12 {
+            let expected = match edge {
+                4..=7 => Some(edge),
+                _ => None,
+            };
+            assert_eq!(cache.get(GridKey::new(&first, edge)), expected);
+        }
+        assert_eq!(cache.get(GridKey::new(&second, 0)), Some(4));
+        assert_eq!(cache.get(GridKey::new(&second, 4)), Some(12));
+    }
+}
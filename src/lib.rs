@@ -26,6 +26,10 @@ pub mod distance;
 /// Utilities for outputting mesh data in specific formats.
 pub mod extractor;
 
+/// Compiling distance fields to a flat bytecode, for fast evaluation without
+/// deeply nested generic types.
+pub mod bytecode;
+
 /// Primitives for building distance fields from implicit functions.
 pub mod implicit;
 
@@ -35,12 +39,59 @@ pub mod sampler;
 /// Algorithms for traversing bounded regions of distance fields.
 pub mod traversal;
 
+/// Generating vertex normals from the faces of extracted meshes.
+pub mod normals;
+
+/// Generating texture coordinates and tangents for extracted meshes.
+pub mod texturing;
+
+/// Writing extracted meshes to vertex and index buffers in configurable formats.
+pub mod buffer;
+
+/// Rendering and ray-casting distance fields directly, without extracting a
+/// mesh.
+pub mod sphere_tracing;
+
+/// Checking that sources produce well-behaved distance fields.
+pub mod diagnostics;
+
+/// An owned half-edge mesh, for walking and editing extracted meshes.
+pub mod half_edge;
+
+/// Checking and repairing the topology of extracted meshes.
+pub mod analysis;
+
+/// Finding and filtering the separate pieces of extracted meshes.
+pub mod components;
+
+/// Measuring the area, volume and mass properties of extracted meshes.
+pub mod measure;
+
+/// Measuring how accurately extracted meshes follow the surface of their source.
+pub mod evaluation;
+
+/// Simplifying extracted meshes.
+pub mod decimation;
+
+/// Remeshing extracted meshes into well-shaped triangles.
+pub mod remeshing;
+
+/// Smoothing extracted meshes.
+pub mod smoothing;
+
+/// Writing extracted meshes to common file formats.
+pub mod export;
+
 /// Algorithms for accurately placing vertices on features (edges or corners) of
 /// an implicit surface.
 pub mod feature;
 
 mod dual_contouring;
+mod error;
 mod extended_marching_cubes;
+#[cfg(test)]
+mod fixtures;
+mod incremental_marching_cubes;
 mod index_cache;
 mod linear_hashed_marching_cubes;
 mod linear_hashed_octree;
@@ -50,8 +101,11 @@ mod marching_cubes_tables;
 mod mesh;
 mod morton;
 mod point_cloud;
+mod workspace;
 
+pub use self::error::Error;
+pub use self::workspace::Workspace;
 pub use self::{
-    dual_contouring::*, extended_marching_cubes::*, linear_hashed_marching_cubes::*,
-    marching_cubes::*, point_cloud::*,
+    dual_contouring::*, extended_marching_cubes::*, incremental_marching_cubes::*,
+    linear_hashed_marching_cubes::*, marching_cubes::*, point_cloud::*,
 };
@@ -13,14 +13,20 @@
 // limitations under the License.
 use crate::{
     distance::Signed,
-    extractor::Extractor,
-    index_cache::MortonKey,
-    marching_cubes_impl::{classify_corners, find_edge_crossings, march_cube},
+    error::Error,
+    extractor::VertexExtractor,
+    index_cache::{IndexCache, MortonKey},
+    marching_cubes_impl::{
+        classify_corners, edge_crossing_vertex, find_edge_crossings, march_cube,
+        refine_edge_crossings,
+    },
     math::Vec3,
-    mesh::MeshTopologyBuilder,
-    sampler::Sample,
-    source::ScalarSource,
+    mesh::{MeshTopologyBuilder, VertexHandle},
+    morton::Morton,
+    sampler::{Refinement, Sample},
+    source::{IntervalSource, ScalarSource},
     traversal::ImplicitOctree,
+    workspace::Workspace,
 };
 
 /// Convert isosurfaces to meshes using marching cubes over a linear hashed
@@ -38,7 +44,8 @@ use crate::{
 ///
 /// * Still can't accurately reproduce sharp edges which are not grid-aligned.
 pub struct LinearHashedMarchingCubes {
-    max_depth: usize,
+    implicit_octree: ImplicitOctree,
+    refinement: Option<Refinement>,
 }
 
 impl LinearHashedMarchingCubes {
@@ -48,8 +55,20 @@ impl LinearHashedMarchingCubes {
     /// the tree to span the equivalent of a cubic grid at most
     /// `2.pow(max_depth)` in either direction. Distances will be evaluated
     /// in Euclidean space.
-    pub fn new(max_depth: usize) -> Self {
-        Self { max_depth }
+    pub fn new(max_depth: usize) -> Result<Self, Error> {
+        Ok(Self {
+            implicit_octree: ImplicitOctree::new(max_depth)?,
+            refinement: None,
+        })
+    }
+
+    /// Create a new LinearHashedMarchingCubes, which refines the position of
+    /// each vertex by searching along its octree edge (see [Refinement]).
+    pub fn new_with_refinement(max_depth: usize, refinement: Refinement) -> Result<Self, Error> {
+        Ok(Self {
+            implicit_octree: ImplicitOctree::new(max_depth)?,
+            refinement: Some(refinement),
+        })
     }
 
     /// Extracts a mesh from the given [Sample].
@@ -59,27 +78,127 @@ impl LinearHashedMarchingCubes {
     ///
     /// The resulting vertex and face data will be returned via the provided
     /// Extractor.
-    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E)
+    ///
+    /// Scratch memory is allocated afresh for each call. To reuse it between
+    /// calls, use [extract_with](LinearHashedMarchingCubes::extract_with).
+    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
     where
         S: Sample<Signed> + ScalarSource,
-        E: Extractor,
+        E: VertexExtractor,
     {
-        let mut implicit_octree = ImplicitOctree::new(self.max_depth);
-        let mut mesh_builder = MeshTopologyBuilder::new(extractor);
+        self.extract_with(&mut Workspace::new(), source, extractor)
+    }
 
-        implicit_octree.traverse(source, |keys, corners, values| {
-            let cube_index = classify_corners(&values);
+    /// Extracts a mesh from the given [Sample], as per
+    /// [extract](LinearHashedMarchingCubes::extract), using the scratch memory
+    /// in the given [Workspace].
+    ///
+    /// Once the workspace has warmed up, repeatedly extracting similar
+    /// surfaces doesn't allocate.
+    pub fn extract_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
+    where
+        S: Sample<Signed> + ScalarSource,
+        E: VertexExtractor,
+    {
+        let Workspace {
+            morton_indices,
+            octree,
+            mesh,
+            ..
+        } = workspace;
+        let mut mesh_builder = MeshTopologyBuilder::new(morton_indices, mesh, extractor);
+        let refinement = self.refinement;
+
+        self.implicit_octree
+            .traverse_in(octree, source, |keys, corners, values| {
+                Self::march_dual_cube(&mut mesh_builder, source, refinement, keys, corners, values);
+            })?;
+
+        mesh_builder.build()?.extract_indices(extractor);
+        Ok(())
+    }
 
-            let mut vertices = [Vec3::zero(); 12];
-            find_edge_crossings(cube_index, &corners, &values, &mut vertices);
-            march_cube(cube_index, |a, b, c| {
-                let a = mesh_builder.add_vertex(Some(MortonKey::new(&keys, a)), vertices[a]);
-                let b = mesh_builder.add_vertex(Some(MortonKey::new(&keys, b)), vertices[b]);
-                let c = mesh_builder.add_vertex(Some(MortonKey::new(&keys, c)), vertices[c]);
-                mesh_builder.add_face(a, b, c);
+    /// Extracts a mesh from the given [Sample], using the bounds provided by
+    /// an [IntervalSource] to decide where to refine the octree.
+    ///
+    /// This guarantees that no part of the surface will be missed because it
+    /// fell between samples, at the cost of evaluating the bounds for every
+    /// octree node. See [ImplicitOctree::traverse_bounded].
+    pub fn extract_bounded<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        E: VertexExtractor,
+    {
+        self.extract_bounded_with(&mut Workspace::new(), source, extractor)
+    }
+
+    /// Extracts a mesh from the given [Sample] as per
+    /// [extract_bounded](LinearHashedMarchingCubes::extract_bounded), using
+    /// the scratch memory in the given [Workspace].
+    pub fn extract_bounded_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        E: VertexExtractor,
+    {
+        let Workspace {
+            morton_indices,
+            octree,
+            mesh,
+            ..
+        } = workspace;
+        let mut mesh_builder = MeshTopologyBuilder::new(morton_indices, mesh, extractor);
+        let refinement = self.refinement;
+
+        self.implicit_octree
+            .traverse_bounded_in(octree, source, |keys, corners, values| {
+                Self::march_dual_cube(&mut mesh_builder, source, refinement, keys, corners, values);
+            })?;
+
+        mesh_builder.build()?.extract_indices(extractor);
+        Ok(())
+    }
+
+    fn march_dual_cube<S, E>(
+        mesh_builder: &mut MeshTopologyBuilder<IndexCache<MortonKey, VertexHandle>, E>,
+        source: &S,
+        refinement: Option<Refinement>,
+        keys: &[Morton; 8],
+        corners: &[Vec3; 8],
+        values: &[Signed; 8],
+    ) where
+        S: Sample<Signed>,
+        E: VertexExtractor,
+    {
+        let cube_index = classify_corners(values);
+
+        let mut vertices = [Vec3::zero(); 12];
+        find_edge_crossings(cube_index, corners, values, &mut vertices);
+        if let Some(refinement) = refinement {
+            refine_edge_crossings(
+                cube_index,
+                corners,
+                values,
+                source,
+                refinement,
+                &mut vertices,
+            );
+        }
+        march_cube(cube_index, |a, b, c| {
+            let [a, b, c] = [a, b, c].map(|edge| {
+                let vertex = edge_crossing_vertex(edge, corners, &vertices);
+                mesh_builder.add_vertex(Some(MortonKey::new(keys, edge)), vertex)
             });
+            mesh_builder.add_face(a, b, c);
         });
-
-        mesh_builder.build().extract_indices(extractor);
     }
 }
@@ -11,12 +11,13 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::morton::Morton;
+use crate::{error::Error, morton::Morton};
 use std::collections::{HashMap, VecDeque};
 
 pub struct LinearHashedOctree<Node> {
     nodes: HashMap<Morton, Node>,
     leaves: Vec<Morton>,
+    queue: VecDeque<Morton>,
 }
 
 impl<Node> LinearHashedOctree<Node> {
@@ -24,19 +25,25 @@ impl<Node> LinearHashedOctree<Node> {
         Self {
             nodes: HashMap::new(),
             leaves: Vec::new(),
+            queue: VecDeque::new(),
         }
     }
 
-    pub fn build<R, C>(&mut self, mut should_refine: R, mut construct_node: C)
+    pub fn build<R, C>(&mut self, mut should_refine: R, mut construct_node: C) -> Result<(), Error>
     where
         R: FnMut(Morton, &Node) -> bool,
-        C: FnMut(Morton) -> Node,
+        C: FnMut(Morton) -> Result<Node, Error>,
     {
-        let mut queue = VecDeque::new();
+        // Discard any previous tree, but keep the memory
+        self.nodes.clear();
+        self.leaves.clear();
+        self.queue.clear();
+
+        let queue = &mut self.queue;
         queue.push_back(Morton::new());
 
         while let Some(key) = queue.pop_front() {
-            let node = construct_node(key);
+            let node = construct_node(key)?;
 
             if should_refine(key, &node) {
                 for i in 0..8 {
@@ -48,6 +55,8 @@ impl<Node> LinearHashedOctree<Node> {
 
             self.nodes.insert(key, node);
         }
+
+        Ok(())
     }
 
     pub fn walk_leaves<W>(&self, mut walker: W)
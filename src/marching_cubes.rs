@@ -12,14 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
-    distance::Distance,
-    extractor::Extractor,
-    index_cache::GridKey,
-    marching_cubes_impl::{classify_corners, find_edge_crossings, march_cube},
+    distance::{Distance, Signed},
+    error::Error,
+    extractor::VertexExtractor,
+    index_cache::{EdgeCache, GridKey, VertexCache},
+    marching_cubes_impl::{
+        classify_corners, edge_crossing_vertex, find_edge_crossings, march_cube,
+        refine_edge_crossings,
+    },
     math::Vec3,
-    mesh::MeshTopologyBuilder,
-    sampler::Sample,
+    mesh::{MeshTopologyBuilder, VertexHandle},
+    sampler::{Refinement, Sample},
+    source::IntervalSource,
     traversal::PrimalGrid,
+    workspace::Workspace,
 };
 
 /// Convert isosurfaces to meshes using marching cubes.
@@ -37,16 +43,35 @@ use crate::{
 /// * Can't accurately reproduce sharp edges in the isosurface.
 pub struct MarchingCubes<D: Distance> {
     primal_grid: PrimalGrid<D>,
+    refinement: Option<Refinement>,
 }
 
 impl<D: Distance> MarchingCubes<D> {
     /// Create a new MarchingCubes with the given chunk size.
     ///
     /// For a given `size`, this will evaluate chunks of `size^3` voxels.
-    pub fn new(size: usize) -> Self {
-        Self {
-            primal_grid: PrimalGrid::new(size),
-        }
+    pub fn new(size: usize) -> Result<Self, Error> {
+        Ok(Self {
+            primal_grid: PrimalGrid::new(size)?,
+            refinement: None,
+        })
+    }
+
+    /// Create a new MarchingCubes with the given chunk size, which refines
+    /// the position of each vertex by searching along its grid edge (see
+    /// [Refinement]).
+    pub fn new_with_refinement(size: usize, refinement: Refinement) -> Result<Self, Error> {
+        Ok(Self {
+            primal_grid: PrimalGrid::new(size)?,
+            refinement: Some(refinement),
+        })
+    }
+
+    /// The grid which meshes are extracted from, for passes which need to
+    /// sample the same points before extraction (such as
+    /// [IslandFilter](crate::components::IslandFilter)).
+    pub fn primal_grid(&mut self) -> &mut PrimalGrid<D> {
+        &mut self.primal_grid
     }
 
     /// Extracts a mesh from the given [Sample].
@@ -56,28 +81,318 @@ impl<D: Distance> MarchingCubes<D> {
     ///
     /// The resulting vertex and face data will be returned via the provided
     /// Extractor.
-    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E)
+    ///
+    /// Scratch memory is allocated afresh for each call. To reuse it between
+    /// calls (i.e. when remeshing chunks of terrain as they are edited), use
+    /// [extract_with](MarchingCubes::extract_with).
+    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
+    where
+        S: Sample<D>,
+        E: VertexExtractor,
+    {
+        self.extract_with(&mut Workspace::new(), source, extractor)
+    }
+
+    /// Extracts a mesh from the given [Sample], as per
+    /// [extract](MarchingCubes::extract), using the scratch memory in the
+    /// given [Workspace].
+    ///
+    /// Once the workspace has warmed up, repeatedly extracting similar
+    /// surfaces doesn't allocate.
+    pub fn extract_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
     where
         S: Sample<D>,
-        E: Extractor,
+        E: VertexExtractor,
     {
-        let mut mesh_builder = MeshTopologyBuilder::new(extractor);
+        workspace.edges.reset(self.primal_grid.size());
+        let mut mesh_builder =
+            MeshTopologyBuilder::new(&mut workspace.edges, &mut workspace.mesh, extractor);
+        let refinement = self.refinement;
 
         self.primal_grid.traverse(source, |keys, corners, values| {
-            let cube_index = classify_corners(&values);
+            Self::march_grid_cube(&mut mesh_builder, source, refinement, keys, corners, values);
+        })?;
+
+        mesh_builder.build()?.extract_indices(extractor);
+        Ok(())
+    }
+
+    /// Extracts a mesh from the given [Sample], passing each vertex and face
+    /// to the extractor as soon as it is found.
+    ///
+    /// Produces the same surface as [extract](MarchingCubes::extract), but
+    /// rather than holding the whole mesh until the end, only the vertices on
+    /// the boundary between the current slab of cells and the next are
+    /// remembered. Memory use is therefore proportional to the area of a
+    /// single slab, rather than the volume of the grid.
+    ///
+    /// Geometry is output slab by slab along the z axis, and every vertex is
+    /// output before any face which refers to it. Unlike the other extraction
+    /// methods, vertices and faces are interleaved.
+    pub fn extract_streaming<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
+    where
+        S: Sample<D>,
+        E: VertexExtractor,
+    {
+        self.extract_streaming_with(&mut Workspace::new(), source, extractor)
+    }
+
+    /// Extracts a mesh from the given [Sample] as per
+    /// [extract_streaming](MarchingCubes::extract_streaming), using the
+    /// scratch memory in the given [Workspace].
+    pub fn extract_streaming_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
+    where
+        S: Sample<D>,
+        E: VertexExtractor,
+    {
+        let refinement = self.refinement;
+        let indices = &mut workspace.streaming_edges;
+        indices.reset(self.primal_grid.size());
+        let mut next_index = 0;
+        let mut error = None;
 
-            let mut vertices = [Vec3::zero(); 12];
-            find_edge_crossings(cube_index, &corners, &values, &mut vertices);
+        self.primal_grid.traverse(source, |keys, corners, values| {
+            if error.is_some() {
+                return;
+            }
 
+            let (cube_index, vertices) = Self::find_vertices(source, refinement, corners, values);
             march_cube(cube_index, |a, b, c| {
-                let a = mesh_builder.add_vertex(Some(GridKey::new(keys, a)), vertices[a]);
-                let b = mesh_builder.add_vertex(Some(GridKey::new(keys, b)), vertices[b]);
-                let c = mesh_builder.add_vertex(Some(GridKey::new(keys, c)), vertices[c]);
+                if error.is_some() {
+                    return;
+                }
+
+                // Stop before outputting a vertex the extractor can't index
+                let maximum = extractor.max_index();
+                let mut face = [0; 3];
+                for (index, edge) in face.iter_mut().zip([a, b, c]) {
+                    let key = GridKey::new(keys, edge);
+                    *index = match indices.get(key) {
+                        Some(index) => index,
+                        None if next_index > maximum => {
+                            error.get_or_insert(Error::IndexOverflow {
+                                index: next_index,
+                                maximum,
+                            });
+                            return;
+                        }
+                        None => {
+                            let vertex = edge_crossing_vertex(edge, corners, &vertices);
+                            extractor.extract_vertex(vertex);
+                            indices.put(key, next_index);
+                            next_index += 1;
+                            next_index - 1
+                        }
+                    };
+                }
+
+                for index in face {
+                    extractor.extract_index(index);
+                }
+            });
+        })?;
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn march_grid_cube<S, E>(
+        mesh_builder: &mut MeshTopologyBuilder<EdgeCache<VertexHandle>, E>,
+        source: &S,
+        refinement: Option<Refinement>,
+        keys: &[(usize, usize, usize); 8],
+        corners: &[Vec3; 8],
+        values: &[D; 8],
+    ) where
+        S: Sample<D>,
+        E: VertexExtractor,
+    {
+        let (cube_index, vertices) = Self::find_vertices(source, refinement, corners, values);
 
-                mesh_builder.add_face(a, b, c);
+        march_cube(cube_index, |a, b, c| {
+            let [a, b, c] = [a, b, c].map(|edge| {
+                let vertex = edge_crossing_vertex(edge, corners, &vertices);
+                mesh_builder.add_vertex(Some(GridKey::new(keys, edge)), vertex)
             });
+
+            mesh_builder.add_face(a, b, c);
         });
+    }
+
+    // Classify the cube, and find the surface crossing on each of its edges
+    fn find_vertices<S>(
+        source: &S,
+        refinement: Option<Refinement>,
+        corners: &[Vec3; 8],
+        values: &[D; 8],
+    ) -> (usize, [Vec3; 12])
+    where
+        S: Sample<D>,
+    {
+        let cube_index = classify_corners(values);
+
+        let mut vertices = [Vec3::zero(); 12];
+        find_edge_crossings(cube_index, corners, values, &mut vertices);
+        if let Some(refinement) = refinement {
+            refine_edge_crossings(
+                cube_index,
+                corners,
+                values,
+                source,
+                refinement,
+                &mut vertices,
+            );
+        }
+        (cube_index, vertices)
+    }
+}
+
+impl MarchingCubes<Signed> {
+    /// Extracts a mesh from the given [Sample], skipping any region of the
+    /// grid which the [IntervalSource] proves doesn't contain the surface.
+    ///
+    /// Produces the same surface as [extract](MarchingCubes::extract), but
+    /// avoids sampling empty space. See [PrimalGrid::traverse_bounded].
+    pub fn extract_bounded<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        E: VertexExtractor,
+    {
+        self.extract_bounded_with(&mut Workspace::new(), source, extractor)
+    }
+
+    /// Extracts a mesh from the given [Sample] as per
+    /// [extract_bounded](MarchingCubes::extract_bounded), using the scratch
+    /// memory in the given [Workspace].
+    pub fn extract_bounded_with<S, E>(
+        &mut self,
+        workspace: &mut Workspace,
+        source: &S,
+        extractor: &mut E,
+    ) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        E: VertexExtractor,
+    {
+        workspace.edges.reset(self.primal_grid.size());
+        let mut mesh_builder =
+            MeshTopologyBuilder::new(&mut workspace.edges, &mut workspace.mesh, extractor);
+        let refinement = self.refinement;
+
+        self.primal_grid
+            .traverse_bounded(source, |keys, corners, values| {
+                Self::march_grid_cube(&mut mesh_builder, source, refinement, keys, corners, values);
+            })?;
+
+        mesh_builder.build()?.extract_indices(extractor);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extractor::Vertex, fixtures::ball, sampler::Sampler};
+
+    #[derive(Default)]
+    struct Records {
+        vertices: Vec<Vec3>,
+        triangles: Vec<[[u32; 3]; 3]>,
+        pending: Vec<usize>,
+        // The number of vertices output before the first face
+        first_face: Option<usize>,
+    }
+
+    impl VertexExtractor for Records {
+        fn extract_vertex(&mut self, vertex: Vertex) {
+            self.vertices.push(vertex.position);
+        }
+
+        fn extract_index(&mut self, index: usize) {
+            assert!(index < self.vertices.len());
+            self.first_face.get_or_insert(self.vertices.len());
+            self.pending.push(index);
+            if let [a, b, c] = self.pending[..] {
+                let p = [a, b, c].map(|i| {
+                    let p = self.vertices[i];
+                    [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+                });
+                self.triangles.push(p);
+                self.pending.clear();
+            }
+        }
+    }
+
+    #[test]
+    fn test_streaming() {
+        let ball = ball();
+        let sampler = Sampler::new(&ball);
+        let mut marching_cubes = MarchingCubes::<Signed>::new(24).unwrap();
+
+        let mut buffered = Records::default();
+        marching_cubes.extract(&sampler, &mut buffered).unwrap();
+        let mut streamed = Records::default();
+        marching_cubes
+            .extract_streaming(&sampler, &mut streamed)
+            .unwrap();
+
+        // The same vertices and faces, though not in the same order
+        assert_eq!(streamed.vertices.len(), buffered.vertices.len());
+        buffered.triangles.sort_unstable();
+        streamed.triangles.sort_unstable();
+        assert_eq!(streamed.triangles, buffered.triangles);
+
+        // Faces start arriving long before the last vertex
+        assert_eq!(buffered.first_face, Some(buffered.vertices.len()));
+        assert!(streamed.first_face.unwrap() < streamed.vertices.len() / 4);
+    }
+
+    // Records the mesh, but can only index a limited number of vertices
+    struct Limited(Records, usize);
+
+    impl VertexExtractor for Limited {
+        fn extract_vertex(&mut self, vertex: Vertex) {
+            assert!(self.0.vertices.len() <= self.1);
+            self.0.extract_vertex(vertex);
+        }
+
+        fn extract_index(&mut self, index: usize) {
+            self.0.extract_index(index);
+        }
+
+        fn max_index(&self) -> usize {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_streaming_index_overflow() {
+        let ball = ball();
+        let sampler = Sampler::new(&ball);
+        let mut marching_cubes = MarchingCubes::<Signed>::new(24).unwrap();
 
-        mesh_builder.build().extract_indices(extractor);
+        let mut limited = Limited(Records::default(), 99);
+        let result = marching_cubes.extract_streaming(&sampler, &mut limited);
+        assert_eq!(
+            result,
+            Err(Error::IndexOverflow {
+                index: 100,
+                maximum: 99
+            })
+        );
+        assert_eq!(limited.0.vertices.len(), 100);
+        assert!(limited.0.pending.is_empty());
     }
 }
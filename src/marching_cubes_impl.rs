@@ -13,9 +13,10 @@
 // limitations under the License.
 use crate::{
     distance::Distance,
+    extractor::{Vertex, VertexOrigin},
     marching_cubes_tables::{EDGE_CONNECTION, EDGE_CROSSING_MASK, TRIANGLE_CONNECTION},
     math::Vec3,
-    sampler::Sample,
+    sampler::{refine_crossing, Refinement, Sample},
     source::HermiteSource,
 };
 
@@ -28,8 +29,8 @@ where
     D: Distance,
 {
     let mut cube_index = 0;
-    for i in 0..8 {
-        if !values[i].is_positive() {
+    for (i, value) in values.iter().enumerate() {
+        if !value.is_positive() {
             cube_index |= 1 << i;
         }
     }
@@ -55,6 +56,61 @@ pub fn find_edge_crossings<D>(
     }
 }
 
+/// Move each edge crossing found by [find_edge_crossings] onto the surface, by
+/// searching along the edge.
+pub fn refine_edge_crossings<D, S>(
+    cube_index: usize,
+    corners: &[Vec3; 8],
+    values: &[D; 8],
+    source: &S,
+    refinement: Refinement,
+    vertices: &mut [Vec3; 12],
+) where
+    D: Distance,
+    S: Sample<D>,
+{
+    let edges = EDGE_CROSSING_MASK[cube_index];
+
+    for i in 0..12 {
+        if (edges & (1 << i)) != 0 {
+            let [u, v] = EDGE_CONNECTION[i];
+
+            vertices[i] = refine_crossing(
+                source, refinement, values[u], values[v], corners[u], corners[v],
+            );
+        }
+    }
+}
+
+/// Build the vertex record for the edge crossing on the given edge of the
+/// cube, as found by [find_edge_crossings].
+pub fn edge_crossing_vertex(edge: usize, corners: &[Vec3; 8], vertices: &[Vec3; 12]) -> Vertex {
+    let [u, v] = EDGE_CONNECTION[edge];
+    let (start, end) = (corners[u], corners[v]);
+
+    // The crossing may have been refined, so recover the factor from its
+    // position rather than the distances
+    let length = (end - start).len_sq();
+    let factor = if length > 0.0 {
+        (vertices[edge] - start).dot(end - start) / length
+    } else {
+        0.5
+    };
+
+    Vertex::new(vertices[edge], VertexOrigin::Edge { start, end, factor })
+}
+
+/// Build the vertex record for a vertex placed inside the cube.
+pub fn cell_vertex(position: Vec3, corners: &[Vec3; 8]) -> Vertex {
+    Vertex::new(
+        position,
+        VertexOrigin::Cell {
+            min: corners[0],
+            max: corners[6],
+        },
+    )
+}
+
 pub fn sample_normals_at_corners<D, S>(source: &S, corners: &[Vec3; 8], normals: &mut [Vec3; 8])
 where
     D: Distance,
@@ -101,7 +157,7 @@ pub fn sample_normals_at_edge_crossings<D, S>(
 /// forming triangles.
 pub fn march_cube<F>(cube_index: usize, mut face_callback: F)
 where
-    F: FnMut(usize, usize, usize) -> (),
+    F: FnMut(usize, usize, usize),
 {
     for i in 0..5 {
         if TRIANGLE_CONNECTION[cube_index][3 * i] < 0 {
@@ -109,7 +165,7 @@ where
         }
 
         face_callback(
-            TRIANGLE_CONNECTION[cube_index][3 * i + 0] as usize,
+            TRIANGLE_CONNECTION[cube_index][3 * i] as usize,
             TRIANGLE_CONNECTION[cube_index][3 * i + 1] as usize,
             TRIANGLE_CONNECTION[cube_index][3 * i + 2] as usize,
         );
@@ -0,0 +1,161 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conservative interval arithmetic, used to bound the value of a distance
+//! field over a whole region at once.
+
+/// A closed range of values [min, max].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Interval {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Interval {
+    /// Create an interval from its lower and upper bounds
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    /// Create an interval containing exactly one value
+    pub fn point(f: f32) -> Self {
+        Self { min: f, max: f }
+    }
+
+    /// The distance between the lower and upper bounds
+    pub fn width(&self) -> f32 {
+        self.max - self.min
+    }
+
+    /// Test if the interval contains the given value
+    pub fn contains(&self, f: f32) -> bool {
+        self.min <= f && f <= self.max
+    }
+
+    /// Test if every value in the interval is positive
+    pub fn is_positive(&self) -> bool {
+        self.min > 0.0
+    }
+
+    /// Test if every value in the interval is negative
+    pub fn is_negative(&self) -> bool {
+        self.max < 0.0
+    }
+
+    /// The absolute value of every value in the interval
+    pub fn abs(&self) -> Self {
+        if self.min >= 0.0 {
+            *self
+        } else if self.max <= 0.0 {
+            -*self
+        } else {
+            Self::new(0.0, (-self.min).max(self.max))
+        }
+    }
+
+    /// The square of every value in the interval
+    pub fn square(&self) -> Self {
+        let a = self.abs();
+        Self::new(a.min * a.min, a.max * a.max)
+    }
+
+    /// The square root of every value in the interval. Negative values are
+    /// clamped to zero.
+    pub fn sqrt(&self) -> Self {
+        Self::new(self.min.max(0.0).sqrt(), self.max.max(0.0).sqrt())
+    }
+
+    /// The pairwise minimum of values in this interval and another
+    pub fn min(&self, other: Self) -> Self {
+        Self::new(self.min.min(other.min), self.max.min(other.max))
+    }
+
+    /// The pairwise maximum of values in this interval and another
+    pub fn max(&self, other: Self) -> Self {
+        Self::new(self.min.max(other.min), self.max.max(other.max))
+    }
+
+    /// The smallest interval containing both this interval and another
+    pub fn union(&self, other: Self) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+}
+
+impl std::ops::Neg for Interval {
+    type Output = Interval;
+    fn neg(self) -> Interval {
+        Interval::new(-self.max, -self.min)
+    }
+}
+
+impl std::ops::Add for Interval {
+    type Output = Interval;
+    fn add(self, other: Interval) -> Interval {
+        Interval::new(self.min + other.min, self.max + other.max)
+    }
+}
+
+impl std::ops::Sub for Interval {
+    type Output = Interval;
+    fn sub(self, other: Interval) -> Interval {
+        Interval::new(self.min - other.max, self.max - other.min)
+    }
+}
+
+impl std::ops::Mul for Interval {
+    type Output = Interval;
+    fn mul(self, other: Interval) -> Interval {
+        let a = self.min * other.min;
+        let b = self.min * other.max;
+        let c = self.max * other.min;
+        let d = self.max * other.max;
+        Interval::new(a.min(b).min(c).min(d), a.max(b).max(c).max(d))
+    }
+}
+
+impl std::ops::Add<f32> for Interval {
+    type Output = Interval;
+    fn add(self, other: f32) -> Interval {
+        Interval::new(self.min + other, self.max + other)
+    }
+}
+
+impl std::ops::Sub<f32> for Interval {
+    type Output = Interval;
+    fn sub(self, other: f32) -> Interval {
+        Interval::new(self.min - other, self.max - other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_arithmetic() {
+        let a = Interval::new(-1.0, 2.0);
+        let b = Interval::new(3.0, 4.0);
+
+        assert_eq!(a + b, Interval::new(2.0, 6.0));
+        assert_eq!(a - b, Interval::new(-5.0, -1.0));
+        assert_eq!(a * b, Interval::new(-4.0, 8.0));
+        assert_eq!(-a, Interval::new(-2.0, 1.0));
+        assert_eq!(a.abs(), Interval::new(0.0, 2.0));
+        assert_eq!(a.square(), Interval::new(0.0, 4.0));
+        assert_eq!(b.square(), Interval::new(9.0, 16.0));
+        assert_eq!(a.sqrt(), Interval::new(0.0, 2.0f32.sqrt()));
+        assert_eq!(a.min(b), a);
+        assert_eq!(a.max(b), b);
+    }
+}
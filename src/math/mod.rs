@@ -11,9 +11,11 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+pub mod interval;
 pub mod svd;
 pub mod vector;
 
+pub use interval::*;
 pub use vector::*;
 
 use std::ops::{Add, Mul};
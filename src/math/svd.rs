@@ -54,7 +54,8 @@ impl SVD {
 
     pub fn solve(mut self, vec: &[f64]) -> Vec3 {
         let mut point = [0.0; 3];
-        let mut v = vec.to_vec();
+        let mut v = [0.0; 12];
+        v[..vec.len()].copy_from_slice(vec);
 
         // solve linear system given by mat and vec using the
         // singular value decomposition of mat into u, v and d.
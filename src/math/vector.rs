@@ -12,10 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-///! Ideally we'd reuse an exiting geometry library, but in the interest both
-/// of minimising dependencies, and of compatibility with multiple geometry
-/// libraries, we'll define our own.
-use std;
+//! Ideally we'd reuse an exiting geometry library, but in the interest both
+//! of minimising dependencies, and of compatibility with multiple geometry
+//! libraries, we'll define our own.
 
 /// A 2 dimensional vector
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
@@ -134,7 +133,7 @@ macro_rules! impl_vector {
             /// Normalised copy of this vector
             pub fn normalised(&self) -> Option<Self> {
                 let l = self.len();
-                if l.abs() < std::f32::EPSILON {
+                if l.abs() < f32::EPSILON {
                     None
                 } else {
                     Some(Self {
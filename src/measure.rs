@@ -0,0 +1,211 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    buffer::IndexType, distance::Distance, error::Error, marching_cubes_impl::classify_corners,
+    math::Vec3, normals::face_normal, sampler::Sample, traversal::PrimalGrid,
+};
+
+/// The geometric properties of a mesh, and of the solid it encloses.
+///
+/// The volume, centroid and inertia are only meaningful for closed meshes,
+/// and treat the solid as having uniform, unit density.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MassProperties {
+    pub area: f32,
+    /// The signed volume enclosed by the mesh. Positive for meshes wound as
+    /// extracted (i.e. clockwise when viewed from outside), and negative for
+    /// inverted ones.
+    pub volume: f32,
+    /// The center of mass of the enclosed solid.
+    pub centroid: Vec3,
+    /// The inertia tensor of the enclosed solid about its centroid, in rows.
+    pub inertia: [[f32; 3]; 3],
+}
+
+/// Measure the area of an indexed triangle mesh, and the volume, centroid and
+/// inertia tensor of the solid it encloses.
+///
+/// These are computed exactly for the mesh, by summing over the tetrahedra
+/// formed by the origin and each face (following the divergence theorem).
+/// Accumulation is performed in double precision, so large meshes don't lose
+/// accuracy.
+pub fn measure<I: IndexType>(positions: &[Vec3], indices: &[I]) -> MassProperties {
+    let mut area = 0.0;
+    let mut volume = 0.0;
+    let mut first_moment = [0.0f64; 3];
+    let mut second_moment = [[0.0f64; 3]; 3];
+
+    for face in indices.chunks_exact(3) {
+        let p = [0, 1, 2].map(|i| positions[face[i].to_index()]);
+        let normal = face_normal(p.iter().copied());
+        area += normal.len() as f64 * 0.5;
+
+        // The signed volume of the tetrahedron, and the integrals of x and
+        // x * x^T over it, given that its fourth corner is the origin
+        let det = p[0].dot(normal) as f64;
+        volume += det / 6.0;
+        let v = p.map(|p| [p.x as f64, p.y as f64, p.z as f64]);
+        for i in 0..3 {
+            let sum_i = v[0][i] + v[1][i] + v[2][i];
+            first_moment[i] += det / 24.0 * sum_i;
+            for j in 0..3 {
+                let sum_j = v[0][j] + v[1][j] + v[2][j];
+                let products = v.iter().map(|v| v[i] * v[j]).sum::<f64>();
+                second_moment[i][j] += det / 120.0 * (products + sum_i * sum_j);
+            }
+        }
+    }
+
+    let centroid = if volume != 0.0 {
+        first_moment.map(|m| m / volume)
+    } else {
+        [0.0; 3]
+    };
+
+    // Move the second moment to the centroid, and convert it to an inertia
+    // tensor
+    let mut covariance = second_moment;
+    for i in 0..3 {
+        for j in 0..3 {
+            covariance[i][j] -= volume * centroid[i] * centroid[j];
+        }
+    }
+    let trace = covariance[0][0] + covariance[1][1] + covariance[2][2];
+    let mut inertia = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            let diagonal = if i == j { trace } else { 0.0 };
+            inertia[i][j] = (diagonal - covariance[i][j]) as f32;
+        }
+    }
+
+    MassProperties {
+        area: area as f32,
+        volume: volume as f32,
+        centroid: Vec3::new(centroid[0] as f32, centroid[1] as f32, centroid[2] as f32),
+        inertia,
+    }
+}
+
+/// Estimate the volume inside the surface within the unit cube, directly from
+/// the classification of the grid cells that extraction would visit, without
+/// building a mesh.
+///
+/// Each cell contributes the fraction of its corners which lie inside the
+/// surface, so the estimate converges on the true volume as `size` increases.
+pub fn voxel_volume<D, S>(source: &S, size: usize) -> Result<f32, Error>
+where
+    D: Distance,
+    S: Sample<D>,
+{
+    let mut grid = PrimalGrid::<D>::new(size)?;
+    let cell_volume = (1.0 / (size - 1) as f64).powi(3);
+    let mut inside = 0u64;
+
+    grid.traverse(source, |keys, _, values| {
+        // Ignore any cells past the far side of the unit cube
+        if keys.iter().any(|k| k.2 >= size) {
+            return;
+        }
+        inside += classify_corners(values).count_ones() as u64;
+    })?;
+
+    Ok((inside as f64 / 8.0 * cell_volume) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed,
+        fixtures::{extract_mesh, Centered},
+        implicit::{Sphere, Torus},
+        sampler::Sampler,
+        source::ScalarSource,
+    };
+    use std::f32::consts::PI;
+
+    fn extract<S: ScalarSource>(source: &S) -> MassProperties {
+        let (positions, indices) = extract_mesh(source, 48);
+        measure(&positions, &indices)
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() < expected * tolerance,
+            "{} is not within {}% of {}",
+            actual,
+            tolerance * 100.0,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_sphere() {
+        let r = 0.3;
+        let sphere = Centered(Sphere::new(r));
+        let properties = extract(&sphere);
+
+        let volume = 4.0 / 3.0 * PI * r * r * r;
+        assert_close(properties.area, 4.0 * PI * r * r, 0.01);
+        assert_close(properties.volume, volume, 0.01);
+        assert!((properties.centroid - Vec3::from_scalar(0.5)).len() < 1e-4);
+
+        // A solid sphere has the same moment of inertia about every axis
+        let moment = 0.4 * volume * r * r;
+        for i in 0..3 {
+            for j in 0..3 {
+                if i == j {
+                    assert_close(properties.inertia[i][j], moment, 0.02);
+                } else {
+                    assert!(properties.inertia[i][j].abs() < moment * 0.01);
+                }
+            }
+        }
+
+        let sampler = Sampler::new(&sphere);
+        assert_close(
+            voxel_volume::<Signed, _>(&sampler, 48).unwrap(),
+            volume,
+            0.02,
+        );
+    }
+
+    #[test]
+    fn test_torus() {
+        let (radius, tube_radius) = (0.25, 0.1);
+        let torus = Centered(Torus::new(radius, tube_radius));
+        let properties = extract(&torus);
+
+        let volume = 2.0 * PI * PI * radius * tube_radius * tube_radius;
+        assert_close(properties.area, 4.0 * PI * PI * radius * tube_radius, 0.01);
+        assert_close(properties.volume, volume, 0.02);
+        assert!((properties.centroid - Vec3::from_scalar(0.5)).len() < 1e-4);
+
+        let sampler = Sampler::new(&torus);
+        assert_close(
+            voxel_volume::<Signed, _>(&sampler, 48).unwrap(),
+            volume,
+            0.03,
+        );
+    }
+
+    #[test]
+    fn test_voxel_volume_of_filled_cube() {
+        // Traversal also visits cells past z = 1, which must not be counted
+        let sphere = Centered(Sphere::new(2.0));
+        let volume = voxel_volume::<Signed, _>(&Sampler::new(&sphere), 16).unwrap();
+        assert!((volume - 1.0).abs() < 1e-5);
+    }
+}
@@ -11,11 +11,12 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::{extractor::Extractor, index_cache::IndexCache, math::Vec3};
-use std::{
-    collections::{hash_set::Iter as HashSetIter, HashMap, HashSet},
-    hash::Hash,
+use crate::{
+    error::Error,
+    extractor::{Vertex, VertexExtractor},
+    index_cache::VertexCache,
 };
+use std::collections::{hash_map::Keys, HashMap};
 
 /// A handle to a specific vertex within a vertex array
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
@@ -44,8 +45,11 @@ pub struct Edge(VertexHandle, VertexHandle);
 pub struct MeshTopology {
     next_vertex: usize,
     faces: Vec<Face>,
-    edges: HashSet<Edge>,
-    edge_to_face: HashMap<Edge, Vec<FaceHandle>>,
+    // Maps each edge to its list of adjoining faces. The lists are kept when
+    // the mesh is cleared, so that refilling the mesh doesn't allocate.
+    edge_to_face: HashMap<Edge, usize>,
+    face_lists: Vec<Vec<FaceHandle>>,
+    face_list_count: usize,
 }
 
 impl MeshTopology {
@@ -54,11 +58,21 @@ impl MeshTopology {
         Self {
             next_vertex: 0,
             faces: vec![],
-            edges: HashSet::new(),
             edge_to_face: HashMap::new(),
+            face_lists: vec![],
+            face_list_count: 0,
         }
     }
 
+    /// Remove every vertex and face, retaining the allocated memory for
+    /// reuse.
+    pub fn clear(&mut self) {
+        self.next_vertex = 0;
+        self.faces.clear();
+        self.edge_to_face.clear();
+        self.face_list_count = 0;
+    }
+
     /// Allocate a new vertex handle. The caller is responsible for
     /// storing the actual vertex data associated with this handle.
     pub fn add_vertex(&mut self) -> VertexHandle {
@@ -72,25 +86,32 @@ impl MeshTopology {
         let face = FaceHandle(self.faces.len());
         self.faces.push(Face([a, b, c]));
 
-        let edge_a = Edge::new(a, b);
-        self.edges.insert(edge_a);
-        self.edge_to_face.entry(edge_a).or_default().push(face);
+        self.link_edge(Edge::new(a, b), face);
+        self.link_edge(Edge::new(b, c), face);
+        self.link_edge(Edge::new(c, a), face);
 
-        let edge_b = Edge::new(b, c);
-        self.edges.insert(edge_b);
-        self.edge_to_face.entry(edge_b).or_default().push(face);
+        face
+    }
 
-        let edge_c = Edge::new(c, a);
-        self.edges.insert(edge_c);
-        self.edge_to_face.entry(edge_c).or_default().push(face);
+    fn link_edge(&mut self, edge: Edge, face: FaceHandle) {
+        let face_lists = &mut self.face_lists;
+        let face_list_count = &mut self.face_list_count;
 
-        face
+        let list = *self.edge_to_face.entry(edge).or_insert_with(|| {
+            if *face_list_count == face_lists.len() {
+                face_lists.push(vec![]);
+            }
+            face_lists[*face_list_count].clear();
+            *face_list_count += 1;
+            *face_list_count - 1
+        });
+        self.face_lists[list].push(face);
     }
 
     /// Build an index buffer from the mesh, suitable for use by rendering APIs
     pub fn extract_indices<E>(&self, extractor: &mut E)
     where
-        E: Extractor,
+        E: VertexExtractor,
     {
         for face in &self.faces {
             for v in &face.0 {
@@ -100,22 +121,49 @@ impl MeshTopology {
     }
 
     /// An iterator over the unique edges in the mesh
-    pub fn edges(&self) -> HashSetIter<Edge> {
-        self.edges.iter()
+    pub fn edges(&self) -> Keys<'_, Edge, usize> {
+        self.edge_to_face.keys()
     }
 
     /// The faces that share a given edge. In an ideal world, meshes would be
     /// manifold, and at most 2 faces would share a single edge. However
     /// isosurface extraction may produce non-manifold meshes with 3 or more
     /// faces sharing an edge.
-    pub fn adjoining_faces(&self, edge: Edge) -> Vec<Face> {
-        self.edge_to_face
-            .get(&edge)
-            .cloned()
-            .unwrap_or_default()
-            .iter()
-            .map(|f| self.faces[f.0])
-            .collect()
+    pub fn adjoining_faces(&self, edge: Edge) -> impl ExactSizeIterator<Item = Face> + '_ {
+        let handles = match self.edge_to_face.get(&edge) {
+            Some(&list) => &self.face_lists[list][..],
+            None => &[],
+        };
+        handles.iter().map(move |f| self.faces[f.0])
+    }
+
+    /// Label each face with the connected component it belongs to, where
+    /// faces sharing an edge are connected. Components are numbered in the
+    /// order of their first face.
+    pub fn connected_components(&self) -> Vec<usize> {
+        let mut labels = vec![usize::MAX; self.faces.len()];
+        let mut next = 0;
+        for seed in 0..self.faces.len() {
+            if labels[seed] != usize::MAX {
+                continue;
+            }
+            labels[seed] = next;
+            let mut stack = vec![seed];
+            while let Some(f) = stack.pop() {
+                let [a, b, c] = self.faces[f].0;
+                for edge in [Edge::new(a, b), Edge::new(b, c), Edge::new(c, a)] {
+                    let list = self.edge_to_face.get(&edge);
+                    for g in list.into_iter().flat_map(|&l| &self.face_lists[l]) {
+                        if labels[g.0] == usize::MAX {
+                            labels[g.0] = next;
+                            stack.push(g.0);
+                        }
+                    }
+                }
+            }
+            next += 1;
+        }
+        labels
     }
 
     /// Rotate an edge within the mesh.
@@ -133,39 +181,39 @@ impl MeshTopology {
     /// represents a crease), and the edge currently runs in the opposite
     /// direction to the crease.
     ///
-    /// Edge rotation only works for edges that are shared by exactly 2 faces,
-    /// so we silently ignore requests to rotate other types of edge.
+    /// Edge rotation only works for edges that are shared by exactly 2
+    /// non-degenerate faces, so we silently ignore requests to rotate other
+    /// types of edge.
     pub fn rotate_edge(&mut self, edge: Edge) {
-        if let Some(adjoining) = self.edge_to_face.get(&edge) {
+        if let Some(&list) = self.edge_to_face.get(&edge) {
             // Only rotate if the edge is adjoining exactly 2 faces
-            if let [handle_a, handle_b] = adjoining[..] {
+            if let [handle_a, handle_b] = self.face_lists[list][..] {
                 let face_a = self.faces[handle_a.0];
                 let face_b = self.faces[handle_b.0];
 
-                // Find the two vertices that aren't on the shared edge
-                let c = face_a.vertex_opposite(edge);
-                let d = face_b.vertex_opposite(edge);
+                // Find the two vertices that aren't on the shared edge. Degenerate
+                // faces may not have one.
+                let (c, d) = match (face_a.vertex_opposite(edge), face_b.vertex_opposite(edge)) {
+                    (Some(c), Some(d)) => (c, d),
+                    _ => return,
+                };
 
                 // We don't know which way the edge runs, so use the vertex winding
                 // to determine if we need to flip it
-                let (u, v) = if face_a.matches_winding_direction(edge) {
-                    (edge.end(), edge.start())
-                } else {
-                    (edge.start(), edge.end())
+                let (u, v) = match face_a.matches_winding_direction(edge) {
+                    Some(true) => (edge.end(), edge.start()),
+                    Some(false) => (edge.start(), edge.end()),
+                    None => return,
                 };
 
                 // Overwrite the two faces with the two new faces
                 self.faces[handle_a.0].0 = [c, d, u];
                 self.faces[handle_b.0].0 = [c, v, d];
 
-                // Add our new edge to the auxiliary tables
-                let e = Edge::new(c, d);
-                self.edges.insert(e);
-                self.edge_to_face.insert(e, vec![handle_a, handle_b]);
-
-                // And finally remove the original edge
-                self.edges.remove(&edge);
+                // Replace the original edge with our new edge in the
+                // auxiliary tables. Both adjoin the same pair of faces.
                 self.edge_to_face.remove(&edge);
+                self.edge_to_face.insert(Edge::new(c, d), list);
             }
         }
     }
@@ -186,27 +234,23 @@ impl Edge {
 impl Face {
     /// Find the vertex in the face that is not on the provided edge.
     /// Note that if you pass an edge that is not part of this face, the
-    /// result will be an arbitrary vertex on this face.
-    pub fn vertex_opposite(&self, edge: Edge) -> VertexHandle {
-        for &v in self.0.iter() {
-            if v != edge.0 && v != edge.1 {
-                return v;
-            }
-        }
-        unreachable!();
+    /// result will be an arbitrary vertex on this face. Degenerate faces,
+    /// which repeat a vertex, may not have an opposite vertex.
+    pub fn vertex_opposite(&self, edge: Edge) -> Option<VertexHandle> {
+        self.0.iter().copied().find(|&v| v != edge.0 && v != edge.1)
     }
 
-    fn matches_winding_direction(&self, edge: Edge) -> bool {
+    fn matches_winding_direction(&self, edge: Edge) -> Option<bool> {
         for i in 0..3 {
             let j = (i + 1) % 3;
 
             if edge.0 == self.0[i] && edge.1 == self.0[j] {
-                return true;
+                return Some(true);
             } else if edge.0 == self.0[j] && edge.1 == self.0[i] {
-                return false;
+                return Some(false);
             }
         }
-        unreachable!();
+        None
     }
 }
 
@@ -222,22 +266,36 @@ impl Edge {
     }
 }
 
-pub struct MeshTopologyBuilder<'a, K: Eq + Hash + Copy, E: Extractor> {
-    index_cache: IndexCache<K, VertexHandle>,
-    mesh: MeshTopology,
-    extractor: &'a mut E,
+pub struct MeshTopologyBuilder<'a, 'b, C: VertexCache<VertexHandle>, E: VertexExtractor> {
+    index_cache: &'a mut C,
+    mesh: &'a mut MeshTopology,
+    extractor: &'b mut E,
+    error: Option<Error>,
 }
 
-impl<'a, K: Eq + Hash + Copy, E: Extractor> MeshTopologyBuilder<'a, K, E> {
-    pub fn new(extractor: &'a mut E) -> Self {
+impl<'a, 'b, C, E> MeshTopologyBuilder<'a, 'b, C, E>
+where
+    C: VertexCache<VertexHandle>,
+    E: VertexExtractor,
+{
+    /// Create a builder which deduplicates vertices using the given cache,
+    /// and builds the mesh in place. Both are cleared first, so that they
+    /// may be reused between extractions without allocating.
+    pub fn new(index_cache: &'a mut C, mesh: &'a mut MeshTopology, extractor: &'b mut E) -> Self {
+        index_cache.clear();
+        mesh.clear();
         Self {
-            index_cache: IndexCache::new(),
-            mesh: MeshTopology::new(),
+            index_cache,
+            mesh,
             extractor,
+            error: None,
         }
     }
 
-    pub fn add_vertex(&mut self, key: Option<K>, vertex: Vec3) -> VertexHandle {
+    /// Add a vertex, or return the existing vertex with the same key. Once the
+    /// mesh has more vertices than the extractor can index, no more vertices
+    /// are extracted, and [build](MeshTopologyBuilder::build) will fail.
+    pub fn add_vertex(&mut self, key: Option<C::Key>, vertex: Vertex) -> VertexHandle {
         if let Some(index) = key.and_then(|k| self.index_cache.get(k)) {
             index
         } else {
@@ -245,7 +303,15 @@ impl<'a, K: Eq + Hash + Copy, E: Extractor> MeshTopologyBuilder<'a, K, E> {
             if let Some(key) = key {
                 self.index_cache.put(key, index);
             }
-            self.extractor.extract_vertex(vertex);
+            let maximum = self.extractor.max_index();
+            if index.0 > maximum {
+                self.error.get_or_insert(Error::IndexOverflow {
+                    index: index.0,
+                    maximum,
+                });
+            } else {
+                self.extractor.extract_vertex(vertex);
+            }
             index
         }
     }
@@ -254,7 +320,10 @@ impl<'a, K: Eq + Hash + Copy, E: Extractor> MeshTopologyBuilder<'a, K, E> {
         self.mesh.add_face(a, b, c);
     }
 
-    pub fn build(self) -> MeshTopology {
-        self.mesh
+    pub fn build(self) -> Result<&'a mut MeshTopology, Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.mesh),
+        }
     }
 }
@@ -12,7 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::math::Vec3;
-use std;
 
 const THREE_2: usize = 9;
 const THREE_1: usize = 3;
@@ -35,6 +34,11 @@ const DILATE_T3: u64 = 0x6DB6_DB6D_B6DB_6DB6; // ~tx
 const LG2_3: f64 = 0.480_898_346_96; // 1.0 / (ln(2) * 3);
 const MAX_LEVEL: usize = (8 * 8 - 1) / 3; // ((sizeof(u64) in bits) - 1) / 3
 
+/// The maximum depth of an octree addressed by Morton codes. Locating the
+/// primal vertices of a node requires one more level of headroom than the node
+/// itself.
+pub const MAX_DEPTH: usize = MAX_LEVEL - 1;
+
 /// Refer to an octree node via interleaved integer coordinates
 #[derive(Default, Hash, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Morton(u64);
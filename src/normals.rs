@@ -0,0 +1,315 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    error::Error,
+    extractor::{Vertex, VertexExtractor},
+    feature::FEATURE_ANGLE,
+    math::Vec3,
+};
+
+/// How the normals of the faces around a vertex contribute to its normal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalWeighting {
+    /// Weight each face by its area, which favours large faces.
+    Area,
+    /// Weight each face by the angle of its corner at the vertex, which
+    /// doesn't depend on how the surface around the vertex was triangulated.
+    Angle,
+}
+
+/// The way in which [WithGeneratedNormals] assigns normals to vertices.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalMode {
+    /// Give each vertex the weighted average of the normals of its faces.
+    Smooth(NormalWeighting),
+    /// Give every face its own vertices, with the normal of the face, so that
+    /// the mesh renders as facets.
+    Flat,
+    /// Average normals only across edges where the cosine of the angle between
+    /// the faces is at least `threshold`, and duplicate vertices along sharper
+    /// edges so that they render as hard creases.
+    Crease {
+        weighting: NormalWeighting,
+        threshold: f32,
+    },
+}
+
+impl NormalMode {
+    /// Crease along edges sharper than the [FEATURE_ANGLE] used to detect
+    /// features during extraction.
+    pub fn crease(weighting: NormalWeighting) -> Self {
+        NormalMode::Crease {
+            weighting,
+            threshold: FEATURE_ANGLE,
+        }
+    }
+}
+
+/// Generate vertex normals from the faces of the mesh, and pass the result to
+/// another extractor.
+///
+/// Unlike sampling normals from a [HermiteSource](crate::source::HermiteSource),
+/// this works for any source, and produces hard edges for faceted shapes (i.e.
+/// the output of [DualContouring](crate::DualContouring)). Since normals
+/// depend on every face around a vertex, the mesh is held until extraction is
+/// complete, and only passed on by [finish](WithGeneratedNormals::finish).
+pub struct WithGeneratedNormals<'a, A, E: VertexExtractor<A>> {
+    mode: NormalMode,
+    extractor: &'a mut E,
+    vertices: Vec<Vertex<A>>,
+    indices: Vec<usize>,
+}
+
+impl<'a, A: Clone, E: VertexExtractor<A>> WithGeneratedNormals<'a, A, E> {
+    pub fn new(mode: NormalMode, extractor: &'a mut E) -> Self {
+        Self {
+            mode,
+            extractor,
+            vertices: vec![],
+            indices: vec![],
+        }
+    }
+
+    /// Generate normals, and output the mesh. Fails if splitting vertices
+    /// along creases produces more vertices than the extractor can index.
+    pub fn finish(self) -> Result<(), Error> {
+        let faces = self.indices.chunks_exact(3).collect::<Vec<_>>();
+        let face_normals = faces
+            .iter()
+            .map(|f| face_normal(f.iter().map(|&i| self.vertices[i].position)))
+            .collect::<Vec<_>>();
+
+        let mut vertex_faces = vec![vec![]; self.vertices.len()];
+        for (f, face) in faces.iter().enumerate() {
+            for (corner, &v) in face.iter().enumerate() {
+                vertex_faces[v].push((f, corner));
+            }
+        }
+
+        // Each vertex is output once for every distinct normal among its
+        // corners
+        let mut output: Vec<Vec<(Vec3, usize)>> = vec![vec![]; self.vertices.len()];
+        let mut next = 0;
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for (f, face) in faces.iter().enumerate() {
+            for &v in face.iter() {
+                let normal = self.corner_normal(f, &vertex_faces[v], &faces, &face_normals);
+                let index = match output[v].iter().find(|(n, _)| *n == normal) {
+                    Some(&(_, index)) => index,
+                    None => {
+                        output[v].push((normal, next));
+                        next += 1;
+                        next - 1
+                    }
+                };
+                indices.push(index);
+            }
+        }
+
+        let maximum = self.extractor.max_index();
+        if next > 0 && next - 1 > maximum {
+            return Err(Error::IndexOverflow {
+                index: next - 1,
+                maximum,
+            });
+        }
+
+        // Vertices are output in order of their index
+        let mut vertices = output
+            .iter()
+            .enumerate()
+            .flat_map(|(v, normals)| normals.iter().map(move |&(n, index)| (index, v, n)))
+            .collect::<Vec<_>>();
+        vertices.sort_unstable_by_key(|&(index, _, _)| index);
+        for (_, v, normal) in vertices {
+            let vertex = &self.vertices[v];
+            self.extractor.extract_vertex(Vertex {
+                normal: normal.normalised().or(vertex.normal),
+                attributes: vertex.attributes.clone(),
+                ..*vertex
+            });
+        }
+        for index in indices {
+            self.extractor.extract_index(index);
+        }
+        Ok(())
+    }
+
+    // The (unnormalised) normal of a vertex as seen from one of its faces
+    fn corner_normal(
+        &self,
+        face: usize,
+        vertex_faces: &[(usize, usize)],
+        faces: &[&[usize]],
+        face_normals: &[Vec3],
+    ) -> Vec3 {
+        let (weighting, threshold) = match self.mode {
+            NormalMode::Flat => return face_normals[face].normalised().unwrap_or_default(),
+            NormalMode::Smooth(weighting) => (weighting, -1.0),
+            NormalMode::Crease {
+                weighting,
+                threshold,
+            } => (weighting, threshold),
+        };
+        let own = face_normals[face].normalised().unwrap_or_default();
+
+        vertex_faces
+            .iter()
+            .filter_map(|&(f, corner)| {
+                let normal = face_normals[f];
+                let unit = normal.normalised()?;
+                if unit.dot(own) < threshold {
+                    return None;
+                }
+                Some(match weighting {
+                    // The face normal is proportional to the area of the face
+                    NormalWeighting::Area => normal,
+                    NormalWeighting::Angle => {
+                        let p = |i: usize| self.vertices[faces[f][(corner + i) % 3]].position;
+                        let u = (p(1) - p(0)).normalised().unwrap_or_default();
+                        let v = (p(2) - p(0)).normalised().unwrap_or_default();
+                        unit * u.dot(v).clamp(-1.0, 1.0).acos()
+                    }
+                })
+            })
+            .fold(Vec3::zero(), |sum, n| sum + n)
+    }
+}
+
+impl<'a, A, E: VertexExtractor<A>> VertexExtractor<A> for WithGeneratedNormals<'a, A, E> {
+    fn extract_vertex(&mut self, vertex: Vertex<A>) {
+        self.vertices.push(vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.indices.push(index);
+    }
+}
+
+// Extracted faces wind clockwise when viewed from outside the surface, so
+// this points outwards, in the same direction as the gradient of the source
+pub(crate) fn face_normal(mut positions: impl Iterator<Item = Vec3>) -> Vec3 {
+    let a = positions.next().unwrap();
+    let b = positions.next().unwrap();
+    let c = positions.next().unwrap();
+    (c - a).cross(b - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed, extractor::VertexOrigin, fixtures::ball, sampler::Sampler,
+        source::HermiteSource, MarchingCubes,
+    };
+
+    #[derive(Default)]
+    struct Records(Vec<Vertex>, Vec<usize>);
+
+    impl VertexExtractor for Records {
+        fn extract_vertex(&mut self, vertex: Vertex) {
+            self.0.push(vertex);
+        }
+
+        fn extract_index(&mut self, index: usize) {
+            self.1.push(index);
+        }
+    }
+
+    fn generate<F>(mode: NormalMode, extract: F) -> Records
+    where
+        F: FnOnce(&mut WithGeneratedNormals<(), Records>),
+    {
+        let mut records = Records::default();
+        let mut generator = WithGeneratedNormals::new(mode, &mut records);
+        extract(&mut generator);
+        generator.finish().unwrap();
+        records
+    }
+
+    #[test]
+    fn test_smooth_normals() {
+        let ball = ball();
+        let sampler = Sampler::new(&ball);
+        for &weighting in &[NormalWeighting::Area, NormalWeighting::Angle] {
+            let records = generate(NormalMode::Smooth(weighting), |generator| {
+                MarchingCubes::<Signed>::new(16)
+                    .unwrap()
+                    .extract(&sampler, generator)
+                    .unwrap()
+            });
+
+            for vertex in &records.0 {
+                let expected = ball.sample_normal(vertex.position).normalised().unwrap();
+                assert!(vertex.normal.unwrap().dot(expected) > 0.98);
+            }
+        }
+    }
+
+    #[test]
+    fn test_creases() {
+        // A unit cube, with faces wound clockwise when viewed from outside
+        let corner = |i: usize| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32);
+        let extract = |generator: &mut WithGeneratedNormals<(), Records>| {
+            for i in 0..8 {
+                generator.extract_vertex(Vertex::new(
+                    corner(i),
+                    VertexOrigin::Cell {
+                        min: Vec3::zero(),
+                        max: Vec3::one(),
+                    },
+                ));
+            }
+            for axis in 0..3 {
+                for &side in &[0, 1] {
+                    let on_side = (0..8)
+                        .filter(|&i| i >> axis & 1 == side)
+                        .collect::<Vec<_>>();
+                    let [a, b, c, d] = [on_side[0], on_side[1], on_side[3], on_side[2]];
+                    for mut face in [[a, b, c], [a, c, d]] {
+                        let outward = corner(a)[axis] - 0.5;
+                        let p = face.map(corner);
+                        if face_normal(p.iter().copied())[axis] * outward < 0.0 {
+                            face.swap(1, 2);
+                        }
+                        for &v in &face {
+                            generator.extract_index(v);
+                        }
+                    }
+                }
+            }
+        };
+
+        let smooth = generate(NormalMode::Smooth(NormalWeighting::Angle), extract);
+        assert_eq!(smooth.0.len(), 8);
+        for vertex in &smooth.0 {
+            let expected = (vertex.position - Vec3::from_scalar(0.5)).normalised();
+            assert!((vertex.normal.unwrap() - expected.unwrap()).len() < 1e-5);
+        }
+
+        // Every face of the cube renders flat, with each corner duplicated
+        // for each of its faces
+        for &mode in &[NormalMode::crease(NormalWeighting::Area), NormalMode::Flat] {
+            let creased = generate(mode, extract);
+            assert_eq!(creased.0.len(), 24);
+            for face in creased.1.chunks(3) {
+                let p = face.iter().map(|&i| creased.0[i].position);
+                let normal = face_normal(p).normalised().unwrap();
+                for &i in face {
+                    assert_eq!(creased.0[i].normal, Some(normal));
+                }
+            }
+        }
+    }
+}
@@ -12,8 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
-    distance::Distance, extractor::Extractor, marching_cubes_impl::classify_corners,
-    sampler::Sample, traversal::PrimalGrid,
+    distance::Distance,
+    error::Error,
+    extractor::VertexExtractor,
+    marching_cubes_impl::{cell_vertex, classify_corners},
+    math::Vec3,
+    sampler::{refine_crossing, Refinement, Sample},
+    traversal::PrimalGrid,
 };
 
 /// Convert isosurfaces to point clouds
@@ -28,16 +33,27 @@ use crate::{
 ///   ideally on the GPU itself.
 pub struct PointCloud<D: Distance> {
     primal_grid: PrimalGrid<D>,
+    refinement: Option<Refinement>,
 }
 
 impl<D: Distance> PointCloud<D> {
     /// Create a new PointCloud with the given chunk size.
     ///
     /// For a given `size`, this will evaluate chunks of `size^3` voxels.
-    pub fn new(size: usize) -> Self {
-        PointCloud {
-            primal_grid: PrimalGrid::new(size),
-        }
+    pub fn new(size: usize) -> Result<Self, Error> {
+        Ok(PointCloud {
+            primal_grid: PrimalGrid::new(size)?,
+            refinement: None,
+        })
+    }
+
+    /// Create a new PointCloud with the given chunk size, which projects each
+    /// point from the center of its cell onto the surface (see [Refinement]).
+    pub fn new_with_refinement(size: usize, refinement: Refinement) -> Result<Self, Error> {
+        Ok(PointCloud {
+            primal_grid: PrimalGrid::new(size)?,
+            refinement: Some(refinement),
+        })
     }
 
     /// Extracts a point cloud from the given [Sample].
@@ -47,18 +63,56 @@ impl<D: Distance> PointCloud<D> {
     ///
     /// The resulting vertex data will be returned via the provided
     /// Extractor. Note that no face data will be produced.
-    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E)
+    pub fn extract<S, E>(&mut self, source: &S, extractor: &mut E) -> Result<(), Error>
     where
         S: Sample<D>,
-        E: Extractor,
+        E: VertexExtractor,
     {
+        let refinement = self.refinement;
+
         self.primal_grid.traverse(source, |_keys, corners, values| {
-            let cube_index = classify_corners(&values);
+            let cube_index = classify_corners(values);
 
             if cube_index != 0 && cube_index != 255 {
-                let p = corners[0].lerp(corners[6], 0.5);
-                extractor.extract_vertex(p);
+                let mut p = corners[0].lerp(corners[6], 0.5);
+                if let Some(refinement) = refinement {
+                    p = Self::project_to_surface(source, refinement, corners, values, p);
+                }
+                extractor.extract_vertex(cell_vertex(p, corners));
             }
-        });
+        })
+    }
+
+    // Search for the surface along the line through the center of the cell
+    // which runs from the inside corners towards the outside corners
+    fn project_to_surface<S>(
+        source: &S,
+        refinement: Refinement,
+        corners: &[Vec3; 8],
+        values: &[D; 8],
+        center: Vec3,
+    ) -> Vec3
+    where
+        S: Sample<D>,
+    {
+        let mut direction = Vec3::zero();
+        for (&corner, value) in corners.iter().zip(values.iter()) {
+            let offset = corner - center;
+            direction += if value.is_positive() { offset } else { -offset };
+        }
+
+        let direction = match direction.normalised() {
+            Some(direction) => direction,
+            None => return center,
+        };
+        let reach = (corners[6] - corners[0]).len() * 0.5;
+        let (p_a, p_b) = (center - direction * reach, center + direction * reach);
+        let (a, b) = (source.sample(p_a), source.sample(p_b));
+
+        if a.is_positive() == b.is_positive() || !a.is_finite() || !b.is_finite() {
+            center
+        } else {
+            refine_crossing(source, refinement, a, b, p_a, p_b)
+        }
     }
 }
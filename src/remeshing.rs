@@ -0,0 +1,269 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    decimation::flips_faces,
+    half_edge::{HalfEdgeMesh, VertexHandle},
+    math::Vec3,
+    sampler::project_onto_surface,
+    source::{Attribute, HermiteSource},
+};
+
+/// Rebuilds a mesh from near-equilateral triangles of a uniform size, which
+/// removes the slivers produced by marching cubes and makes the result
+/// suitable for simulation.
+///
+/// This is an implementation of the incremental remeshing described in [A Remeshing Approach to Multiresolution Modeling](https://dl.acm.org/doi/10.1145/1057432.1057457).
+/// Each iteration splits long edges, collapses short edges, flips edges to
+/// even out vertex valences, and relaxes each vertex towards the centroid of
+/// its neighbours. Vertices are then projected back onto the source surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Remesher {
+    /// The desired length of every edge.
+    pub target_edge_length: f32,
+    /// The number of rounds of remeshing to perform.
+    pub iterations: usize,
+    /// Don't move or remove vertices on the boundary of the mesh.
+    pub preserve_boundaries: bool,
+    /// Don't move or remove vertices flagged as features (i.e. by
+    /// [ExtendedMarchingCubes](crate::ExtendedMarchingCubes)).
+    pub preserve_features: bool,
+}
+
+impl Remesher {
+    /// Create a Remesher which aims for edges of the given length.
+    pub fn new(target_edge_length: f32) -> Self {
+        Self {
+            target_edge_length,
+            iterations: 5,
+            preserve_boundaries: true,
+            preserve_features: true,
+        }
+    }
+
+    /// Remesh in place, projecting vertices onto the surface of the source.
+    pub fn remesh<A, S>(&self, mesh: &mut HalfEdgeMesh<A>, source: &S)
+    where
+        A: Attribute,
+        S: HermiteSource,
+    {
+        let high = self.target_edge_length * 4.0 / 3.0;
+        let low = self.target_edge_length * 4.0 / 5.0;
+
+        for _ in 0..self.iterations {
+            self.split_long_edges(mesh, high);
+            self.collapse_short_edges(mesh, low, high);
+            self.equalise_valences(mesh);
+            self.relax(mesh, source);
+        }
+    }
+
+    fn is_locked<A>(&self, mesh: &HalfEdgeMesh<A>, v: VertexHandle) -> bool {
+        (self.preserve_boundaries && mesh.is_boundary_vertex(v))
+            || (self.preserve_features && mesh.vertex(v).feature)
+    }
+
+    fn split_long_edges<A: Attribute>(&self, mesh: &mut HalfEdgeMesh<A>, high: f32) {
+        // Each split may leave halves which are still too long, so keep going
+        // until there's nothing left to split
+        loop {
+            let mut split = false;
+            for (a, b) in edges(mesh) {
+                if let Some(h) = mesh.find_half_edge(a, b) {
+                    if (mesh.position(a) - mesh.position(b)).len() > high {
+                        mesh.split_edge(h, 0.5);
+                        split = true;
+                    }
+                }
+            }
+            if !split {
+                break;
+            }
+        }
+    }
+
+    fn collapse_short_edges<A>(&self, mesh: &mut HalfEdgeMesh<A>, low: f32, high: f32) {
+        for (a, b) in edges(mesh) {
+            let h = match mesh.find_half_edge(a, b) {
+                Some(h) => h,
+                None => continue,
+            };
+            if (mesh.position(a) - mesh.position(b)).len() >= low {
+                continue;
+            }
+
+            let (remove, keep, position) = match (self.is_locked(mesh, a), self.is_locked(mesh, b))
+            {
+                (true, true) => continue,
+                (true, false) => (b, a, mesh.position(a)),
+                (false, true) => (a, b, mesh.position(b)),
+                (false, false) => (a, b, mesh.position(a).lerp(mesh.position(b), 0.5)),
+            };
+
+            // Don't collapse if that would create new long edges
+            let too_long = mesh
+                .vertex_neighbours(remove)
+                .chain(mesh.vertex_neighbours(keep))
+                .any(|n| n != remove && n != keep && (mesh.position(n) - position).len() > high);
+            if too_long || flips_faces(mesh, remove, keep, position) {
+                continue;
+            }
+
+            if mesh.collapse_edge_into(h, keep).is_some() {
+                mesh.vertex_mut(keep).position = position;
+            }
+        }
+    }
+
+    fn equalise_valences<A>(&self, mesh: &mut HalfEdgeMesh<A>) {
+        let deviation = |mesh: &HalfEdgeMesh<A>, v: VertexHandle, change: isize| {
+            let target = if mesh.is_boundary_vertex(v) { 4 } else { 6 };
+            let valence = mesh.vertex_neighbours(v).count() as isize + change;
+            (valence - target).abs()
+        };
+
+        for (a, b) in edges(mesh) {
+            let h = match mesh.find_half_edge(a, b) {
+                Some(h) => h,
+                None => continue,
+            };
+            let t = match mesh.twin(h) {
+                Some(t) => t,
+                None => continue,
+            };
+            // Flipping an edge between two features would lose the crease
+            if self.preserve_features && mesh.vertex(a).feature && mesh.vertex(b).feature {
+                continue;
+            }
+            let (c, d) = (mesh.opposite_vertex(h), mesh.opposite_vertex(t));
+
+            let before = deviation(mesh, a, 0)
+                + deviation(mesh, b, 0)
+                + deviation(mesh, c, 0)
+                + deviation(mesh, d, 0);
+            let after = deviation(mesh, a, -1)
+                + deviation(mesh, b, -1)
+                + deviation(mesh, c, 1)
+                + deviation(mesh, d, 1);
+            if after >= before {
+                continue;
+            }
+
+            // Only flip if the two faces form a convex quad, otherwise the flipped
+            // faces would face the wrong way
+            let p = [a, b, c, d].map(|v| mesh.position(v));
+            let normal = (p[1] - p[0]).cross(p[2] - p[0]) + (p[0] - p[1]).cross(p[3] - p[1]);
+            let first = (p[3] - p[0]).cross(p[2] - p[0]);
+            let second = (p[1] - p[3]).cross(p[2] - p[3]);
+            if first.dot(normal) > 0.0 && second.dot(normal) > 0.0 {
+                mesh.flip_edge(h);
+            }
+        }
+    }
+
+    fn relax<A, S: HermiteSource>(&self, mesh: &mut HalfEdgeMesh<A>, source: &S) {
+        // Compute every new position before moving anything, so that the
+        // result doesn't depend on the order in which vertices are visited
+        let moves = mesh
+            .vertices()
+            .filter(|&v| !mesh.is_boundary_vertex(v) && !self.is_locked(mesh, v))
+            .filter_map(|v| {
+                let p = mesh.position(v);
+                let (sum, count) = mesh
+                    .vertex_neighbours(v)
+                    .fold((Vec3::zero(), 0.0), |(sum, count), n| {
+                        (sum + mesh.position(n), count + 1.0)
+                    });
+                if count == 0.0 {
+                    return None;
+                }
+                let centroid = sum / count;
+
+                // Only move within the tangent plane, and let projection take
+                // care of the rest
+                let normal = source.sample_normal(p).normalised().unwrap_or_default();
+                let target = centroid + normal * normal.dot(p - centroid);
+                Some((v, project_onto_surface(source, target)))
+            })
+            .collect::<Vec<_>>();
+
+        for (v, p) in moves {
+            let vertex = mesh.vertex_mut(v);
+            vertex.position = p;
+            vertex.normal = source.sample_normal(p).normalised();
+        }
+    }
+}
+
+// Snapshot the edges of the mesh, as vertex pairs which survive modification
+fn edges<A>(mesh: &HalfEdgeMesh<A>) -> Vec<(VertexHandle, VertexHandle)> {
+    mesh.half_edges()
+        .filter(|&h| mesh.twin(h).is_none_or(|t| h < t))
+        .map(|h| (mesh.start(h), mesh.end(h)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed, fixtures::ball, sampler::Sampler, source::ScalarSource, MarchingCubes,
+    };
+
+    // The fraction of faces with an angle of less than 30º
+    fn sliver_fraction<A>(mesh: &HalfEdgeMesh<A>) -> f32 {
+        let slivers = mesh
+            .faces()
+            .filter(|&f| {
+                let p = mesh.face_vertices(f).map(|v| mesh.position(v));
+                (0..3).any(|i| {
+                    let u = (p[(i + 1) % 3] - p[i]).normalised().unwrap_or_default();
+                    let v = (p[(i + 2) % 3] - p[i]).normalised().unwrap_or_default();
+                    u.dot(v) > 0.866
+                })
+            })
+            .count();
+        slivers as f32 / mesh.face_count() as f32
+    }
+
+    #[test]
+    fn test_remeshing() {
+        let ball = ball();
+        let mut mesh = HalfEdgeMesh::<()>::new();
+        MarchingCubes::<Signed>::new(16)
+            .unwrap()
+            .extract(&Sampler::new(&ball), &mut mesh)
+            .unwrap();
+        let slivers = sliver_fraction(&mesh);
+
+        let length = 0.05;
+        Remesher::new(length).remesh(&mut mesh, &ball);
+
+        assert!(mesh.boundary_loops().is_empty());
+        let pairs = edges(&mesh);
+        assert_eq!(mesh.vertex_count() + mesh.face_count() - pairs.len(), 2);
+
+        for v in mesh.vertices() {
+            assert!(ball.sample_scalar(mesh.position(v)).0.abs() < 1e-5);
+        }
+
+        let total = pairs
+            .iter()
+            .map(|&(a, b)| (mesh.position(a) - mesh.position(b)).len())
+            .sum::<f32>();
+        let mean = total / pairs.len() as f32;
+        assert!((mean - length).abs() < length * 0.2);
+
+        assert!(sliver_fraction(&mesh) < slivers / 2.0);
+    }
+}
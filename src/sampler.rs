@@ -12,9 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::Program,
     distance::{Directed, Distance, Signed},
-    math::Vec3,
-    source::{HermiteSource, ScalarSource, VectorSource},
+    error::Error,
+    math::{Interval, Vec3},
+    source::{AttributeSource, HermiteSource, IntervalSource, ScalarSource, VectorSource},
 };
 
 /// Sample a distance field defined in terms of a specific [Distance] metric.
@@ -22,6 +24,119 @@ pub trait Sample<D: Distance>: Sized {
     fn sample(&self, p: Vec3) -> D;
 }
 
+/// Sample the source, failing if the resulting distance is NaN or infinite.
+pub(crate) fn sample_finite<D, S>(source: &S, point: Vec3) -> Result<D, Error>
+where
+    D: Distance,
+    S: Sample<D>,
+{
+    let distance = source.sample(point);
+    if distance.is_finite() {
+        Ok(distance)
+    } else {
+        Err(Error::NonFiniteSample { point })
+    }
+}
+
+/// Move a point onto the surface by Newton steps along the gradient. Assumes the
+/// gradient has unit length, as in a true distance field, and uses the
+/// normalised normal in its place.
+pub(crate) fn project_onto_surface<S: HermiteSource>(source: &S, mut p: Vec3) -> Vec3 {
+    for _ in 0..8 {
+        let d = source.sample_scalar(p).0;
+        if d.abs() < 1e-7 {
+            break;
+        }
+        match source.sample_normal(p).normalised() {
+            Some(n) => p -= n * d,
+            None => break,
+        }
+    }
+    p
+}
+
+/// Options for refining the positions at which the surface crosses the edges
+/// of the sampling grid.
+///
+/// By default, extraction algorithms assume the distance field varies linearly
+/// along each edge. That places vertices off the surface of non-linear fields
+/// (i.e. noise, or anything which isn't a true distance field). Refinement
+/// instead searches along each edge, resampling the source until the crossing
+/// is found.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Refinement {
+    /// Stop searching once the crossing is known to within this distance.
+    pub tolerance: f32,
+    /// The maximum number of samples taken for each crossing.
+    pub max_iterations: usize,
+}
+
+impl Refinement {
+    pub fn new(tolerance: f32, max_iterations: usize) -> Self {
+        Self {
+            tolerance,
+            max_iterations,
+        }
+    }
+}
+
+/// Find the zero-crossing between two points with distances of opposite
+/// signs, by false position search (with the Illinois modification, so that
+/// the bracket shrinks from both ends).
+pub(crate) fn refine_crossing<D, S>(
+    source: &S,
+    refinement: Refinement,
+    mut a: D,
+    mut b: D,
+    mut p_a: Vec3,
+    mut p_b: Vec3,
+) -> Vec3
+where
+    D: Distance,
+    S: Sample<D>,
+{
+    let mut p = D::find_crossing_point(a, b, p_a, p_b);
+    let mut kept_a = None;
+
+    for _ in 0..refinement.max_iterations {
+        if (p_b - p_a).len() < refinement.tolerance {
+            break;
+        }
+
+        let d = source.sample(p);
+        if !d.is_finite() {
+            break;
+        }
+
+        // Replace whichever end has the same sign, and if the other end has
+        // been kept twice in a row, halve its distance
+        if d.is_positive() == a.is_positive() {
+            a = d;
+            p_a = p;
+            if kept_a == Some(false) {
+                b = D::zero().lerp(b, 0.5);
+            }
+            kept_a = Some(false);
+        } else {
+            b = d;
+            p_b = p;
+            if kept_a == Some(true) {
+                a = D::zero().lerp(a, 0.5);
+            }
+            kept_a = Some(true);
+        }
+
+        let next = D::find_crossing_point(a, b, p_a, p_b);
+        let step = (next - p).len();
+        p = next;
+        if step < refinement.tolerance {
+            break;
+        }
+    }
+
+    p
+}
+
 /// Samplers abstract sampling across multiple different [Distance] metrics
 pub struct Sampler<'a, S> {
     pub source: &'a S,
@@ -52,6 +167,22 @@ impl<'a, S: ScalarSource> ScalarSource for Sampler<'a, S> {
     }
 }
 
+impl<'a, S: IntervalSource> IntervalSource for Sampler<'a, S> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.source.sample_interval(min, max)
+    }
+
+    fn specialise(&self, min: Vec3, max: Vec3) -> Option<Program> {
+        self.source.specialise(min, max)
+    }
+}
+
+impl<'a, T, S: AttributeSource<T>> AttributeSource<T> for Sampler<'a, S> {
+    fn sample_attribute(&self, p: Vec3) -> T {
+        self.source.sample_attribute(p)
+    }
+}
+
 impl<'a, S: VectorSource + ScalarSource> VectorSource for Sampler<'a, S> {
     fn sample_vector(&self, p: Vec3) -> Directed {
         self.source.sample_vector(p)
@@ -63,3 +194,56 @@ impl<'a, S: HermiteSource> HermiteSource for Sampler<'a, S> {
         self.source.sample_normal(p)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extractor::OnlyVertices, MarchingCubes, PointCloud};
+
+    // The squared distance to a sphere, which varies quadratically across each
+    // grid edge
+    struct Quadratic;
+
+    impl ScalarSource for Quadratic {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            Signed((p - Vec3::from_scalar(0.5)).len_sq() - 0.09)
+        }
+    }
+
+    fn max_error(vertices: &[f32]) -> f32 {
+        vertices
+            .chunks(3)
+            .map(|v| ((Vec3::new(v[0], v[1], v[2]) - Vec3::from_scalar(0.5)).len() - 0.3).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_refinement() {
+        let sampler = Sampler::new(&Quadratic);
+        let refinement = Refinement::new(1e-6, 16);
+
+        let mut linear = vec![];
+        MarchingCubes::<Signed>::new(8)
+            .unwrap()
+            .extract(&sampler, &mut OnlyVertices::new(&mut linear))
+            .unwrap();
+
+        let mut refined = vec![];
+        MarchingCubes::<Signed>::new_with_refinement(8, refinement)
+            .unwrap()
+            .extract(&sampler, &mut OnlyVertices::new(&mut refined))
+            .unwrap();
+
+        assert_eq!(linear.len(), refined.len());
+        assert!(max_error(&linear) > 0.001);
+        assert!(max_error(&refined) < 1e-5);
+
+        let mut points = vec![];
+        PointCloud::<Signed>::new_with_refinement(8, refinement)
+            .unwrap()
+            .extract(&sampler, &mut OnlyVertices::new(&mut points))
+            .unwrap();
+        assert!(!points.is_empty());
+        assert!(max_error(&points) < 1e-5);
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    extractor::{Vertex, VertexOrigin},
+    half_edge::HalfEdgeMesh,
+    math::Vec3,
+};
+
+/// The smoothing algorithm applied by a [Smoother].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SmoothingMethod {
+    /// Move each vertex a fraction `lambda` of the way towards the centroid of
+    /// its neighbours. Simple, but shrinks the mesh.
+    Laplacian { lambda: f32 },
+    /// Alternate a Laplacian step of `lambda` with an inflating step of `mu`
+    /// (which should be negative, and slightly larger in magnitude than
+    /// `lambda`), which smooths without shrinking.
+    ///
+    /// See [Curve and Surface Smoothing Without Shrinkage](https://doi.org/10.1109/ICCV.1995.466848).
+    Taubin { lambda: f32, mu: f32 },
+    /// Follow each Laplacian step by pushing vertices back towards their
+    /// original positions, weighted by `alpha`, and towards the positions
+    /// of the previous iteration, weighted by `beta`.
+    ///
+    /// See [Improved Laplacian Smoothing of Noisy Surface Meshes](https://doi.org/10.1111/1467-8659.00334).
+    HC { alpha: f32, beta: f32 },
+}
+
+/// Smooths the surface of a mesh without changing its connectivity.
+///
+/// This is most useful for removing the terraces produced by running
+/// marching cubes over voxel data, where every vertex lies at the midpoint of
+/// a grid edge.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Smoother {
+    pub method: SmoothingMethod,
+    /// The number of smoothing passes to apply.
+    pub iterations: usize,
+    /// Don't move vertices flagged as features (i.e. by
+    /// [ExtendedMarchingCubes](crate::ExtendedMarchingCubes)).
+    pub pin_features: bool,
+    /// Don't move vertices on the boundary of the mesh.
+    pub pin_boundaries: bool,
+    /// Don't let vertices leave the region of the grid they were generated in
+    /// (see [VertexOrigin]), so that the smoothed mesh still describes the same
+    /// volume at the resolution of the grid.
+    ///
+    /// Vertices generated on a grid edge may move up to half the length of the
+    /// edge away from it, while vertices generated inside a cell may move
+    /// anywhere within the cell.
+    pub constrain_to_cell: bool,
+}
+
+impl Smoother {
+    /// Create a Smoother which performs 10 iterations of the given method,
+    /// and pins feature and boundary vertices.
+    pub fn new(method: SmoothingMethod) -> Self {
+        Self {
+            method,
+            iterations: 10,
+            pin_features: true,
+            pin_boundaries: true,
+            constrain_to_cell: false,
+        }
+    }
+
+    /// Smooth the mesh in place.
+    pub fn smooth<A>(&self, mesh: &mut HalfEdgeMesh<A>) {
+        let vertices = mesh.vertices().collect::<Vec<_>>();
+        let size = vertices.last().map_or(0, |v| v.index() + 1);
+        let mut neighbours = vec![vec![]; size];
+        let mut pinned = vec![true; size];
+        let mut positions = vec![Vec3::zero(); size];
+
+        for &v in &vertices {
+            let i = v.index();
+            neighbours[i] = mesh.vertex_neighbours(v).map(|n| n.index()).collect();
+            pinned[i] = neighbours[i].is_empty()
+                || (self.pin_features && mesh.vertex(v).feature)
+                || (self.pin_boundaries && mesh.is_boundary_vertex(v));
+            positions[i] = mesh.position(v);
+        }
+        let original = positions.clone();
+
+        let smoothing = Smoothing {
+            neighbours: &neighbours,
+            pinned: &pinned,
+        };
+        for _ in 0..self.iterations {
+            match self.method {
+                SmoothingMethod::Laplacian { lambda } => {
+                    positions = smoothing.laplacian(&positions, lambda);
+                }
+                SmoothingMethod::Taubin { lambda, mu } => {
+                    positions = smoothing.laplacian(&positions, lambda);
+                    positions = smoothing.laplacian(&positions, mu);
+                }
+                SmoothingMethod::HC { alpha, beta } => {
+                    positions = smoothing.hc(&original, &positions, alpha, beta);
+                }
+            }
+
+            if self.constrain_to_cell {
+                for &v in &vertices {
+                    let p = &mut positions[v.index()];
+                    *p = constrain(mesh.vertex(v), *p);
+                }
+            }
+        }
+
+        for v in vertices {
+            mesh.vertex_mut(v).position = positions[v.index()];
+        }
+    }
+}
+
+struct Smoothing<'a> {
+    neighbours: &'a [Vec<usize>],
+    pinned: &'a [bool],
+}
+
+impl<'a> Smoothing<'a> {
+    fn centroid(&self, positions: &[Vec3], i: usize) -> Vec3 {
+        let sum = self.neighbours[i]
+            .iter()
+            .fold(Vec3::zero(), |sum, &n| sum + positions[n]);
+        sum / self.neighbours[i].len() as f32
+    }
+
+    fn laplacian(&self, positions: &[Vec3], lambda: f32) -> Vec<Vec3> {
+        (0..positions.len())
+            .map(|i| {
+                if self.pinned[i] {
+                    positions[i]
+                } else {
+                    positions[i] + (self.centroid(positions, i) - positions[i]) * lambda
+                }
+            })
+            .collect()
+    }
+
+    fn hc(&self, original: &[Vec3], previous: &[Vec3], alpha: f32, beta: f32) -> Vec<Vec3> {
+        let smoothed = self.laplacian(previous, 1.0);
+
+        // The distance each vertex moved away from a blend of its original and
+        // previous positions
+        let differences = (0..previous.len())
+            .map(|i| smoothed[i] - (original[i] * alpha + previous[i] * (1.0 - alpha)))
+            .collect::<Vec<_>>();
+
+        (0..previous.len())
+            .map(|i| {
+                if self.pinned[i] {
+                    return previous[i];
+                }
+                let average = self.centroid(&differences, i);
+                smoothed[i] - (differences[i] * beta + average * (1.0 - beta))
+            })
+            .collect()
+    }
+}
+
+// Clamp a position to the region of the grid in which the vertex was generated
+fn constrain<A>(vertex: &Vertex<A>, p: Vec3) -> Vec3 {
+    let (min, max) = match vertex.origin {
+        VertexOrigin::Edge { start, end, .. } => {
+            // Allow movement up to half a cell perpendicular to the edge, but
+            // not past either end
+            let axis = (end - start).abs();
+            let margin = (Vec3::from_scalar(axis.max_component()) - axis) * 0.5;
+            (start.min(end) - margin, start.max(end) + margin)
+        }
+        VertexOrigin::Cell { min, max } => (min, max),
+    };
+    p.max(min).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed, implicit::RectangularPrism, sampler::Sampler, source::ScalarSource,
+        ExtendedMarchingCubes, MarchingCubes,
+    };
+
+    // A voxelised sphere, which is either fully inside or fully outside
+    struct Voxels;
+
+    impl ScalarSource for Voxels {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            let inside = (p - Vec3::from_scalar(0.5)).len() < 0.3;
+            Signed(if inside { -1.0 } else { 1.0 })
+        }
+    }
+
+    // The mean and standard deviation of the distance of each vertex from the
+    // center of the sphere
+    fn radii<A>(mesh: &HalfEdgeMesh<A>) -> (f32, f32) {
+        let radii = mesh
+            .vertices()
+            .map(|v| (mesh.position(v) - Vec3::from_scalar(0.5)).len())
+            .collect::<Vec<_>>();
+        let mean = radii.iter().sum::<f32>() / radii.len() as f32;
+        let variance = radii.iter().map(|r| (r - mean) * (r - mean)).sum::<f32>();
+        (mean, (variance / radii.len() as f32).sqrt())
+    }
+
+    // The number of grid points along each axis of the voxel sphere
+    const SIZE: usize = 24;
+
+    fn voxel_sphere() -> HalfEdgeMesh {
+        let mut mesh = HalfEdgeMesh::new();
+        MarchingCubes::<Signed>::new(SIZE)
+            .unwrap()
+            .extract(&Sampler::new(&Voxels), &mut mesh)
+            .unwrap();
+        mesh
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let (mean, deviation) = radii(&voxel_sphere());
+
+        let methods = [
+            SmoothingMethod::Laplacian { lambda: 0.5 },
+            SmoothingMethod::Taubin {
+                lambda: 0.5,
+                mu: -0.53,
+            },
+            SmoothingMethod::HC {
+                alpha: 0.0,
+                beta: 0.5,
+            },
+        ];
+        let results = methods
+            .iter()
+            .map(|&method| {
+                let mut mesh = voxel_sphere();
+                Smoother::new(method).smooth(&mut mesh);
+                radii(&mesh)
+            })
+            .collect::<Vec<_>>();
+
+        for &(_, smoothed) in &results {
+            assert!(smoothed < deviation / 2.0);
+        }
+        // Laplacian smoothing shrinks the sphere, while the others don't
+        let shrinkage = |(smoothed, _): (f32, f32)| mean - smoothed;
+        assert!(shrinkage(results[1]) < shrinkage(results[0]) / 2.0);
+        assert!(shrinkage(results[2]) < shrinkage(results[0]) / 2.0);
+
+        // Constrained vertices stay close to the edges they came from
+        let mut mesh = voxel_sphere();
+        let original = mesh.vertices().map(|v| *mesh.vertex(v)).collect::<Vec<_>>();
+        let mut smoother = Smoother::new(methods[1]);
+        smoother.constrain_to_cell = true;
+        smoother.smooth(&mut mesh);
+        // The spacing of the grid points the mesh was extracted from
+        let cell = 1.0 / (SIZE - 1) as f32;
+        for (v, before) in mesh.vertices().zip(original) {
+            assert!(
+                (mesh.position(v) - before.position).abs().max_component() <= cell * 0.5 + 1e-6
+            );
+        }
+        assert!(radii(&mesh).1 < deviation);
+    }
+
+    #[test]
+    fn test_pinned_features() {
+        let prism = RectangularPrism::new(Vec3::from_scalar(0.2));
+        let mut mesh = HalfEdgeMesh::<()>::new();
+        ExtendedMarchingCubes::new(16)
+            .unwrap()
+            .extract(&Sampler::new(&prism), &mut mesh)
+            .unwrap();
+        let pinned = |mesh: &HalfEdgeMesh| {
+            mesh.vertices()
+                .filter(|&v| mesh.vertex(v).feature || mesh.is_boundary_vertex(v))
+                .map(|v| mesh.position(v))
+                .collect::<Vec<_>>()
+        };
+        let before = pinned(&mesh);
+
+        Smoother::new(SmoothingMethod::Laplacian { lambda: 0.5 }).smooth(&mut mesh);
+        assert_eq!(before, pinned(&mesh));
+    }
+}
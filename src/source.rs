@@ -12,9 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
+    bytecode::{Compile, Compiler, Point, Program, Register},
     distance::{Directed, Signed},
-    math::Vec3,
+    math::{Interval, Vec2, Vec3},
 };
+use std::cell::{Cell, RefCell};
 
 /// A source capable of sampling a signed distance field at discrete
 /// coordinates.
@@ -27,6 +29,112 @@ pub trait ScalarSource {
     fn sample_scalar(&self, p: Vec3) -> Signed;
 }
 
+/// A source capable of sampling a signed distance field at many coordinates
+/// in a single call, amortising any per-sample overhead.
+pub trait BatchScalarSource: ScalarSource {
+    /// Samples the distance field at each of the given coordinates, and
+    /// writes the result to the matching entry in `distances`.
+    ///
+    /// The default implementation simply samples each coordinate in turn.
+    fn sample_scalar_batch(&self, points: &[Vec3], distances: &mut [Signed]) {
+        for (&p, d) in points.iter().zip(distances.iter_mut()) {
+            *d = self.sample_scalar(p);
+        }
+    }
+}
+
+/// A source capable of bounding a signed distance field over a whole region.
+pub trait IntervalSource: ScalarSource {
+    /// Returns bounds on the signed distance anywhere within the axis-aligned
+    /// box from `min` to `max`.
+    ///
+    /// The bounds must be conservative: the value at every point in the box
+    /// must lie within the returned [Interval], although the interval may be
+    /// wider than necessary. This allows callers to prove that a region
+    /// contains no surface, and skip it entirely.
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval;
+
+    /// Returns a program which samples the same distance field as this source
+    /// anywhere within the axis-aligned box from `min` to `max`, but which is
+    /// cheaper to evaluate there. Outside the box, the program's results are
+    /// undefined.
+    ///
+    /// Bounded traversals specialise the source to each region they sample.
+    /// The default implementation returns None, and the source itself is
+    /// sampled instead.
+    fn specialise(&self, _min: Vec3, _max: Vec3) -> Option<Program> {
+        None
+    }
+}
+
+/// A source of arbitrary user attributes (i.e. colours, materials, or texture
+/// coordinates) to attach to each vertex.
+pub trait AttributeSource<T> {
+    /// Samples the attribute at the given (x, y, z) coordinates.
+    fn sample_attribute(&self, p: Vec3) -> T;
+}
+
+/// A value which can be interpolated, for use as a vertex attribute.
+///
+/// Attributes are sampled at either end of the grid edge on which a vertex
+/// lies, and interpolated with the same factor used to place the vertex.
+pub trait Attribute {
+    /// Interpolate a fraction `factor` of the way from `self` to `other`.
+    fn lerp(&self, other: &Self, factor: f32) -> Self;
+}
+
+impl Attribute for () {
+    fn lerp(&self, _: &Self, _: f32) -> Self {}
+}
+
+impl Attribute for f32 {
+    fn lerp(&self, other: &Self, factor: f32) -> Self {
+        (1.0 - factor) * self + factor * other
+    }
+}
+
+impl Attribute for Vec2 {
+    fn lerp(&self, other: &Self, factor: f32) -> Self {
+        *self * (1.0 - factor) + *other * factor
+    }
+}
+
+impl Attribute for Vec3 {
+    fn lerp(&self, other: &Self, factor: f32) -> Self {
+        Vec3::lerp(self, *other, factor)
+    }
+}
+
+impl<const N: usize> Attribute for [f32; N] {
+    fn lerp(&self, other: &Self, factor: f32) -> Self {
+        let mut result = *self;
+        for (r, o) in result.iter_mut().zip(other.iter()) {
+            *r = Attribute::lerp(r, o, factor);
+        }
+        result
+    }
+}
+
+// Discrete attributes (i.e. material IDs) can't be blended, so take whichever
+// value is nearest
+macro_rules! impl_discrete_attribute {
+    ($($t:ty),*) => {
+        $(
+            impl Attribute for $t {
+                fn lerp(&self, other: &Self, factor: f32) -> Self {
+                    if factor < 0.5 {
+                        *self
+                    } else {
+                        *other
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_discrete_attribute!(u8, u16, u32);
+
 /// A source capable of sampling a directed distance field at discrete
 /// coordinates.
 pub trait VectorSource {
@@ -79,6 +187,21 @@ impl<S: VectorSource + ScalarSource> VectorSource for CentralDifference<S> {
     }
 }
 
+impl<S: ScalarSource + Compile> Compile for CentralDifference<S> {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        self.source.compile(compiler, p)
+    }
+}
+
+impl<S: IntervalSource> IntervalSource for CentralDifference<S> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.source.sample_interval(min, max)
+    }
+    fn specialise(&self, min: Vec3, max: Vec3) -> Option<Program> {
+        self.source.specialise(min, max)
+    }
+}
+
 impl<S: ScalarSource> HermiteSource for CentralDifference<S> {
     fn sample_normal(&self, p: Vec3) -> Vec3 {
         let dx = Vec3::new(self.epsilon, 0.0, 0.0);
@@ -93,3 +216,286 @@ impl<S: ScalarSource> HermiteSource for CentralDifference<S> {
         Vec3::new(vx, vy, vz) / (2.0 * self.epsilon)
     }
 }
+
+/// Counts of the lookups made in a [CachedSource].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStatistics {
+    /// Lookups answered from the cache.
+    pub hits: usize,
+    /// Lookups which had to sample the underlying source.
+    pub misses: usize,
+    /// Cached samples discarded to make room for another position.
+    pub evictions: usize,
+}
+
+impl CacheStatistics {
+    /// The fraction of lookups answered from the cache.
+    pub fn hit_rate(&self) -> f32 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f32 / lookups as f32
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct CacheEntry {
+    key: Option<[i64; 3]>,
+    scalar: Option<Signed>,
+    normal: Option<Vec3>,
+}
+
+/// Adapts an expensive source by memoising its scalar and normal samples.
+///
+/// Extractors often sample the same point more than once: dual contouring
+/// samples the normal at each grid point once for every cell sharing it, and
+/// normals are sampled at points where the distance has already been found.
+/// Wrapping the source (and any [CentralDifference] used to derive its
+/// normals) in a CachedSource, and passing that to the extractor, shares the
+/// samples between every pass over the grid.
+///
+/// Samples are keyed by their position, rounded to a multiple of `quantum`,
+/// so positions closer together than that share a sample. The cache holds a
+/// fixed number of positions in a spatially hashed table, and a new position
+/// evicts whichever position it collides with.
+pub struct CachedSource<S> {
+    pub source: S,
+    quantum: f32,
+    entries: RefCell<Vec<CacheEntry>>,
+    statistics: Cell<CacheStatistics>,
+}
+
+impl<S> CachedSource<S> {
+    /// Create a cache holding up to 65536 positions, with a quantum fine
+    /// enough to distinguish any two points in the unit cube.
+    pub fn new(source: S) -> Self {
+        Self::new_with_capacity(source, 1 << 16, 1.0 / (1 << 24) as f32)
+    }
+
+    /// Create a cache holding up to `capacity` positions (rounded up to a
+    /// power of two), which rounds positions to a multiple of `quantum`.
+    pub fn new_with_capacity(source: S, capacity: usize, quantum: f32) -> Self {
+        let empty = CacheEntry {
+            key: None,
+            scalar: None,
+            normal: None,
+        };
+
+        Self {
+            source,
+            quantum,
+            entries: RefCell::new(vec![empty; capacity.max(1).next_power_of_two()]),
+            statistics: Cell::new(CacheStatistics::default()),
+        }
+    }
+
+    /// The hits and misses since the cache was created or cleared.
+    pub fn statistics(&self) -> CacheStatistics {
+        self.statistics.get()
+    }
+
+    /// Discard every cached sample, and reset the statistics. Necessary if
+    /// the underlying source changes.
+    pub fn clear(&mut self) {
+        for entry in self.entries.get_mut().iter_mut() {
+            entry.key = None;
+        }
+        self.statistics.set(CacheStatistics::default());
+    }
+
+    // Look up one of the values cached at p, or sample and cache it
+    fn lookup<T, G, F>(&self, p: Vec3, get: G, sample: F) -> T
+    where
+        G: Fn(&mut CacheEntry) -> &mut Option<T>,
+        F: FnOnce() -> T,
+        T: Copy,
+    {
+        let key = [p.x, p.y, p.z].map(|c| (c / self.quantum).round() as i64);
+        let mut hash = (key[0].wrapping_mul(73_856_093)
+            ^ key[1].wrapping_mul(19_349_663)
+            ^ key[2].wrapping_mul(83_492_791)) as u64;
+
+        // Keys are usually multiples of the grid spacing, so mix the high bits
+        // into the low bits used to pick a slot
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let hash = (hash ^ (hash >> 31)) as usize;
+
+        let mut statistics = self.statistics.get();
+        {
+            let mut entries = self.entries.borrow_mut();
+            let slot = hash & (entries.len() - 1);
+            if entries[slot].key == Some(key) {
+                if let Some(value) = *get(&mut entries[slot]) {
+                    statistics.hits += 1;
+                    self.statistics.set(statistics);
+                    return value;
+                }
+            }
+        }
+
+        // Don't hold the borrow while sampling, in case the source itself
+        // samples this cache
+        let value = sample();
+        statistics.misses += 1;
+
+        let mut entries = self.entries.borrow_mut();
+        let slot = hash & (entries.len() - 1);
+        let entry = &mut entries[slot];
+        if entry.key != Some(key) {
+            if entry.key.is_some() {
+                statistics.evictions += 1;
+            }
+            *entry = CacheEntry {
+                key: Some(key),
+                scalar: None,
+                normal: None,
+            };
+        }
+        *get(entry) = Some(value);
+        self.statistics.set(statistics);
+        value
+    }
+}
+
+impl<S: ScalarSource> ScalarSource for CachedSource<S> {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        self.lookup(p, |e| &mut e.scalar, || self.source.sample_scalar(p))
+    }
+}
+
+impl<S: HermiteSource> HermiteSource for CachedSource<S> {
+    fn sample_normal(&self, p: Vec3) -> Vec3 {
+        self.lookup(p, |e| &mut e.normal, || self.source.sample_normal(p))
+    }
+}
+
+impl<S: VectorSource> VectorSource for CachedSource<S> {
+    fn sample_vector(&self, p: Vec3) -> Directed {
+        self.source.sample_vector(p)
+    }
+}
+
+// Specialised programs would bypass the cache, so the source is never
+// specialised
+impl<S: IntervalSource> IntervalSource for CachedSource<S> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.source.sample_interval(min, max)
+    }
+}
+
+/// Attaches a constant attribute to every point of a source. Combine painted
+/// sources with the CSG operators to vary attributes over a surface.
+pub struct Painted<S, T> {
+    pub source: S,
+    pub attribute: T,
+}
+
+impl<S, T> Painted<S, T> {
+    pub fn new(source: S, attribute: T) -> Self {
+        Self { source, attribute }
+    }
+}
+
+impl<S: ScalarSource, T> ScalarSource for Painted<S, T> {
+    fn sample_scalar(&self, p: Vec3) -> Signed {
+        self.source.sample_scalar(p)
+    }
+}
+
+impl<S: VectorSource, T> VectorSource for Painted<S, T> {
+    fn sample_vector(&self, p: Vec3) -> Directed {
+        self.source.sample_vector(p)
+    }
+}
+
+impl<S: HermiteSource, T> HermiteSource for Painted<S, T> {
+    fn sample_normal(&self, p: Vec3) -> Vec3 {
+        self.source.sample_normal(p)
+    }
+}
+
+impl<S: IntervalSource, T> IntervalSource for Painted<S, T> {
+    fn sample_interval(&self, min: Vec3, max: Vec3) -> Interval {
+        self.source.sample_interval(min, max)
+    }
+    fn specialise(&self, min: Vec3, max: Vec3) -> Option<Program> {
+        self.source.specialise(min, max)
+    }
+}
+
+impl<S: Compile, T> Compile for Painted<S, T> {
+    fn compile(&self, compiler: &mut Compiler, p: Point) -> Register {
+        self.source.compile(compiler, p)
+    }
+}
+
+impl<S, T: Clone> AttributeSource<T> for Painted<S, T> {
+    fn sample_attribute(&self, _: Vec3) -> T {
+        self.attribute.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extractor::IndexedVertices, feature::MinimiseQEF, implicit::Torus, sampler::Sampler,
+        DualContouring,
+    };
+
+    // Counts the samples taken from the underlying source
+    struct Counted<S> {
+        source: S,
+        samples: Cell<usize>,
+    }
+
+    impl<S: ScalarSource> ScalarSource for Counted<S> {
+        fn sample_scalar(&self, p: Vec3) -> Signed {
+            self.samples.set(self.samples.get() + 1);
+            self.source.sample_scalar(p)
+        }
+    }
+
+    fn extract<S: HermiteSource>(source: &S) -> (Vec<f32>, Vec<u32>) {
+        let (mut vertices, mut indices) = (vec![], vec![]);
+        DualContouring::new(24, MinimiseQEF {})
+            .unwrap()
+            .extract(
+                &Sampler::new(source),
+                &mut IndexedVertices::new(&mut vertices, &mut indices),
+            )
+            .unwrap();
+        (vertices, indices)
+    }
+
+    #[test]
+    fn test_cached_source() {
+        let uncached = CentralDifference::new(Counted {
+            source: Torus::new(0.25, 0.1),
+            samples: Cell::new(0),
+        });
+        let expected = extract(&uncached);
+
+        let cached = CachedSource::new(CentralDifference::new(Counted {
+            source: Torus::new(0.25, 0.1),
+            samples: Cell::new(0),
+        }));
+        assert_eq!(extract(&cached), expected);
+
+        // Normals at shared corners are only computed once
+        let statistics = cached.statistics();
+        let samples = cached.source.source.samples.get();
+        assert!(statistics.hits > 0);
+        assert!(uncached.source.samples.get() - samples >= statistics.hits);
+
+        // A tiny cache still gives the same result, but evicts constantly
+        let mut tiny = CachedSource::new_with_capacity(uncached, 4, 1e-6);
+        tiny.clear();
+        assert_eq!(extract(&tiny), expected);
+        assert!(tiny.statistics().evictions > 0);
+        assert!(tiny.statistics().hit_rate() < statistics.hit_rate());
+    }
+}
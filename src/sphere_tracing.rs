@@ -0,0 +1,316 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    distance::Signed,
+    math::Vec3,
+    source::{BatchScalarSource, HermiteSource, ScalarSource},
+};
+
+/// A ray, starting at `origin` and extending indefinitely along `direction`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// The direction of the ray. Should be normalised, as distances along the
+    /// ray are measured in multiples of this vector.
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// Create a ray from an origin and direction.
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self { origin, direction }
+    }
+
+    /// The point at distance `t` along the ray.
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+/// The point at which a ray hits the surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hit {
+    /// The point on the surface.
+    pub point: Vec3,
+    /// The distance along the ray to the point.
+    pub distance: f32,
+    /// The number of samples taken to reach the point.
+    pub steps: usize,
+}
+
+impl Hit {
+    /// The normalised surface normal at the hit point.
+    pub fn normal<S: HermiteSource>(&self, source: &S) -> Vec3 {
+        source
+            .sample_normal(self.point)
+            .normalised()
+            .unwrap_or_default()
+    }
+}
+
+/// Find the intersection of rays with a surface by sphere tracing, as
+/// described in [Sphere tracing: a geometric method for the antialiased ray tracing of implicit surfaces](https://doi.org/10.1007/s003710050084).
+///
+/// Sphere tracing relies on the distance field being Lipschitz continuous:
+/// the field may not change faster than the `lipschitz` constant times the
+/// distance moved. Euclidean signed distance fields have a constant of 1.
+/// Fields which over-estimate the distance to the surface (i.e. most
+/// non-Euclidean fields) need a larger constant, or rays may pass straight
+/// through thin parts of the surface.
+///
+/// Setting `relaxation` above 1 enables the over-relaxation technique from
+/// [Enhanced Sphere Tracing](https://doi.org/10.2312/stag.20141233), which
+/// takes larger steps while the ray is far from the surface, and falls back
+/// to regular steps whenever it might have overshot.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SphereTracer {
+    /// The maximum distance along each ray to search for the surface.
+    pub max_distance: f32,
+    /// The maximum number of steps taken along each ray.
+    pub max_steps: usize,
+    /// A ray has hit the surface once it comes within this distance.
+    pub epsilon: f32,
+    /// The Lipschitz constant of the distance field.
+    pub lipschitz: f32,
+    /// The over-relaxation factor, in the range [1, 2).
+    pub relaxation: f32,
+}
+
+impl SphereTracer {
+    /// Create a SphereTracer for Euclidean distance fields, which will search
+    /// up to `max_distance` along each ray.
+    pub fn new(max_distance: f32) -> Self {
+        Self {
+            max_distance,
+            max_steps: 256,
+            epsilon: 0.0001,
+            lipschitz: 1.0,
+            relaxation: 1.0,
+        }
+    }
+
+    /// Trace a single ray, returning the point where it first hits the
+    /// surface, if any.
+    pub fn trace<S: ScalarSource>(&self, source: &S, ray: Ray) -> Option<Hit> {
+        let mut state = TraceState::new(self);
+
+        loop {
+            let distance = source.sample_scalar(ray.at(state.t));
+            if let Some(hit) = state.advance(self, ray, distance) {
+                return hit;
+            }
+        }
+    }
+
+    /// Trace many rays at once. The result for each ray is written to the
+    /// corresponding entry in `hits`.
+    ///
+    /// All rays are advanced in lockstep, so that the distance field is
+    /// sampled for every active ray in a single batch.
+    pub fn trace_batch<S: BatchScalarSource>(
+        &self,
+        source: &S,
+        rays: &[Ray],
+        hits: &mut [Option<Hit>],
+    ) {
+        let mut states: Vec<TraceState> = rays.iter().map(|_| TraceState::new(self)).collect();
+        let mut active: Vec<usize> = (0..rays.len().min(hits.len())).collect();
+        let mut points = Vec::with_capacity(active.len());
+        let mut distances = Vec::with_capacity(active.len());
+
+        while !active.is_empty() {
+            points.clear();
+            points.extend(active.iter().map(|&i| rays[i].at(states[i].t)));
+            distances.clear();
+            distances.resize(active.len(), Signed(0.0));
+
+            source.sample_scalar_batch(&points, &mut distances);
+
+            let mut j = 0;
+            active.retain(|&i| {
+                let result = states[i].advance(self, rays[i], distances[j]);
+                j += 1;
+                match result {
+                    Some(hit) => {
+                        hits[i] = hit;
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+    }
+}
+
+impl Default for SphereTracer {
+    fn default() -> Self {
+        Self::new(f32::MAX)
+    }
+}
+
+// The progress of a single ray.
+struct TraceState {
+    // The distance travelled along the ray.
+    t: f32,
+    // The current over-relaxation factor.
+    omega: f32,
+    // The radius of the unbounding sphere around the previous point.
+    previous_radius: f32,
+    // The length of the previous step.
+    step: f32,
+    // The sign of the field at the ray origin, so that we can trace out of the
+    // surface as well as into it.
+    sign: Option<f32>,
+    steps: usize,
+}
+
+impl TraceState {
+    fn new(tracer: &SphereTracer) -> Self {
+        Self {
+            t: 0.0,
+            omega: tracer.relaxation,
+            previous_radius: 0.0,
+            step: 0.0,
+            sign: None,
+            steps: 0,
+        }
+    }
+
+    // Advance the ray given the distance sampled at the current point.
+    // Returns Some once the ray has terminated, with the hit if there is one.
+    fn advance(
+        &mut self,
+        tracer: &SphereTracer,
+        ray: Ray,
+        distance: Signed,
+    ) -> Option<Option<Hit>> {
+        self.steps += 1;
+
+        if !distance.0.is_finite() {
+            return Some(None);
+        }
+
+        let sign = *self
+            .sign
+            .get_or_insert(if distance.0 < 0.0 { -1.0 } else { 1.0 });
+        let radius = sign * distance.0 / tracer.lipschitz;
+
+        if self.omega > 1.0 && radius.abs() + self.previous_radius < self.step {
+            // The unbounding spheres around the last two points don't overlap,
+            // so the relaxed step may have skipped over the surface. Step
+            // back, and take a safe step instead.
+            self.t -= self.step - self.previous_radius;
+            self.step = self.previous_radius;
+            self.omega = 1.0;
+        } else if radius.abs() < tracer.epsilon {
+            return Some(Some(Hit {
+                point: ray.at(self.t),
+                distance: self.t,
+                steps: self.steps,
+            }));
+        } else {
+            self.step = radius * self.omega;
+            self.previous_radius = radius.abs();
+            self.t += self.step;
+        }
+
+        if self.t > tracer.max_distance || self.steps >= tracer.max_steps {
+            Some(None)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bytecode::Program,
+        implicit::{RectangularPrism, Sphere, Torus},
+    };
+
+    #[test]
+    fn test_sphere_tracing() {
+        let sphere = Sphere::new(1.0);
+        let tracer = SphereTracer::new(10.0);
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = tracer.trace(&sphere, ray).unwrap();
+        assert!((hit.distance - 4.0).abs() < 0.001);
+        assert!((hit.point - Vec3::new(0.0, 0.0, -1.0)).len() < 0.001);
+        assert_eq!(hit.normal(&sphere), Vec3::new(0.0, 0.0, -1.0));
+
+        // Starting inside the surface
+        let ray = Ray::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0));
+        let hit = tracer.trace(&sphere, ray).unwrap();
+        assert!((hit.distance - 1.0).abs() < 0.001);
+
+        let ray = Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(tracer.trace(&sphere, ray), None);
+
+        // A Lipschitz constant of 2 halves the step size
+        let cautious = SphereTracer {
+            lipschitz: 2.0,
+            ..tracer
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = cautious.trace(&sphere, ray).unwrap();
+        assert!((hit.distance - 4.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_over_relaxation() {
+        let prism = RectangularPrism::new(Vec3::new(5.0, 5.0, 0.5));
+        let tracer = SphereTracer::new(10.0);
+        let relaxed = SphereTracer {
+            relaxation: 1.6,
+            ..tracer
+        };
+
+        // A grazing ray, which regular sphere tracing takes many steps over
+        let direction = Vec3::new(1.0, 0.0, -0.05).normalised().unwrap();
+        let ray = Ray::new(Vec3::new(-4.5, 0.0, 0.7), direction);
+
+        let hit = tracer.trace(&prism, ray).unwrap();
+        let relaxed_hit = relaxed.trace(&prism, ray).unwrap();
+
+        assert!((hit.distance - relaxed_hit.distance).abs() < 0.001);
+        assert!(relaxed_hit.steps < hit.steps);
+    }
+
+    #[test]
+    fn test_batch_matches_single_rays() {
+        let program = Program::compile(&Torus::new(1.0, 0.25));
+        let tracer = SphereTracer {
+            relaxation: 1.2,
+            ..SphereTracer::new(10.0)
+        };
+
+        let rays: Vec<Ray> = (0..32)
+            .map(|i| {
+                let origin = Vec3::new(-3.0 + i as f32 * 0.2, 0.0, -5.0);
+                Ray::new(origin, Vec3::new(0.0, 0.0, 1.0))
+            })
+            .collect();
+        let mut hits = vec![None; rays.len()];
+        tracer.trace_batch(&program, &rays, &mut hits);
+
+        for (&ray, hit) in rays.iter().zip(hits.iter()) {
+            assert_eq!(tracer.trace(&program, ray), *hit);
+        }
+        assert!(hits.iter().any(|h| h.is_some()));
+        assert!(hits.iter().any(|h| h.is_none()));
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    buffer::IndexType,
+    export::ExportAttribute,
+    extractor::{Vertex, VertexExtractor},
+    math::{Vec2, Vec3},
+    normals::face_normal,
+    source::Attribute,
+};
+
+/// Everything needed to texture a vertex, either by triplanar mapping or with
+/// a single box-projected texture, and to apply normal maps to it.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct TextureFrame {
+    /// Weights for blending textures projected along the x, y and z axes,
+    /// which sum to 1.
+    pub blend_weights: Vec3,
+    /// Texture coordinates, projected along the axis the normal is closest to.
+    pub uv: Vec2,
+    /// The unit tangent, perpendicular to the normal, in the direction of
+    /// increasing u.
+    pub tangent: Vec3,
+    /// Either 1 or -1. The bitangent (in the direction of increasing v) is
+    /// `normal.cross(tangent) * bitangent_sign`, as in MikkTSpace.
+    pub bitangent_sign: f32,
+}
+
+impl Attribute for TextureFrame {
+    fn lerp(&self, other: &Self, factor: f32) -> Self {
+        Self {
+            blend_weights: self.blend_weights.lerp(other.blend_weights, factor),
+            uv: Attribute::lerp(&self.uv, &other.uv, factor),
+            tangent: self
+                .tangent
+                .lerp(other.tangent, factor)
+                .normalised()
+                .unwrap_or(self.tangent),
+            bitangent_sign: Attribute::lerp(&self.bitangent_sign, &other.bitangent_sign, factor)
+                .signum(),
+        }
+    }
+}
+
+impl ExportAttribute for TextureFrame {
+    const COMPONENTS: usize = 9;
+
+    fn write_components(&self, components: &mut Vec<f32>) {
+        self.blend_weights.write_components(components);
+        self.uv.write_components(components);
+        self.tangent.write_components(components);
+        components.push(self.bitangent_sign);
+    }
+}
+
+/// Generates texture coordinates, triplanar blend weights and tangents from
+/// the positions and normals of a mesh.
+///
+/// Texture coordinates are box-projected: each vertex is projected onto the
+/// plane perpendicular to whichever axis its normal is closest to, oriented so
+/// that every projection is seen from outside the surface. Tangents are then
+/// computed per face from those coordinates, and accumulated per vertex
+/// weighted by corner angle, following [MikkTSpace](http://www.mikktspace.com/).
+///
+/// Faces whose vertices are projected along different axes have stretched
+/// texture coordinates, but their tangents still follow the coordinates that
+/// are interpolated across them, so normal maps stay consistent with the
+/// texture.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Texturing {
+    /// The number of texture repeats per unit of distance.
+    pub scale: f32,
+    /// The power to which each component of the normal is raised when
+    /// computing blend weights. Larger values give sharper transitions between
+    /// projections.
+    pub blend_sharpness: f32,
+}
+
+impl Texturing {
+    /// Create a Texturing which maps each unit of distance to `scale` texture
+    /// repeats, with a blend sharpness of 4.
+    pub fn new(scale: f32) -> Self {
+        Self {
+            scale,
+            blend_sharpness: 4.0,
+        }
+    }
+
+    /// The weights for blending triplanar projections at a point with the
+    /// given normal.
+    pub fn blend_weights(&self, normal: Vec3) -> Vec3 {
+        let n = normal.abs();
+        let w = Vec3::new(
+            n.x.powf(self.blend_sharpness),
+            n.y.powf(self.blend_sharpness),
+            n.z.powf(self.blend_sharpness),
+        );
+        let sum = w.x + w.y + w.z;
+        if sum > 0.0 {
+            w / sum
+        } else {
+            Vec3::from_scalar(1.0 / 3.0)
+        }
+    }
+
+    /// The box-projected texture coordinates of a point with the given normal.
+    pub fn project(&self, position: Vec3, normal: Vec3) -> Vec2 {
+        let (u, v) = projection_axes(normal);
+        Vec2::new(position.dot(u), position.dot(v)) * self.scale
+    }
+
+    /// Generate a frame for each vertex of an indexed triangle mesh. Normals
+    /// must be given for every vertex, and needn't be normalised.
+    pub fn generate<I: IndexType>(
+        &self,
+        positions: &[Vec3],
+        normals: &[Vec3],
+        indices: &[I],
+    ) -> Vec<TextureFrame> {
+        let mut tangents = vec![Vec3::zero(); positions.len()];
+        let mut bitangents = vec![Vec3::zero(); positions.len()];
+
+        for face in indices.chunks_exact(3) {
+            let v = [face[0].to_index(), face[1].to_index(), face[2].to_index()];
+            let p = v.map(|i| positions[i]);
+            let uv = v.map(|i| self.project(positions[i], normals[i]));
+
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+            let det = d1.x * d2.y - d2.x * d1.y;
+            if det.abs() < f32::EPSILON {
+                continue;
+            }
+            let tangent = (e1 * d2.y - e2 * d1.y) / det;
+            let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+
+            for corner in 0..3 {
+                let a = (p[(corner + 1) % 3] - p[corner]).normalised();
+                let b = (p[(corner + 2) % 3] - p[corner]).normalised();
+                if let (Some(a), Some(b)) = (a, b) {
+                    let angle = a.dot(b).clamp(-1.0, 1.0).acos();
+                    tangents[v[corner]] += tangent * angle;
+                    bitangents[v[corner]] += bitangent * angle;
+                }
+            }
+        }
+
+        positions
+            .iter()
+            .zip(normals)
+            .enumerate()
+            .map(|(i, (&p, &n))| {
+                let n = n.normalised().unwrap_or_default();
+                // Vertices without any usable faces fall back to the axis
+                // their texture coordinates are projected along
+                let tangent = orthogonalise(tangents[i], n)
+                    .or_else(|| orthogonalise(projection_axes(n).0, n))
+                    .unwrap_or_default();
+                let bitangent_sign = if n.cross(tangent).dot(bitangents[i]) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                TextureFrame {
+                    blend_weights: self.blend_weights(n),
+                    uv: self.project(p, n),
+                    tangent,
+                    bitangent_sign,
+                }
+            })
+            .collect()
+    }
+}
+
+// The directions of increasing u and v when projecting along the axis closest
+// to the normal, chosen so that u, v and the normal form a right-handed frame
+fn projection_axes(normal: Vec3) -> (Vec3, Vec3) {
+    let a = normal.abs();
+    let (x, y, z) = (
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    );
+    if a.x >= a.y && a.x >= a.z {
+        (z * -normal.x.signum(), y)
+    } else if a.y >= a.z {
+        (x, z * -normal.y.signum())
+    } else {
+        (x * normal.z.signum(), y)
+    }
+}
+
+fn orthogonalise(tangent: Vec3, normal: Vec3) -> Option<Vec3> {
+    (tangent - normal * normal.dot(tangent)).normalised()
+}
+
+/// Generate a [TextureFrame] for each vertex, and pass the result to another
+/// extractor as vertex attributes.
+///
+/// Vertices which the extraction algorithm didn't provide a normal for are
+/// given the area-weighted average of the normals of their faces. Tangents
+/// depend on every face around a vertex, so the mesh is held until extraction
+/// is complete, and only passed on by [finish](WithTexturing::finish).
+pub struct WithTexturing<'a, E: VertexExtractor<TextureFrame>> {
+    texturing: Texturing,
+    extractor: &'a mut E,
+    vertices: Vec<Vertex>,
+    indices: Vec<usize>,
+}
+
+impl<'a, E: VertexExtractor<TextureFrame>> WithTexturing<'a, E> {
+    pub fn new(texturing: Texturing, extractor: &'a mut E) -> Self {
+        Self {
+            texturing,
+            extractor,
+            vertices: vec![],
+            indices: vec![],
+        }
+    }
+
+    /// Generate texture frames, and output the mesh.
+    pub fn finish(self) {
+        let positions = self.vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+
+        let mut face_normals = vec![Vec3::zero(); positions.len()];
+        for face in self.indices.chunks_exact(3) {
+            let normal = face_normal(face.iter().map(|&i| positions[i]));
+            for &i in face {
+                face_normals[i] += normal;
+            }
+        }
+        let normals = self
+            .vertices
+            .iter()
+            .zip(face_normals)
+            .map(|(v, n)| v.normal.or_else(|| n.normalised()).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        let frames = self.texturing.generate(&positions, &normals, &self.indices);
+        for ((vertex, normal), frame) in self.vertices.into_iter().zip(normals).zip(frames) {
+            self.extractor.extract_vertex(Vertex {
+                position: vertex.position,
+                normal: normal.normalised(),
+                origin: vertex.origin,
+                feature: vertex.feature,
+                attributes: frame,
+            });
+        }
+        for index in self.indices {
+            self.extractor.extract_index(index);
+        }
+    }
+}
+
+impl<'a, E: VertexExtractor<TextureFrame>> VertexExtractor for WithTexturing<'a, E> {
+    fn extract_vertex(&mut self, vertex: Vertex) {
+        self.vertices.push(vertex);
+    }
+
+    fn extract_index(&mut self, index: usize) {
+        self.indices.push(index);
+    }
+
+    fn max_index(&self) -> usize {
+        self.extractor.max_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Signed,
+        fixtures::ball,
+        normals::{NormalMode, NormalWeighting, WithGeneratedNormals},
+        sampler::Sampler,
+        MarchingCubes,
+    };
+
+    struct Records(Vec<Vertex<TextureFrame>>);
+
+    impl VertexExtractor<TextureFrame> for Records {
+        fn extract_vertex(&mut self, vertex: Vertex<TextureFrame>) {
+            self.0.push(vertex);
+        }
+
+        fn extract_index(&mut self, _: usize) {}
+    }
+
+    #[test]
+    fn test_projection() {
+        // A quad in the x-z plane, seen from above and below
+        let positions = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let indices = [0u16, 1, 2, 0, 2, 3];
+        let texturing = Texturing::new(2.0);
+
+        for &up in &[1.0, -1.0] {
+            let normals = [Vec3::new(0.0, up, 0.0); 4];
+            let frames = texturing.generate(&positions, &normals, &indices);
+            for ((frame, p), n) in frames.iter().zip(&positions).zip(&normals) {
+                assert_eq!(frame.blend_weights, Vec3::new(0.0, 1.0, 0.0));
+                assert_eq!(frame.uv, Vec2::new(p.x, -p.z * up) * 2.0);
+                assert_eq!(frame.tangent, Vec3::new(1.0, 0.0, 0.0));
+                assert_eq!(frame.bitangent_sign, 1.0);
+
+                // The bitangent points along increasing v
+                let bitangent = n.cross(frame.tangent) * frame.bitangent_sign;
+                assert_eq!(bitangent, Vec3::new(0.0, 0.0, -up));
+            }
+        }
+    }
+
+    #[test]
+    fn test_projection_seam() {
+        // A single face, whose last vertex is projected along a different
+        // axis to the others
+        let positions = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.5),
+        ];
+        let normals = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.1, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.9),
+        ];
+        let frames = Texturing::new(1.0).generate(&positions, &normals, &[0u16, 1, 2]);
+        assert_ne!(
+            projection_axes(normals[0]),
+            projection_axes(normals[2]),
+            "the face should cross a projection seam"
+        );
+
+        // The tangents follow the texture coordinates which are emitted
+        let (e1, e2) = (positions[1] - positions[0], positions[2] - positions[0]);
+        let (d1, d2) = (frames[1].uv - frames[0].uv, frames[2].uv - frames[0].uv);
+        let det = d1.x * d2.y - d2.x * d1.y;
+        let tangent = (e1 * d2.y - e2 * d1.y) / det;
+        let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+
+        for (frame, n) in frames.iter().zip(&normals) {
+            let n = n.normalised().unwrap();
+            let expected = orthogonalise(tangent, n).unwrap();
+            assert!((frame.tangent - expected).len() < 1e-5);
+            let sign = n.cross(frame.tangent).dot(bitangent).signum();
+            assert_eq!(frame.bitangent_sign, sign);
+        }
+    }
+
+    #[test]
+    fn test_extractor() {
+        let mut records = Records(vec![]);
+        let mut texturing = WithTexturing::new(Texturing::new(1.0), &mut records);
+        let mut normals =
+            WithGeneratedNormals::new(NormalMode::Smooth(NormalWeighting::Angle), &mut texturing);
+        MarchingCubes::<Signed>::new(16)
+            .unwrap()
+            .extract(&Sampler::new(&ball()), &mut normals)
+            .unwrap();
+        normals.finish().unwrap();
+        texturing.finish();
+
+        assert!(!records.0.is_empty());
+        for vertex in &records.0 {
+            let frame = vertex.attributes;
+            let normal = vertex.normal.unwrap();
+            let weights = frame.blend_weights;
+            assert!((weights.x + weights.y + weights.z - 1.0).abs() < 1e-5);
+            assert!((frame.tangent.len() - 1.0).abs() < 1e-5);
+            assert!(frame.tangent.dot(normal).abs() < 1e-5);
+            assert_eq!(frame.bitangent_sign.abs(), 1.0);
+        }
+    }
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
-    distance::Distance, marching_cubes_tables::CORNERS, math::Vec3, sampler::Sample,
+    distance::Distance, error::Error, marching_cubes_tables::CORNERS, math::Vec3, sampler::Sample,
     traversal::PrimalGrid,
 };
 
@@ -29,17 +29,24 @@ pub struct DualGrid<D: Distance> {
 impl<D: Distance> DualGrid<D> {
     /// Create a dual grid that spans a primal grid with dimensions NxNxN.
     /// The dual grid will have dimension (N-1) along each axis.
-    pub fn new(size: usize) -> Self {
+    pub fn new(size: usize) -> Result<Self, Error> {
+        let primal_grid = PrimalGrid::new(size)?;
         let size_minus_one = size - 1;
 
-        Self {
+        Ok(Self {
             size,
-            primal_grid: PrimalGrid::new(size),
+            primal_grid,
             duals: [
                 vec![(Vec3::zero(), D::zero()); size_minus_one * size_minus_one],
                 vec![(Vec3::zero(), D::zero()); size_minus_one * size_minus_one],
             ],
-        }
+        })
+    }
+
+    /// The number of dual grid points along each axis (one fewer than the
+    /// primal grid).
+    pub fn size(&self) -> usize {
+        self.size - 1
     }
 
     /// Traverse the dual grid, sampling from the provided Sampler at each point
@@ -54,7 +61,8 @@ impl<D: Distance> DualGrid<D> {
         source: &S,
         mut vertex_callback: Option<V>,
         mut cube_callback: C,
-    ) where
+    ) -> Result<(), Error>
+    where
         S: Sample<D>,
         V: FnMut(&[Vec3; 8], &[D; 8]) -> Option<Vec3>,
         C: FnMut(&[(usize, usize, usize); 8], &[Vec3; 8], &[D; 8]),
@@ -91,6 +99,6 @@ impl<D: Distance> DualGrid<D> {
                 }
                 cube_callback(&keys, &corners, &values);
             }
-        });
+        })
     }
 }
@@ -12,10 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::{
-    distance::Distance, linear_hashed_octree::LinearHashedOctree,
-    marching_cubes_tables::REMAP_CUBE, math::Vec3, morton::Morton, sampler::Sample,
+    bytecode::Program,
+    distance::{Distance, Signed},
+    error::Error,
+    linear_hashed_octree::LinearHashedOctree,
+    marching_cubes_tables::REMAP_CUBE,
+    math::Vec3,
+    morton::{Morton, MAX_DEPTH},
+    sampler::{sample_finite, Sample, Sampler},
+    source::IntervalSource,
 };
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 /// Traverses over the leaves in a sparse octree that uses morton coordinates to
 /// represent nodes in the tree.
@@ -23,11 +30,35 @@ pub struct ImplicitOctree {
     max_depth: usize,
 }
 
+/// Memory used while traversing an octree. Extractors keep this in their
+/// [Workspace](crate::Workspace), so that it can be reused between traversals.
+pub(crate) struct OctreeScratch<D> {
+    octree: LinearHashedOctree<D>,
+    primal_vertices: HashMap<Morton, usize>,
+}
+
+impl<D> OctreeScratch<D> {
+    pub fn new() -> Self {
+        Self {
+            octree: LinearHashedOctree::new(),
+            primal_vertices: HashMap::new(),
+        }
+    }
+}
+
 impl ImplicitOctree {
     /// Create a implicit octree with depth N, which is equivalent to a cubic
-    /// grid with dimensions 2^N along each axis.
-    pub fn new(max_depth: usize) -> Self {
-        Self { max_depth }
+    /// grid with dimensions 2^N along each axis. The depth is limited by the
+    /// precision of the underlying Morton codes.
+    pub fn new(max_depth: usize) -> Result<Self, Error> {
+        if max_depth > MAX_DEPTH {
+            return Err(Error::ExcessiveDepth {
+                depth: max_depth,
+                maximum: MAX_DEPTH,
+            });
+        }
+
+        Ok(Self { max_depth })
     }
 
     /// Build an implicit octree by sampling from the provided Sampler to find
@@ -36,28 +67,61 @@ impl ImplicitOctree {
     /// each 2x2x2 cube of neighbouring leaf vertices. The callback will be
     /// provided the Morton coordinates for each vertex, the vertices
     /// themselves, and the field values at those vertices.
-    pub fn traverse<D, S, C>(&mut self, source: &S, mut callback: C)
+    pub fn traverse<D, S, C>(&mut self, source: &S, callback: C) -> Result<(), Error>
     where
         D: Distance,
         S: Sample<D>,
         C: FnMut(&[Morton; 8], &[Vec3; 8], &[D; 8]),
     {
-        let mut octree = LinearHashedOctree::new();
+        self.traverse_in(&mut OctreeScratch::new(), source, callback)
+    }
 
-        octree.build(
+    pub(crate) fn traverse_in<D, S, C>(
+        &mut self,
+        scratch: &mut OctreeScratch<D>,
+        source: &S,
+        callback: C,
+    ) -> Result<(), Error>
+    where
+        D: Distance,
+        S: Sample<D>,
+        C: FnMut(&[Morton; 8], &[Vec3; 8], &[D; 8]),
+    {
+        let max_depth = self.max_depth;
+
+        Self::traverse_with_refinement(
+            scratch,
+            |key: Morton| sample_finite(source, key.center()),
             |key: Morton, distance: &D| {
                 let level = key.level();
                 let size = key.size();
                 // TODO: figure out how to construct an octree over a directed distance field
-                level < 2 || (level < self.max_depth && distance.within_extent(size))
-            },
-            |key: Morton| {
-                let p = key.center();
-                source.sample(p)
+                level < 2 || (level < max_depth && distance.within_extent(size))
             },
-        );
+            callback,
+        )
+    }
 
-        let mut primal_vertices = HashMap::new();
+    fn traverse_with_refinement<D, N, R, C>(
+        scratch: &mut OctreeScratch<D>,
+        construct_node: N,
+        should_refine: R,
+        mut callback: C,
+    ) -> Result<(), Error>
+    where
+        D: Distance,
+        N: FnMut(Morton) -> Result<D, Error>,
+        R: FnMut(Morton, &D) -> bool,
+        C: FnMut(&[Morton; 8], &[Vec3; 8], &[D; 8]),
+    {
+        let OctreeScratch {
+            octree,
+            primal_vertices,
+        } = scratch;
+
+        octree.build(should_refine, construct_node)?;
+
+        primal_vertices.clear();
 
         octree.walk_leaves(|key: Morton| {
             let level = key.level();
@@ -80,7 +144,7 @@ impl ImplicitOctree {
         let mut corners = [Vec3::zero(); 8];
         let mut values = [D::zero(); 8];
 
-        for (key, level) in primal_vertices {
+        for (key, level) in primal_vertices.drain() {
             for i in 0..8 {
                 let mut m = key.dual_vertex(level, REMAP_CUBE[i]);
                 while m > Morton::new() {
@@ -96,5 +160,127 @@ impl ImplicitOctree {
 
             callback(&keys, &corners, &values);
         }
+
+        Ok(())
+    }
+
+    /// Build an implicit octree, using the provided [IntervalSource] to decide
+    /// which nodes to refine, and then traverse it as per
+    /// [traverse](ImplicitOctree::traverse).
+    ///
+    /// Rather than estimating whether the surface passes through a node from
+    /// the distance at its center, nodes are refined whenever the bounds on
+    /// the distance field over the whole node include zero. Thin features
+    /// which fall between sample points are therefore never missed, and
+    /// nodes which provably contain no surface are never refined.
+    ///
+    /// Each refined node specialises the source to its bounds via
+    /// [IntervalSource::specialise], where the source supports it, and its
+    /// descendants are sampled and bounded using the specialised source.
+    pub fn traverse_bounded<S, C>(&mut self, source: &S, callback: C) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        C: FnMut(&[Morton; 8], &[Vec3; 8], &[Signed; 8]),
+    {
+        self.traverse_bounded_in(&mut OctreeScratch::new(), source, callback)
+    }
+
+    pub(crate) fn traverse_bounded_in<S, C>(
+        &mut self,
+        scratch: &mut OctreeScratch<Signed>,
+        source: &S,
+        callback: C,
+    ) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        C: FnMut(&[Morton; 8], &[Vec3; 8], &[Signed; 8]),
+    {
+        let max_depth = self.max_depth;
+
+        // The specialised source for the descendants of each refined node, if
+        // any. Programs are shared between a node and its descendants until a
+        // descendant manages to specialise further.
+        let specialised = RefCell::new(HashMap::<Morton, Rc<Program>>::new());
+        let inherited = |key: Morton| {
+            if key.level() > 0 {
+                specialised.borrow().get(&key.parent()).cloned()
+            } else {
+                None
+            }
+        };
+
+        Self::traverse_with_refinement(
+            scratch,
+            |key: Morton| match inherited(key) {
+                Some(program) => sample_finite(&Sampler::new(&*program), key.center()),
+                None => sample_finite(source, key.center()),
+            },
+            |key: Morton, _: &Signed| {
+                let level = key.level();
+                let half_size = Vec3::from_scalar(key.size());
+                let center = key.center();
+                let (min, max) = (center - half_size, center + half_size);
+
+                let program = inherited(key);
+                let bounds = match &program {
+                    Some(program) => program.sample_interval(min, max),
+                    None => source.sample_interval(min, max),
+                };
+
+                let refine = level < 2 || (level < max_depth && bounds.contains(0.0));
+                if refine {
+                    let program = match &program {
+                        Some(program) => program.specialise(min, max),
+                        None => source.specialise(min, max),
+                    }
+                    .map(Rc::new)
+                    .or(program);
+
+                    if let Some(program) = program {
+                        specialised.borrow_mut().insert(key, program);
+                    }
+                }
+                refine
+            },
+            callback,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{two_spheres, Counted},
+        marching_cubes_impl::classify_corners,
+    };
+
+    fn crossings(octree: &mut ImplicitOctree, source: &Counted) -> Vec<[f32; 8]> {
+        let mut found = vec![];
+        octree
+            .traverse_bounded(&Sampler::new(source), |_, _, values| {
+                let cube_index = classify_corners(values);
+                if cube_index != 0 && cube_index != 255 {
+                    found.push(values.map(|v| v.0));
+                }
+            })
+            .unwrap();
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        found
+    }
+
+    #[test]
+    fn test_bounded_traversal_specialises_source() {
+        let mut octree = ImplicitOctree::new(5).unwrap();
+
+        let plain = Counted::new(two_spheres(), false);
+        let expected = crossings(&mut octree, &plain);
+
+        let specialised = Counted::new(two_spheres(), true);
+        let found = crossings(&mut octree, &specialised);
+
+        assert!(!expected.is_empty());
+        assert_eq!(expected, found);
+        assert!(specialised.samples.get() < plain.samples.get() / 2);
     }
 }
@@ -11,32 +11,70 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::{distance::Distance, marching_cubes_tables::CORNERS, math::Vec3, sampler::Sample};
+use crate::{
+    bytecode::Program,
+    distance::{Distance, Signed},
+    error::Error,
+    marching_cubes_tables::CORNERS,
+    math::Vec3,
+    sampler::{sample_finite, Sample, Sampler},
+    source::IntervalSource,
+};
+
+// The number of cells along each axis of the blocks that bounded traversal
+// tests for emptiness.
+const BLOCK_SIZE: usize = 8;
 
 /// Traverses over cubes in a primal grid (i.e. cubes formed by adjacent sample
 /// points).
 pub struct PrimalGrid<D: Distance> {
     size: usize,
     layers: [Vec<(Vec3, D)>; 2],
+    // The z coordinate each entry in the layers was last sampled at, used to
+    // sample lazily during bounded traversal.
+    stamps: [Vec<usize>; 2],
+    // Whether each block in the current slab may contain the surface, during
+    // bounded traversal.
+    active: Vec<bool>,
+    // The source specialised to each block in the current slab, if the source
+    // supports specialisation.
+    specialised: Vec<Option<Program>>,
 }
 
 impl<D: Distance> PrimalGrid<D> {
-    /// Create a cubic grid with dimensions N*N*N
-    pub fn new(size: usize) -> Self {
-        Self {
+    /// Create a cubic grid with dimensions N*N*N. The grid must be at least
+    /// 2 points wide, to contain a single cell.
+    pub fn new(size: usize) -> Result<Self, Error> {
+        if size < 2 {
+            return Err(Error::InvalidSize { size, minimum: 2 });
+        }
+        let blocks = (size - 1).div_ceil(BLOCK_SIZE);
+
+        Ok(Self {
             size,
             layers: [
                 vec![(Vec3::zero(), D::zero()); size * size],
                 vec![(Vec3::zero(), D::zero()); size * size],
             ],
-        }
+            stamps: [vec![usize::MAX; size * size], vec![usize::MAX; size * size]],
+            active: vec![false; blocks * blocks],
+            specialised: (0..blocks * blocks).map(|_| None).collect(),
+        })
+    }
+
+    /// The number of grid points along each axis.
+    pub fn size(&self) -> usize {
+        self.size
     }
 
     /// Traverse the primal grid, sampling from the provided Sampler at each
     /// grid point. The callback will be invoked for each 2x2x2 set of
     /// neighbouring grid points, and provided the corner grid references,
     /// corner points, and the field values at those points.
-    pub fn traverse<S, C>(&mut self, source: &S, mut callback: C)
+    ///
+    /// Traversal stops with an error as soon as the source produces a
+    /// non-finite distance.
+    pub fn traverse<S, C>(&mut self, source: &S, mut callback: C) -> Result<(), Error>
     where
         S: Sample<D>,
         C: FnMut(&[(usize, usize, usize); 8], &[Vec3; 8], &[D; 8]),
@@ -48,7 +86,7 @@ impl<D: Distance> PrimalGrid<D> {
         for y in 0usize..self.size {
             for x in 0..self.size {
                 let corner = Vec3::new(x as f32 * one_over_size, y as f32 * one_over_size, 0.0);
-                self.layers[0][y * self.size + x] = (corner, source.sample(corner));
+                self.layers[0][y * self.size + x] = (corner, sample_finite(source, corner)?);
             }
         }
 
@@ -65,7 +103,7 @@ impl<D: Distance> PrimalGrid<D> {
                         y as f32 * one_over_size,
                         (z + 1) as f32 * one_over_size,
                     );
-                    self.layers[1][y * self.size + x] = (corner, source.sample(corner));
+                    self.layers[1][y * self.size + x] = (corner, sample_finite(source, corner)?);
                 }
             }
 
@@ -86,5 +124,216 @@ impl<D: Distance> PrimalGrid<D> {
 
             self.layers.swap(0, 1);
         }
+
+        Ok(())
+    }
+}
+
+impl PrimalGrid<Signed> {
+    /// Traverse the primal grid, skipping any region that provably doesn't
+    /// contain the surface.
+    ///
+    /// The grid is divided into blocks, and the provided [IntervalSource] is
+    /// used to bound the distance field over each block. Blocks which lie
+    /// entirely inside or entirely outside the surface are neither sampled
+    /// nor passed to the callback. Since the bounds are conservative, no cell
+    /// containing a surface crossing will be skipped. The remaining blocks are
+    /// sampled from the source as specialised to each block by
+    /// [IntervalSource::specialise], where the source supports it.
+    ///
+    /// The callback will be invoked for each 2x2x2 set of neighbouring grid
+    /// points in the remaining blocks, and provided the corner grid
+    /// references, corner points, and the field values at those points.
+    pub fn traverse_bounded<S, C>(&mut self, source: &S, mut callback: C) -> Result<(), Error>
+    where
+        S: Sample<Signed> + IntervalSource,
+        C: FnMut(&[(usize, usize, usize); 8], &[Vec3; 8], &[Signed; 8]),
+    {
+        let Self {
+            size,
+            layers,
+            stamps,
+            active,
+            specialised,
+        } = self;
+        let size = *size;
+        let size_minus_one = size - 1;
+        let one_over_size = 1.0 / (size_minus_one as f32);
+        let blocks = size_minus_one.div_ceil(BLOCK_SIZE);
+
+        for stamps in stamps.iter_mut() {
+            stamps.iter_mut().for_each(|s| *s = usize::MAX);
+        }
+
+        let mut keys = [(0, 0, 0); 8];
+        let mut corners = [Vec3::zero(); 8];
+        let mut values = [Signed(0.0); 8];
+
+        for z in 0..size_minus_one {
+            let bz = z / BLOCK_SIZE;
+
+            // Classify the blocks in this slab as we enter it
+            if z % BLOCK_SIZE == 0 {
+                for by in 0..blocks {
+                    for bx in 0..blocks {
+                        let block = by * blocks + bx;
+                        let lower = Vec3::new(bx as f32, by as f32, bz as f32) * BLOCK_SIZE as f32;
+                        let upper = (lower + Vec3::from_scalar(BLOCK_SIZE as f32))
+                            .min(Vec3::from_scalar(size_minus_one as f32));
+                        let (lower, upper) = (lower * one_over_size, upper * one_over_size);
+                        let bounds = source.sample_interval(lower, upper);
+
+                        // Cells with every corner positive or every corner
+                        // non-positive can't contain a surface crossing
+                        active[block] = !(bounds.min > 0.0 || bounds.max <= 0.0);
+                        specialised[block] = if active[block] {
+                            source.specialise(lower, upper)
+                        } else {
+                            None
+                        };
+                    }
+                }
+            }
+
+            for y in 0..size_minus_one {
+                for x in 0..size_minus_one {
+                    let block = (y / BLOCK_SIZE) * blocks + x / BLOCK_SIZE;
+                    if !active[block] {
+                        continue;
+                    }
+
+                    for i in 0..8 {
+                        let key = (x + CORNERS[i][0], y + CORNERS[i][1], z + CORNERS[i][2]);
+                        let (corner, value) = match &specialised[block] {
+                            Some(program) => Self::sample_lazily(
+                                layers,
+                                stamps,
+                                size,
+                                &Sampler::new(program),
+                                key,
+                                one_over_size,
+                            )?,
+                            None => Self::sample_lazily(
+                                layers,
+                                stamps,
+                                size,
+                                source,
+                                key,
+                                one_over_size,
+                            )?,
+                        };
+                        keys[i] = key;
+                        corners[i] = corner;
+                        values[i] = value;
+                    }
+
+                    callback(&keys, &corners, &values);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sample_lazily<S>(
+        layers: &mut [Vec<(Vec3, Signed)>; 2],
+        stamps: &mut [Vec<usize>; 2],
+        size: usize,
+        source: &S,
+        (x, y, z): (usize, usize, usize),
+        one_over_size: f32,
+    ) -> Result<(Vec3, Signed), Error>
+    where
+        S: Sample<Signed>,
+    {
+        let index = y * size + x;
+        let layer = z % 2;
+
+        if stamps[layer][index] != z {
+            let corner = Vec3::new(
+                x as f32 * one_over_size,
+                y as f32 * one_over_size,
+                z as f32 * one_over_size,
+            );
+            layers[layer][index] = (corner, sample_finite(source, corner)?);
+            stamps[layer][index] = z;
+        }
+
+        Ok(layers[layer][index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{two_spheres, Centered, Counted},
+        implicit::{Sphere, Torus, Union},
+        marching_cubes_impl::classify_corners,
+    };
+
+    #[test]
+    fn test_bounded_traversal_finds_every_crossing() {
+        // A large torus, and a sphere too small to be seen by a single
+        // sample in the center of a block
+        let source = Centered(Union::new(Torus::new(0.25, 0.1), Sphere::new(0.01)));
+        let sampler = Sampler::new(&source);
+
+        let mut grid = PrimalGrid::new(33).unwrap();
+
+        // Full traversal also visits a slab of cells past z = 1, which bounded
+        // traversal doesn't, so only compare cells within the unit cube
+        let mut expected = vec![];
+        grid.traverse(&sampler, |keys, _, values| {
+            let cube_index = classify_corners(values);
+            if cube_index != 0 && cube_index != 255 && keys[0].2 < 32 {
+                expected.push(keys[0]);
+            }
+        })
+        .unwrap();
+
+        let mut visited = 0;
+        let mut found = vec![];
+        grid.traverse_bounded(&sampler, |keys, _, values| {
+            visited += 1;
+            let cube_index = classify_corners(values);
+            if cube_index != 0 && cube_index != 255 {
+                found.push(keys[0]);
+            }
+        })
+        .unwrap();
+
+        expected.sort();
+        found.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(expected, found);
+        assert!(visited < 32 * 32 * 32);
+    }
+
+    fn crossings(grid: &mut PrimalGrid<Signed>, source: &Counted) -> Vec<[f32; 8]> {
+        let mut found = vec![];
+        grid.traverse_bounded(&Sampler::new(source), |_, _, values| {
+            let cube_index = classify_corners(values);
+            if cube_index != 0 && cube_index != 255 {
+                found.push(values.map(|v| v.0));
+            }
+        })
+        .unwrap();
+        found
+    }
+
+    #[test]
+    fn test_bounded_traversal_specialises_source() {
+        let mut grid = PrimalGrid::new(33).unwrap();
+
+        let plain = Counted::new(two_spheres(), false);
+        let expected = crossings(&mut grid, &plain);
+
+        let specialised = Counted::new(two_spheres(), true);
+        let found = crossings(&mut grid, &specialised);
+
+        assert!(!expected.is_empty());
+        assert_eq!(expected, found);
+        assert!(specialised.samples.get() < plain.samples.get() / 2);
     }
 }
@@ -0,0 +1,173 @@
+// Copyright 2021 Tristam MacDonald
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::{
+    distance::Signed,
+    index_cache::{EdgeCache, IndexCache, MortonKey},
+    mesh::{Edge, MeshTopology, VertexHandle},
+    traversal::OctreeScratch,
+};
+use std::collections::HashSet;
+
+/// Scratch memory for extraction, which can be reused between extractions.
+///
+/// Each extractor's `extract_with` methods accept a Workspace, and clear it
+/// rather than dropping it at the start of extraction. So once the first
+/// extraction has grown the workspace to size, re-extracting a similar
+/// surface (i.e. remeshing a modified chunk of terrain) doesn't allocate.
+///
+/// A single Workspace may be shared between any number of extractors, of any
+/// algorithm or size, so long as they aren't extracting at the same time.
+/// Each algorithm only grows the parts of the workspace it uses.
+pub struct Workspace {
+    pub(crate) edges: EdgeCache<VertexHandle>,
+    pub(crate) streaming_edges: EdgeCache<usize>,
+    pub(crate) morton_indices: IndexCache<MortonKey, VertexHandle>,
+    pub(crate) octree: OctreeScratch<Signed>,
+    pub(crate) mesh: MeshTopology,
+    pub(crate) features: HashSet<VertexHandle>,
+    pub(crate) flips: Vec<Edge>,
+}
+
+impl Workspace {
+    /// Create an empty workspace. No memory is allocated until the workspace
+    /// is first used.
+    pub fn new() -> Self {
+        Self {
+            edges: EdgeCache::new(0),
+            streaming_edges: EdgeCache::new(0),
+            morton_indices: IndexCache::new(),
+            octree: OctreeScratch::new(),
+            mesh: MeshTopology::new(),
+            features: HashSet::new(),
+            flips: vec![],
+        }
+    }
+}
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        distance::Directed, extractor::IndexedVertices, feature::MinimiseQEF, implicit::Torus,
+        sampler::Sampler, DualContouring, ExtendedMarchingCubes, LinearHashedMarchingCubes,
+        MarchingCubes,
+    };
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    // Counts allocations on each thread separately, so that tests running in
+    // parallel don't interfere
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count() {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    // Extract twice into the same buffers, and count the allocations made by
+    // the second extraction
+    fn allocations<F>(mut extract: F) -> usize
+    where
+        F: FnMut(&mut IndexedVertices),
+    {
+        let (mut vertices, mut indices) = (vec![], vec![]);
+        extract(&mut IndexedVertices::new(&mut vertices, &mut indices));
+        assert!(!indices.is_empty());
+
+        vertices.clear();
+        indices.clear();
+        let before = ALLOCATIONS.with(|a| a.get());
+        extract(&mut IndexedVertices::new(&mut vertices, &mut indices));
+        ALLOCATIONS.with(|a| a.get()) - before
+    }
+
+    #[test]
+    fn test_reextraction_does_not_allocate() {
+        let torus = Torus::new(0.25, 0.1);
+        let sampler = Sampler::new(&torus);
+
+        // One workspace is shared by every extractor
+        let mut workspace = Workspace::new();
+        let w = &mut workspace;
+
+        let mut marching_cubes = MarchingCubes::<Signed>::new(32).unwrap();
+        let allocated = allocations(|e| marching_cubes.extract_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+        let allocated =
+            allocations(|e| marching_cubes.extract_bounded_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+        let allocated = allocations(|e| {
+            marching_cubes
+                .extract_streaming_with(w, &sampler, e)
+                .unwrap()
+        });
+        assert_eq!(allocated, 0);
+
+        let mut extended = ExtendedMarchingCubes::new(32).unwrap();
+        let allocated = allocations(|e| extended.extract_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+
+        let mut dual_contouring = DualContouring::new(32, MinimiseQEF {}).unwrap();
+        let allocated = allocations(|e| dual_contouring.extract_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+
+        let mut octree = LinearHashedMarchingCubes::new(5).unwrap();
+        let allocated = allocations(|e| octree.extract_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+        let allocated = allocations(|e| octree.extract_bounded_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+
+        // Chunks of different sizes can share the workspace too
+        let mut small = MarchingCubes::<Signed>::new(16).unwrap();
+        let allocated = allocations(|e| small.extract_with(w, &sampler, e).unwrap());
+        assert_eq!(allocated, 0);
+
+        // Sanity check that allocations are counted at all
+        let allocated = allocations(|e| {
+            let mut fresh = MarchingCubes::<Directed>::new(8).unwrap();
+            fresh.extract(&sampler, e).unwrap();
+        });
+        assert!(allocated > 0);
+    }
+}